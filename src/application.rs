@@ -1,27 +1,110 @@
-use std::path::Path;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
 
-use crate::portainer::api::{Authentication, Client, Session};
+use crate::portainer::api::{
+    Authentication, Client, Endpoint, FileContent, Plan, PlanGraph, Session,
+};
 use crate::portainer::client::DefaultClientFactory;
-use crate::portainer::commands::{CLICommand, LoginCredential, ServerConfig};
+use crate::portainer::commands::{
+    CLICommand, DestroySelection, FileMapping, GlobalOptions, LoginCredential, ManifestServers,
+    RegistryCredential, ServerConfig, DEFAULT_SESSION,
+};
+use crate::portainer::decrypt::Keyring;
+use crate::portainer::manifest::{Manifest, ManifestStack};
+use crate::portainer::output::Printer;
+use crate::portainer::project::PROJECT_FILE;
+use crate::portainer::prompt;
+use crate::portainer::reconcile::{self, SyncStatus};
+use crate::portainer::secrets::SecretProviders;
+use crate::portainer::session::encrypted::{self, EncryptedSessionManager, Seal};
+use crate::portainer::session::env::EnvSessionManager;
+use crate::portainer::session::helper::HelperSessionManager;
 use crate::portainer::session::{LocalSessionManager, SessionManager};
 
+const SESSION_FILE: &str = ".portainer.json";
+/// Used instead of the plaintext sessions file once `pctl session encrypt` created it
+const ENCRYPTED_SESSION_FILE: &str = ".portainer.enc.json";
+
 pub struct Application {
-    session: Box<dyn SessionManager>,
+    session: EnvSessionManager,
+    printer: Printer,
+    keys: Rc<Keyring>,
+    secrets: SecretProviders,
+}
+
+fn readpassword() -> Result<String, String> {
+    prompt::password("Type a password: ")
 }
 
-fn readpassword() -> String {
-    use std::io::Write;
-    print!("Type a password: ");
-    std::io::stdout().flush().unwrap();
-    rpassword::read_password().expect("Password is required for loging in!")
+fn prompt_registry_password(credential: RegistryCredential) -> Result<RegistryCredential, String> {
+    Ok(RegistryCredential {
+        password: Some(credential.password.map_or_else(readpassword, Ok)?),
+        ..credential
+    })
 }
 
 impl Application {
-    pub fn new() -> Application {
-        let p = Box::from(Path::new(".portainer.json"));
-        let lsm = LocalSessionManager::new(p).expect("Invalid session file");
-        let session = Box::new(lsm);
-        Application { session }
+    pub fn new(options: GlobalOptions) -> Application {
+        let keys = Rc::new(Keyring::new(options.age_identity));
+        let store: Box<dyn SessionManager> = if let Some(name) = &options.credential_helper {
+            Box::new(HelperSessionManager::new(name))
+        } else if Path::new(ENCRYPTED_SESSION_FILE).exists() {
+            Box::new(Application::encrypted_sessions(keys.clone()))
+        } else {
+            let p = Box::from(Path::new(SESSION_FILE));
+            let lsm = LocalSessionManager::new(p).expect("Invalid session file");
+            Box::new(lsm)
+        };
+        // sessions from the environment take precedence over stored ones
+        let session = EnvSessionManager::new(store);
+        let printer = Printer::new(options.output);
+        let secrets = SecretProviders::new(keys.clone());
+        Application {
+            session,
+            printer,
+            keys,
+            secrets,
+        }
+    }
+
+    fn encrypted_sessions(keys: Rc<Keyring>) -> EncryptedSessionManager {
+        EncryptedSessionManager::new(Box::from(Path::new(ENCRYPTED_SESSION_FILE)), keys)
+    }
+
+    /// Resolves configs and secrets from files, decrypting encrypted ones in memory, or
+    /// from secret providers
+    fn contents(&self, files: &[FileMapping]) -> Result<Vec<FileContent>, String> {
+        files
+            .iter()
+            .map(|FileMapping(name, source)| {
+                let source = source.to_string_lossy().to_string();
+                let content = self
+                    .secrets
+                    .resolve(&source)
+                    .map_err(|err| format!("Cannot resolve '{}': {}", name, err))?;
+                Ok(FileContent {
+                    name: name.to_string(),
+                    source,
+                    content,
+                    labels: HashMap::new(),
+                })
+            })
+            .collect()
+    }
+
+    fn deploy_stack(&self, endpoint: Endpoint, stack: &ManifestStack) -> Result<Plan, String> {
+        endpoint.deploy(
+            stack.compose.clone(),
+            stack.name.clone(),
+            stack.inline_vars.clone(),
+            self.contents(&stack.configs)?,
+            self.contents(&stack.secrets)?,
+            stack.registries.clone(),
+        )
     }
 
     fn load_session(&self, config: ServerConfig, cl: Client) -> Result<Session, String> {
@@ -31,17 +114,203 @@ impl Application {
                 username,
                 password,
             } => {
-                let password = password.unwrap_or(readpassword());
+                let password = password.map_or_else(readpassword, Ok)?;
                 cl.authenticate(Authentication::Login { username, password }, &address)
             }
             ServerConfig::InlineToken { address, token } => {
                 cl.authenticate(Authentication::APIToken(token), &address)
             }
 
+            ServerConfig::Session(name) if name == DEFAULT_SESSION => {
+                let data = self
+                    .session
+                    .get(&name)
+                    .map_err(|err| {
+                        format!(
+                            "{}, pass --session or --address, set PCTL_SESSION or PORTAINER_URL, or set a server in {}",
+                            err, PROJECT_FILE
+                        )
+                    })?;
+                let (auth, url) = data.to_tuple();
+                Ok(cl.authenticate(auth, &url)?.protected(data.protection()))
+            }
             ServerConfig::Session(name) => {
-                let (auth, url) = self.session.get(&name)?.to_tuple();
-                cl.authenticate(auth, &url)
+                let data = self.session.get(&name)?;
+                let (auth, url) = data.to_tuple();
+                Ok(cl.authenticate(auth, &url)?.protected(data.protection()))
+            }
+        }
+    }
+
+    /// Plans every manifest stack on each endpoint it selects, sessions are loaded once.
+    /// Server flags override the sessions of the manifest, while the environment and
+    /// project defaults only serve stacks without a session.
+    fn manifest_plans<F>(
+        &self,
+        servers: &ManifestServers,
+        path: &Path,
+        reverse: bool,
+        plan: F,
+    ) -> Result<PlanGraph, String>
+    where
+        F: Fn(Endpoint, &ManifestStack) -> Result<Plan, String>,
+    {
+        let client = || Client::new(Box::new(DefaultClientFactory));
+        let manifest = Manifest::load(path)?;
+        let mut stacks = manifest.stacks(&self.keys)?;
+        if reverse {
+            stacks.reverse();
+        }
+
+        let protection = manifest.protection();
+        let fixed = match &servers.fixed {
+            Some(config) => Some(
+                self.load_session(config.clone(), client())?
+                    .protected(protection),
+            ),
+            None => None,
+        };
+        let mut fallback = None;
+        let mut sessions: HashMap<String, Session> = HashMap::new();
+        let mut plans = Vec::new();
+        for stack in stacks {
+            let session = match (&fixed, &stack.session, &servers.fallback) {
+                (Some(session), _, _) => session,
+                (None, Some(name), _) => {
+                    if !sessions.contains_key(name) {
+                        let session = self
+                            .load_session(ServerConfig::Session(name.to_string()), client())?
+                            .protected(protection);
+                        sessions.insert(name.to_string(), session);
+                    }
+                    &sessions[name]
+                }
+                (None, None, Some(config)) => {
+                    if fallback.is_none() {
+                        let session = self.load_session(config.clone(), client())?;
+                        fallback = Some(session.protected(protection));
+                    }
+                    fallback.as_ref().unwrap()
+                }
+                (None, None, None) => {
+                    return Err(format!(
+                        "Stack '{}' has no session, set one in the manifest or pass server options",
+                        stack.name
+                    ))
+                }
+            };
+
+            let endpoints = session.endpoints_matching(&stack.endpoint)?;
+            if endpoints.is_empty() {
+                return Err(format!(
+                    "No endpoint matched the selector of stack '{}'",
+                    stack.name
+                ));
+            }
+            for endpoint in endpoints {
+                plans.push(plan(endpoint, &stack)?);
+            }
+        }
+        Ok(PlanGraph(plans))
+    }
+
+    /// Prints differences of deployed stacks, drift is reported as an error so the
+    /// process exits with a failure
+    fn report_drift(&self, plans: PlanGraph) -> Result<(), String> {
+        let diffs = plans.diff()?;
+        self.printer.print(&diffs)?;
+        let drifted = diffs.0.iter().filter(|diff| !diff.is_empty()).count();
+        if drifted > 0 {
+            Err(format!("Drift detected in {} stack(s)", drifted))
+        } else {
+            Ok(())
+        }
+    }
+
+    fn sync_repo(
+        &self,
+        servers: &ManifestServers,
+        repo_dir: &Path,
+        manifest: &Path,
+        pull: bool,
+        allow_destructive: bool,
+    ) -> Result<Vec<reconcile::StackStatus>, String> {
+        if pull {
+            reconcile::pull(repo_dir)?;
+        }
+        let plans = self.manifest_plans(
+            servers,
+            &repo_dir.join(manifest),
+            false,
+            |endpoint, stack| self.deploy_stack(endpoint, stack),
+        )?;
+        reconcile::sync(plans, allow_destructive)
+    }
+
+    /// Syncs the manifest of a repository periodically, the last sync is exposed through
+    /// the status server when listening
+    #[allow(clippy::too_many_arguments)]
+    fn reconcile(
+        &self,
+        servers: ManifestServers,
+        repo_dir: PathBuf,
+        manifest: PathBuf,
+        interval: Duration,
+        pull: bool,
+        allow_destructive: bool,
+        listen: Option<String>,
+        once: bool,
+    ) -> Result<(), String> {
+        // sessions are loaded on every sync, so a password is only asked once
+        let with_password = |server: Option<ServerConfig>| -> Result<_, String> {
+            Ok(match server {
+                Some(ServerConfig::InlineLogin {
+                    address,
+                    username,
+                    password: None,
+                }) => Some(ServerConfig::InlineLogin {
+                    address,
+                    username,
+                    password: Some(readpassword()?),
+                }),
+                server => server,
+            })
+        };
+        let servers = ManifestServers {
+            fixed: with_password(servers.fixed)?,
+            fallback: with_password(servers.fallback)?,
+        };
+        let status = Arc::new(Mutex::new(SyncStatus::default()));
+        if let Some(address) = listen {
+            reconcile::serve(&address, status.clone())?;
+        }
+
+        loop {
+            let started = Instant::now();
+            let result = self.sync_repo(&servers, &repo_dir, &manifest, pull, allow_destructive);
+            let mut status = status.lock().unwrap_or_else(|err| err.into_inner());
+            status.record(reconcile::revision(&repo_dir), started, result);
+            for stack in status.stacks.iter().filter(|s| s.state != "unchanged") {
+                eprintln!(
+                    "{} stack '{}' on endpoint {}{}",
+                    stack.state,
+                    stack.stack,
+                    stack.endpoint,
+                    if stack.detail.is_empty() {
+                        String::new()
+                    } else {
+                        format!(": {}", stack.detail)
+                    }
+                );
+            }
+            if once {
+                return status.error.clone().map_or(Ok(()), Err);
             }
+            if let Some(err) = &status.error {
+                eprintln!("Sync failed: {}", err);
+            }
+            drop(status);
+            thread::sleep(interval);
         }
     }
 
@@ -56,8 +325,8 @@ impl Application {
             } => {
                 let auth = match credential {
                     LoginCredential::ByUserPass { username, password } => Authentication::Login {
-                        username: username.to_string(),
-                        password: password.to_string(),
+                        username,
+                        password: password.map_or_else(readpassword, Ok)?,
                     },
                     LoginCredential::ByAPIToken(value) => {
                         Authentication::APIToken(value.to_string())
@@ -66,7 +335,7 @@ impl Application {
 
                 client
                     .authenticate(auth, &address)?
-                    .save(self.session.as_ref(), &server)
+                    .save(&self.session, &server)
             }
             CLICommand::Deploy {
                 server,
@@ -77,26 +346,466 @@ impl Application {
                 inline_vars,
                 configs,
                 secrets,
+                registries,
+                create_externals,
             } => self
                 .load_session(server, client)?
                 .endpoint(endpoint)?
-                .deploy(compose, stack, inline_vars, configs, secrets)?
-                .prompt(confirmed),
+                .deploy(
+                    compose,
+                    stack,
+                    inline_vars,
+                    self.contents(&configs)?,
+                    self.contents(&secrets)?,
+                    registries,
+                )?
+                .create_externals(create_externals)
+                .prompt(confirmed, &self.printer),
+
+            CLICommand::Apply {
+                server,
+                manifest,
+                confirmed,
+                create_externals,
+            } => self
+                .manifest_plans(&server, &manifest, false, |endpoint, stack| {
+                    self.deploy_stack(endpoint, stack)
+                        .map(|plan| plan.create_externals(create_externals))
+                })?
+                .prompt(confirmed, &self.printer),
+
+            CLICommand::Diff { server, manifest } => {
+                let plans = self.manifest_plans(&server, &manifest, false, |endpoint, stack| {
+                    self.deploy_stack(endpoint, stack)
+                })?;
+                self.printer.print(&plans.view())?;
+                self.printer.print(&plans.diff()?)
+            }
+
+            CLICommand::Drift {
+                server,
+                compose,
+                stack,
+                endpoint,
+                inline_vars,
+                configs,
+                secrets,
+            } => {
+                let plan = self
+                    .load_session(server, client)?
+                    .endpoint(endpoint)?
+                    .deploy(
+                        compose,
+                        stack,
+                        inline_vars,
+                        self.contents(&configs)?,
+                        self.contents(&secrets)?,
+                        Vec::new(),
+                    )?;
+                self.report_drift(PlanGraph(vec![plan]))
+            }
+
+            CLICommand::DriftManifest { server, manifest } => {
+                let plans = self.manifest_plans(&server, &manifest, false, |endpoint, stack| {
+                    self.deploy_stack(endpoint, stack)
+                })?;
+                self.report_drift(plans)
+            }
+
+            CLICommand::Reconcile {
+                server,
+                repo_dir,
+                manifest,
+                interval,
+                pull,
+                allow_destructive,
+                listen,
+                once,
+            } => self.reconcile(
+                server,
+                repo_dir,
+                manifest,
+                interval,
+                pull,
+                allow_destructive,
+                listen,
+                once,
+            ),
+
+            CLICommand::DestroyManifest {
+                server,
+                manifest,
+                confirmed,
+            } => self
+                .manifest_plans(&server, &manifest, true, |endpoint, stack| {
+                    let names = |files: &Vec<FileMapping>| -> Vec<String> {
+                        files.iter().map(|f| f.0.to_string()).collect()
+                    };
+                    endpoint.destroy(DestroySelection::exact(
+                        std::slice::from_ref(&stack.name),
+                        &names(&stack.configs),
+                        &names(&stack.secrets),
+                    ))
+                })?
+                .prompt(confirmed, &self.printer),
 
             CLICommand::Destroy {
                 server,
-                stacks,
                 endpoint,
                 confirmed,
+                selection,
+            } => self
+                .load_session(server, client)?
+                .endpoint(endpoint)?
+                .destroy(selection)?
+                .prompt(confirmed, &self.printer),
+
+            CLICommand::PreviewUp {
+                server,
+                compose,
+                stack,
+                endpoint,
+                confirmed,
+                inline_vars,
                 configs,
                 secrets,
+                registries,
+                labels,
+                create_externals,
+            } => {
+                // files of the preview are labeled too so `preview gc` finds them
+                let labeled = |files: Vec<FileContent>| -> Vec<FileContent> {
+                    files
+                        .into_iter()
+                        .map(|file| FileContent {
+                            labels: labels.iter().cloned().collect(),
+                            ..file
+                        })
+                        .collect()
+                };
+                self.load_session(server, client)?
+                    .endpoint(endpoint)?
+                    .deploy(
+                        compose,
+                        stack,
+                        inline_vars,
+                        labeled(self.contents(&configs)?),
+                        labeled(self.contents(&secrets)?),
+                        registries,
+                    )?
+                    .create_externals(create_externals)
+                    .prompt(confirmed, &self.printer)
+            }
+
+            CLICommand::PreviewGc {
+                server,
+                endpoint,
+                confirmed,
+                open,
+            } => self
+                .load_session(server, client)?
+                .endpoint(endpoint)?
+                .preview_gc(open)?
+                .prompt(confirmed, &self.printer),
+
+            CLICommand::Start {
+                server,
+                stacks,
+                endpoint,
+                confirmed,
+                wait,
+            } => self
+                .load_session(server, client)?
+                .endpoint(endpoint)?
+                .start(stacks, wait)?
+                .prompt(confirmed, &self.printer),
+
+            CLICommand::Stop {
+                server,
+                stacks,
+                endpoint,
+                confirmed,
+                wait,
+            } => self
+                .load_session(server, client)?
+                .endpoint(endpoint)?
+                .stop(stacks, wait)?
+                .prompt(confirmed, &self.printer),
+
+            CLICommand::ListStacks { server, endpoint } => {
+                let stacks = self
+                    .load_session(server, client)?
+                    .endpoint(endpoint)?
+                    .stacks()?;
+                self.printer.print(&stacks)
+            }
+
+            CLICommand::ListEndpoints { server, endpoint } => {
+                let endpoints = self
+                    .load_session(server, client)?
+                    .endpoints(endpoint.as_ref())?;
+                self.printer.print(&endpoints)
+            }
+
+            CLICommand::InspectEndpoint { server, endpoint } => {
+                let view = self
+                    .load_session(server, client)?
+                    .endpoint(endpoint)?
+                    .inspect()?;
+                self.printer.print(&view)
+            }
+
+            CLICommand::CreateEndpoint {
+                server,
+                name,
+                kind,
+                settings,
+            } => {
+                let view = self
+                    .load_session(server, client)?
+                    .create_endpoint(name, kind, settings)?;
+                self.printer.print(&view)
+            }
+
+            CLICommand::UpdateEndpoint {
+                server,
+                endpoint,
+                name,
+                settings,
+            } => {
+                let view = self
+                    .load_session(server, client)?
+                    .endpoint(endpoint)?
+                    .update(name, settings)?;
+                self.printer.print(&view)
+            }
+
+            CLICommand::RemoveEndpoint {
+                server,
+                endpoint,
+                confirmed,
             } => self
                 .load_session(server, client)?
                 .endpoint(endpoint)?
-                .destroy(stacks, configs, secrets)?
-                .prompt(confirmed),
+                .remove()?
+                .prompt(confirmed, &self.printer),
+
+            CLICommand::TagEndpoint {
+                server,
+                endpoint,
+                tags,
+                add,
+            } => {
+                let view = self
+                    .load_session(server, client)?
+                    .endpoint(endpoint)?
+                    .tag(tags, add)?;
+                self.printer.print(&view)
+            }
+
+            CLICommand::ListVolumes { server, endpoint } => {
+                let volumes = self
+                    .load_session(server, client)?
+                    .endpoint(endpoint)?
+                    .volumes()?;
+                self.printer.print(&volumes)
+            }
+
+            CLICommand::InspectVolume {
+                server,
+                endpoint,
+                name,
+            } => {
+                let volume = self
+                    .load_session(server, client)?
+                    .endpoint(endpoint)?
+                    .volume(&name)?;
+                self.printer.print(&volume)
+            }
+
+            CLICommand::CreateVolume {
+                server,
+                endpoint,
+                volume,
+            } => {
+                let volume = self
+                    .load_session(server, client)?
+                    .endpoint(endpoint)?
+                    .create_volume(volume)?;
+                self.printer.print(&volume)
+            }
+
+            CLICommand::ListNetworks { server, endpoint } => {
+                let networks = self
+                    .load_session(server, client)?
+                    .endpoint(endpoint)?
+                    .networks()?;
+                self.printer.print(&networks)
+            }
+
+            CLICommand::CreateNetwork {
+                server,
+                endpoint,
+                network,
+            } => {
+                let network = self
+                    .load_session(server, client)?
+                    .endpoint(endpoint)?
+                    .create_network(network)?;
+                self.printer.print(&vec![network])
+            }
+
+            CLICommand::ListTags { server } => {
+                let tags = self.load_session(server, client)?.tags()?;
+                self.printer.print(&tags)
+            }
+
+            CLICommand::CreateTags { server, names } => {
+                let tags = self.load_session(server, client)?.create_tags(names)?;
+                self.printer.print(&tags)
+            }
+
+            CLICommand::RemoveTags { server, names } => {
+                self.load_session(server, client)?.remove_tags(names)
+            }
+
+            CLICommand::ListRegistries { server } => {
+                let registries = self.load_session(server, client)?.registries()?;
+                self.printer.print(&registries)
+            }
+
+            CLICommand::AddRegistry {
+                server,
+                name,
+                kind,
+                url,
+                credential,
+            } => {
+                let registry = self.load_session(server, client)?.add_registry(
+                    name,
+                    kind,
+                    url,
+                    credential.map(prompt_registry_password).transpose()?,
+                )?;
+                self.printer.print(&vec![registry])
+            }
+
+            CLICommand::UpdateRegistry {
+                server,
+                name,
+                rename,
+                url,
+                credential,
+            } => {
+                let registry = self.load_session(server, client)?.update_registry(
+                    &name,
+                    rename,
+                    url,
+                    credential.map(prompt_registry_password).transpose()?,
+                )?;
+                self.printer.print(&vec![registry])
+            }
+
+            CLICommand::RemoveRegistries { server, names } => {
+                self.load_session(server, client)?.remove_registries(names)
+            }
+
+            CLICommand::ListGroups { server } => {
+                let groups = self.load_session(server, client)?.groups()?;
+                self.printer.print(&groups)
+            }
+
+            CLICommand::CreateGroup {
+                server,
+                name,
+                description,
+                tags,
+            } => {
+                let group =
+                    self.load_session(server, client)?
+                        .create_group(name, description, tags)?;
+                self.printer.print(&vec![group])
+            }
+
+            CLICommand::RemoveGroups { server, names } => {
+                self.load_session(server, client)?.remove_groups(names)
+            }
+
+            CLICommand::AddToGroup {
+                server,
+                group,
+                endpoint,
+            } => {
+                let endpoints = self
+                    .load_session(server, client)?
+                    .add_to_group(&group, &endpoint)?;
+                self.printer.print(&endpoints)
+            }
+
+            CLICommand::Logs {
+                server,
+                stack,
+                service,
+                endpoint,
+                options,
+            } => self
+                .load_session(server, client)?
+                .endpoint(endpoint)?
+                .logs(&stack, service, options),
+
+            CLICommand::Exec {
+                server,
+                stack,
+                service,
+                endpoint,
+                command,
+                tty,
+            } => self
+                .load_session(server, client)?
+                .endpoint(endpoint)?
+                .exec(&stack, &service, command, tty),
+
+            CLICommand::Api { server, call } => {
+                self.load_session(server, client)?.call(call, &self.printer)
+            }
 
             CLICommand::Logout(name) => self.session.remove(&name),
+
+            CLICommand::EncryptSessions { recipients } => {
+                let seal = if recipients.is_empty() {
+                    Seal::Passphrase
+                } else {
+                    Seal::Age(recipients)
+                };
+                Application::encrypted_sessions(self.keys.clone())
+                    .encrypt(Path::new(SESSION_FILE), seal)
+            }
+            CLICommand::DecryptSessions => {
+                Application::encrypted_sessions(self.keys.clone()).decrypt(Path::new(SESSION_FILE))
+            }
+            CLICommand::UnlockSessions => {
+                let key = Application::encrypted_sessions(self.keys.clone()).unlock()?;
+                println!("export {}={}", encrypted::KEY_VAR, key);
+                Ok(())
+            }
+            CLICommand::ProtectSession { name, protection } => {
+                let store = self.session.store();
+                let data = store.get(&name).map_err(|err| {
+                    if self.session.in_env(&name) {
+                        format!(
+                            "Session '{}' is only set by the environment, log in to save it before protecting it",
+                            name
+                        )
+                    } else {
+                        err
+                    }
+                })?;
+                store.save(&name, &data.with_protection(protection))
+            }
+            CLICommand::LockSessions => {
+                println!("unset {}", encrypted::KEY_VAR);
+                Ok(())
+            }
         }
     }
 }
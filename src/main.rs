@@ -3,16 +3,25 @@ mod portainer;
 
 use portainer::commands;
 
-fn main() {
+use std::process::ExitCode;
+
+/// Exits with 2 on invalid arguments, like clap does, and 1 when the command fails
+fn main() -> ExitCode {
     let cmd = commands::parse_command();
 
     match cmd {
-        Err(details) => eprintln!("Invalid arguments!\n{0}", details),
-        Ok(cmd) => {
-            let app = application::Application::new();
+        Err(details) => {
+            eprintln!("Invalid arguments!\n{0}", details);
+            ExitCode::from(2)
+        }
+        Ok((options, cmd)) => {
+            let app = application::Application::new(options);
             match app.handle(cmd) {
-                Ok(()) => (),
-                Err(details) => eprintln!("Failed to handle requested command!\n{0}", details),
+                Ok(()) => ExitCode::SUCCESS,
+                Err(details) => {
+                    eprintln!("Failed to handle requested command!\n{0}", details);
+                    ExitCode::FAILURE
+                }
             }
         }
     }
@@ -1,7 +1,22 @@
 pub mod api;
 pub mod client;
 pub mod commands;
+pub mod compose;
+pub mod decrypt;
+pub mod diff;
+pub mod env;
+pub mod exec;
+pub mod logs;
+pub mod manifest;
+pub mod output;
+pub mod preview;
+pub mod project;
+pub mod prompt;
+pub mod protect;
+pub mod query;
+pub mod reconcile;
 pub mod requests;
+pub mod secrets;
 pub mod session;
 
 type Res<T> = Result<T, String>;
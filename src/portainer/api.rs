@@ -1,9 +1,32 @@
-use std::fs;
-use std::path::Path;
+use std::collections::{BTreeMap, HashMap};
+use std::rc::Rc;
+use std::thread;
+use std::time::{Duration, Instant};
 
-use super::client::{ClientFactory, Credential, PortainerClient};
-use super::commands::{EndpointSelector, FileMapping, InlineEnv};
-use super::requests::{self, raw_requests, Config, Secret, Stack};
+use serde_json::Value as Json;
+use sha2::{Digest, Sha256};
+
+use super::client::{ClientFactory, Credential, PortainerClient, PortainerRequestRaw};
+use super::commands::{
+    ApiCall, Confirmation, DestroySelection, EndpointKind, EndpointSelector, EndpointSettings,
+    InlineEnv, Label, LogOptions, NetworkSpec, RegistryCredential, RegistryKind, StackSelector,
+    VolumeSpec,
+};
+use super::compose::ComposeFile;
+use super::diff;
+use super::exec::{self, Exec};
+use super::logs::{LogPrinter, LogReader};
+use super::output::{
+    DiffView, EndpointView, GroupView, NetworkView, PlanGraphView, PlanStep, PlanView, Printer,
+    RegistryView, StackDiff, StackView, TagView, VolumeView,
+};
+use super::preview::{self, Preview};
+use super::prompt;
+use super::protect::{Protected, Protection};
+use super::requests::{
+    self, raw_requests, Config, EcrRegistryData, GitlabRegistryData, Network, Registry, Secret,
+    Service, Stack, Task, Volume,
+};
 use super::session::{SessionData, SessionManager};
 use super::{Action, Res};
 
@@ -29,9 +52,10 @@ impl Client {
         }
 
         Ok(Session {
-            client,
+            client: Rc::from(client),
             credential,
             url: url.to_string(),
+            protection: Rc::new(Protection::default()),
         })
     }
 
@@ -49,52 +73,440 @@ pub enum Authentication {
 }
 
 pub struct Session {
-    client: Box<dyn PortainerClient>,
+    client: Rc<dyn PortainerClient>,
     credential: Credential,
     url: String,
+    protection: Rc<Protection>,
+}
+
+fn tag_names(client: &dyn PortainerClient) -> Res<HashMap<i32, String>> {
+    Ok(raw_requests::list_tags()
+        .send(client)?
+        .into_iter()
+        .map(|t| (t.id, t.name))
+        .collect())
+}
+
+fn resolve_tags(client: &dyn PortainerClient, names: &[String]) -> Res<Vec<i32>> {
+    let tags = raw_requests::list_tags().send(client)?;
+    names
+        .iter()
+        .map(|name| {
+            tags.iter()
+                .find(|t| &t.name == name)
+                .map(|t| t.id)
+                .ok_or_else(|| format!("Tag '{}' does not exist", name))
+        })
+        .collect()
+}
+
+fn group_names(client: &dyn PortainerClient) -> Res<HashMap<i32, String>> {
+    Ok(raw_requests::list_endpoint_groups()
+        .send(client)?
+        .into_iter()
+        .map(|g| (g.id, g.name))
+        .collect())
+}
+
+fn resolve_group(client: &dyn PortainerClient, name: &str) -> Res<i32> {
+    raw_requests::list_endpoint_groups()
+        .send(client)?
+        .into_iter()
+        .find(|g| g.name == name)
+        .map(|g| g.id)
+        .ok_or_else(|| format!("Endpoint group '{}' does not exist", name))
+}
+
+fn endpoint_view(client: &dyn PortainerClient, endpoint: &requests::Endpoint) -> Res<EndpointView> {
+    Ok(EndpointView::new(
+        endpoint,
+        &tag_names(client)?,
+        &group_names(client)?,
+    ))
+}
+
+fn find_registry(client: &dyn PortainerClient, name: &str) -> Res<Registry> {
+    raw_requests::list_registries()
+        .send(client)?
+        .into_iter()
+        .find(|r| r.name == name)
+        .ok_or_else(|| format!("Registry '{}' does not exist", name))
+}
+
+fn find_registry_by_id(client: &dyn PortainerClient, id: i32) -> Res<Registry> {
+    raw_requests::list_registries()
+        .send(client)?
+        .into_iter()
+        .find(|r| r.id == id)
+        .ok_or_else(|| format!("Registry {} does not exist", id))
 }
 
 fn assert_selected(all: Vec<requests::Endpoint>) -> Res<i32> {
-    if all.len() == 1 {
-        Ok(all.get(0).unwrap().id())
-    } else {
-        Err(format!(
-            "Must select exactly one endpoint, but selected {}",
-            all.len()
-        ))
+    match all.as_slice() {
+        [endpoint] => Ok(endpoint.id()),
+        [] => Err("No endpoint matched the selector".to_string()),
+        candidates => {
+            let names: Vec<String> = candidates
+                .iter()
+                .map(|e| format!("  {} (id: {})", e.name(), e.id()))
+                .collect();
+            Err(format!(
+                "Must select exactly one endpoint, but selected {}:\n{}",
+                candidates.len(),
+                names.join("\n")
+            ))
+        }
     }
 }
+
+/// Keeps endpoints having all of the tags, or any of them when `any` is set
+fn filter_tagged(
+    all: Vec<requests::Endpoint>,
+    tag_ids: &[i32],
+    any: bool,
+) -> Vec<requests::Endpoint> {
+    all.into_iter()
+        .filter(|e| {
+            let has = |id: &i32| e.tag_ids().contains(id);
+            if any {
+                tag_ids.iter().any(has)
+            } else {
+                tag_ids.iter().all(has)
+            }
+        })
+        .collect()
+}
+
 impl Session {
-    pub fn endpoint(self, selector: EndpointSelector) -> Res<Endpoint> {
+    /// Adds protection rules to plans of this session
+    pub fn protected(self, rules: &Protection) -> Session {
+        Session {
+            protection: Rc::new(self.protection.merge(rules)),
+            ..self
+        }
+    }
+
+    /// Lists all endpoints matched by the selector, name patterns and tags are matched
+    /// client-side over the full endpoint list
+    pub fn select_endpoints(&self, selector: &EndpointSelector) -> Res<Vec<requests::Endpoint>> {
         let client = self.client.as_ref();
+        let all = |name: Option<String>| raw_requests::list_endpoints(vec![], name).send(client);
+        let by_name = |matches: &dyn Fn(&str) -> bool| -> Res<Vec<requests::Endpoint>> {
+            Ok(all(None)?
+                .into_iter()
+                .filter(|e| matches(e.name()))
+                .collect())
+        };
 
-        let id = match selector {
-            EndpointSelector::ById(id) => id.to_owned(),
-            EndpointSelector::ByName(name) => assert_selected(
-                raw_requests::list_endpoints(vec![], Some(name.to_string())).send(client)?,
-            )?,
-            EndpointSelector::ByTagIds(tag_ids) => {
-                assert_selected(raw_requests::list_endpoints(tag_ids, None).send(client)?)?
+        match selector {
+            EndpointSelector::ById(id) => Ok(vec![raw_requests::get_endpoint(*id).send(client)?]),
+            EndpointSelector::ByName(name) => Ok(all(Some(name.to_string()))?
+                .into_iter()
+                .filter(|e| e.name() == name)
+                .collect()),
+            EndpointSelector::ByNameGlob(pattern) => by_name(&|name| pattern.matches(name)),
+            EndpointSelector::ByNameRegex(regex) => by_name(&|name| regex.is_match(name)),
+            EndpointSelector::ByTagIds { ids, any } => Ok(filter_tagged(all(None)?, ids, *any)),
+            EndpointSelector::ByTags { tags, any } => {
+                let ids = resolve_tags(client, tags)?;
+                Ok(filter_tagged(all(None)?, &ids, *any))
             }
-            EndpointSelector::ByTags(tags) => {
-                let tag_ids = raw_requests::list_tags()
-                    .send(client)?
-                    .iter()
-                    .filter(|t| tags.contains(&t.name))
-                    .map(|t| t.tagged_endpoints())
-                    .reduce(|a, b| a.intersection(&b).map(|x| *x).collect())
-                    .ok_or("Tags must select a unique endpoint")?
+            EndpointSelector::ByGroupId(id) => Ok(all(None)?
+                .into_iter()
+                .filter(|e| e.group_id == *id)
+                .collect()),
+            EndpointSelector::ByGroup(name) => {
+                let id = resolve_group(client, name)?;
+                Ok(all(None)?
                     .into_iter()
-                    .collect();
-                assert_selected(raw_requests::list_endpoints(tag_ids, None).send(client)?)?
+                    .filter(|e| e.group_id == id)
+                    .collect())
             }
+        }
+    }
+
+    /// Endpoints matched by the selector, sharing the client of this session
+    pub fn endpoints_matching(&self, selector: &EndpointSelector) -> Res<Vec<Endpoint>> {
+        Ok(self
+            .select_endpoints(selector)?
+            .iter()
+            .map(|endpoint| Endpoint {
+                client: self.client.clone(),
+                id: endpoint.id(),
+                protection: self.protection.clone(),
+            })
+            .collect())
+    }
+
+    pub fn endpoint(self, selector: EndpointSelector) -> Res<Endpoint> {
+        let id = match selector {
+            EndpointSelector::ById(id) => id,
+            selector => assert_selected(self.select_endpoints(&selector)?)?,
         };
 
         Ok(Endpoint {
             client: self.client,
             id,
+            protection: self.protection,
         })
     }
+
+    fn request(&self, call: &ApiCall, page: Option<(u32, u32)>) -> Res<Json> {
+        let body = call.body.clone();
+        let req = call.queries.iter().fold(
+            PortainerRequestRaw::new(call.method, &call.path, body),
+            |req, InlineEnv(key, value)| req.with_query(key, value),
+        );
+        let req = match page {
+            Some((start, limit)) => req
+                .with_query("start", &start.to_string())
+                .with_query("limit", &limit.to_string()),
+            None => req,
+        };
+        req.send(self.client.as_ref())
+    }
+
+    fn paginate(&self, call: &ApiCall, size: u32) -> Res<Json> {
+        let mut all = Vec::new();
+        let mut previous = None;
+        for _ in 0..MAX_PAGES {
+            let page = match self.request(call, Some((all.len() as u32, size)))? {
+                Json::Array(items) => items,
+                _ => return Err("Pagination requires a response of json array".to_string()),
+            };
+            // a server ignoring start and limit answers the same page again
+            if page.is_empty() || previous.as_ref() == Some(&page) {
+                return Ok(Json::Array(all));
+            }
+            let last = (page.len() as u32) < size;
+            all.extend(page.iter().cloned());
+            if last {
+                return Ok(Json::Array(all));
+            }
+            previous = Some(page);
+        }
+        Err(format!(
+            "Pagination of '{}' stopped after {} pages, the server may ignore start and limit",
+            call.path, MAX_PAGES
+        ))
+    }
+
+    pub fn call(&self, call: ApiCall, printer: &Printer) -> Action {
+        let response = match call.page_size {
+            Some(size) => self.paginate(&call, size)?,
+            None => self.request(&call, None)?,
+        };
+        let outputs = match &call.select {
+            Some(selector) => selector.select(response),
+            None => vec![response],
+        };
+
+        for output in outputs {
+            printer.print_document(&output, call.raw)?;
+        }
+        Ok(())
+    }
+
+    pub fn endpoints(&self, selector: Option<&EndpointSelector>) -> Res<Vec<EndpointView>> {
+        let client = self.client.as_ref();
+        let tags = tag_names(client)?;
+        let groups = group_names(client)?;
+        let endpoints = match selector {
+            Some(selector) => self.select_endpoints(selector)?,
+            None => raw_requests::list_endpoints(vec![], None).send(client)?,
+        };
+        Ok(endpoints
+            .iter()
+            .map(|e| EndpointView::new(e, &tags, &groups))
+            .collect())
+    }
+
+    pub fn create_endpoint(
+        &self,
+        name: String,
+        kind: EndpointKind,
+        settings: EndpointSettings,
+    ) -> Res<EndpointView> {
+        let client = self.client.as_ref();
+        let tag_ids = match &settings.tags {
+            Some(tags) => resolve_tags(client, tags)?,
+            None => Vec::new(),
+        };
+        // agents serve self-signed certificates
+        let (creation_type, tls, skip_verify) = match kind {
+            EndpointKind::Docker => (1, settings.tls_skip_verify.is_some(), false),
+            EndpointKind::Agent => (2, true, true),
+            EndpointKind::Edge => (4, false, false),
+            EndpointKind::Kubernetes => (5, false, false),
+        };
+        let endpoint = raw_requests::create_endpoint(
+            name,
+            creation_type,
+            settings.url,
+            settings.public_url,
+            settings.group,
+            tag_ids,
+            tls,
+            settings.tls_skip_verify.unwrap_or(skip_verify),
+        )
+        .send(client)?;
+
+        endpoint_view(client, &endpoint)
+    }
+
+    pub fn tags(&self) -> Res<Vec<TagView>> {
+        let tags = raw_requests::list_tags().send(self.client.as_ref())?;
+        Ok(tags.iter().map(TagView::from).collect())
+    }
+
+    /// Creates missing tags, existing ones are left untouched
+    pub fn create_tags(&self, names: Vec<String>) -> Res<Vec<TagView>> {
+        let client = self.client.as_ref();
+        let existing = raw_requests::list_tags().send(client)?;
+        names
+            .into_iter()
+            .map(|name| match existing.iter().find(|t| t.name == name) {
+                Some(tag) => Ok(TagView::from(tag)),
+                None => raw_requests::create_tag(name)
+                    .send(client)
+                    .map(|tag| TagView::from(&tag)),
+            })
+            .collect()
+    }
+
+    /// Removes tags, tags that don't exist are ignored
+    pub fn remove_tags(&self, names: Vec<String>) -> Action {
+        let client = self.client.as_ref();
+        let existing = raw_requests::list_tags().send(client)?;
+        for tag in existing.iter().filter(|t| names.contains(&t.name)) {
+            raw_requests::delete_tag(tag.id).send(client)?;
+        }
+        Ok(())
+    }
+
+    pub fn groups(&self) -> Res<Vec<GroupView>> {
+        let client = self.client.as_ref();
+        let tags = tag_names(client)?;
+        let endpoints = raw_requests::list_endpoints(vec![], None).send(client)?;
+        let groups = raw_requests::list_endpoint_groups().send(client)?;
+        Ok(groups
+            .iter()
+            .map(|g| GroupView::new(g, &tags, &endpoints))
+            .collect())
+    }
+
+    pub fn create_group(
+        &self,
+        name: String,
+        description: String,
+        tags: Vec<String>,
+    ) -> Res<GroupView> {
+        let client = self.client.as_ref();
+        let tag_ids = resolve_tags(client, &tags)?;
+        let group = raw_requests::create_endpoint_group(name, description, tag_ids).send(client)?;
+        Ok(GroupView::new(&group, &tag_names(client)?, &[]))
+    }
+
+    /// Removes endpoint groups, groups that don't exist are ignored
+    pub fn remove_groups(&self, names: Vec<String>) -> Action {
+        let client = self.client.as_ref();
+        let existing = raw_requests::list_endpoint_groups().send(client)?;
+        for group in existing.iter().filter(|g| names.contains(&g.name)) {
+            raw_requests::delete_endpoint_group(group.id).send(client)?;
+        }
+        Ok(())
+    }
+
+    /// Moves every endpoint matched by the selector into the group
+    pub fn add_to_group(&self, group: &str, selector: &EndpointSelector) -> Res<Vec<EndpointView>> {
+        let client = self.client.as_ref();
+        let group_id = resolve_group(client, group)?;
+        let selected = self.select_endpoints(selector)?;
+        if selected.is_empty() {
+            return Err("No endpoint matched the selector".to_string());
+        }
+        for endpoint in selected.iter().filter(|e| e.group_id != group_id) {
+            raw_requests::add_endpoint_to_group(group_id, endpoint.id()).send(client)?;
+        }
+        selected
+            .iter()
+            .map(|e| raw_requests::get_endpoint(e.id()).send(client))
+            .map(|e| endpoint_view(client, &e?))
+            .collect()
+    }
+
+    pub fn registries(&self) -> Res<Vec<RegistryView>> {
+        let registries = raw_requests::list_registries().send(self.client.as_ref())?;
+        Ok(registries.iter().map(RegistryView::from).collect())
+    }
+
+    /// Adds a registry, dockerhub and gitlab default to their public registry urls
+    pub fn add_registry(
+        &self,
+        name: String,
+        kind: RegistryKind,
+        url: Option<String>,
+        credential: Option<RegistryCredential>,
+    ) -> Res<RegistryView> {
+        let (kind, default_url, gitlab, ecr) = match kind {
+            RegistryKind::DockerHub => (6, "docker.io", None, None),
+            RegistryKind::Gitlab { instance_url } => (
+                4,
+                "registry.gitlab.com",
+                Some(GitlabRegistryData { instance_url }),
+                None,
+            ),
+            RegistryKind::Ecr { region } => (7, "", None, Some(EcrRegistryData { region })),
+            RegistryKind::Custom => (3, "", None, None),
+        };
+        let (username, password) = match credential {
+            Some(RegistryCredential { username, password }) => (Some(username), password),
+            None => (None, None),
+        };
+        let registry = raw_requests::create_registry(
+            name,
+            kind,
+            url.unwrap_or_else(|| default_url.to_string()),
+            username,
+            password,
+            gitlab,
+            ecr,
+        )
+        .send(self.client.as_ref())?;
+        Ok(RegistryView::from(&registry))
+    }
+
+    /// Updates a registry, authentication is kept unless new credentials are given
+    pub fn update_registry(
+        &self,
+        name: &str,
+        rename: Option<String>,
+        url: Option<String>,
+        credential: Option<RegistryCredential>,
+    ) -> Res<RegistryView> {
+        let client = self.client.as_ref();
+        let registry = find_registry(client, name)?;
+        let authentication = registry.authentication || credential.is_some();
+        let (username, password) = match credential {
+            Some(RegistryCredential { username, password }) => (Some(username), password),
+            None => (None, None),
+        };
+        raw_requests::update_registry(registry.id, rename, url, authentication, username, password)
+            .send(client)?;
+        let updated = find_registry_by_id(client, registry.id)?;
+        Ok(RegistryView::from(&updated))
+    }
+
+    /// Removes registries, registries that don't exist are ignored
+    pub fn remove_registries(&self, names: Vec<String>) -> Action {
+        let client = self.client.as_ref();
+        let existing = raw_requests::list_registries().send(client)?;
+        for registry in existing.iter().filter(|r| names.contains(&r.name)) {
+            raw_requests::delete_registry(registry.id).send(client)?;
+        }
+        Ok(())
+    }
+
     pub fn save(&self, session: &dyn SessionManager, name: &str) -> Action {
         let data = match &self.credential {
             Credential::APIToken(value) => Some(SessionData::api(&self.url, value)),
@@ -103,14 +515,40 @@ impl Session {
         };
 
         let d = data.ok_or("Saving username and password is not supported!".to_string())?;
+        // logging in again keeps protection rules of the session
+        let d = match session.get(name) {
+            Ok(existing) => d.with_protection(existing.protection().clone()),
+            Err(_) => d,
+        };
 
         session.save(name, &d)
     }
 }
 
+/// Stacks having services with all of the labels
+fn labeled_stacks(
+    client: &dyn PortainerClient,
+    stacks: Vec<Stack>,
+    labels: &[Label],
+) -> Res<Vec<Stack>> {
+    let mut selected = Vec::new();
+    for stack in stacks {
+        let file = raw_requests::get_stack_file(stack.id).send(client)?;
+        let compose = ComposeFile::parse(&file.content)?;
+        if labels
+            .iter()
+            .all(|Label(key, value)| compose.has_label(key, value))
+        {
+            selected.push(stack);
+        }
+    }
+    Ok(selected)
+}
+
 pub struct Endpoint {
-    client: Box<dyn PortainerClient>,
+    client: Rc<dyn PortainerClient>,
     id: i32,
+    protection: Rc<Protection>,
 }
 impl Endpoint {
     pub fn deploy(
@@ -118,24 +556,77 @@ impl Endpoint {
         compose: String,
         stack: String,
         inline_vars: Vec<InlineEnv>,
-        configs: Vec<FileMapping>,
-        secrets: Vec<FileMapping>,
+        configs: Vec<FileContent>,
+        secrets: Vec<FileContent>,
+        registries: Vec<String>,
     ) -> Res<Plan> {
         let client = self.client.as_ref();
+        let accessible = raw_requests::list_endpoint_registries(self.id).send(client)?;
+        let registries = registries
+            .iter()
+            .map(|name| {
+                find_registry(client, name).map(|registry| RegistryAccess {
+                    granted: accessible.iter().any(|r| r.id == registry.id),
+                    registry,
+                })
+            })
+            .collect::<Res<Vec<RegistryAccess>>>()?;
+
         let swarm_id = raw_requests::get_endpoint_info(self.id)
             .send(client)?
             .swarm
+            .cluster
             .id;
 
         let all_stacks =
             raw_requests::list_stacks(Some(self.id), Some(swarm_id.to_string())).send(client)?;
 
+        // configs and secrets are immutable in docker, unchanged ones are kept as they are
+        let names = |files: &[FileContent]| files.iter().map(|f| f.name.to_string()).collect();
+        let existing_configs = if configs.is_empty() {
+            Vec::new()
+        } else {
+            let live = raw_requests::list_configs(self.id, names(&configs)).send(client)?;
+            existing_files("Config", &configs, |file| config_unchanged(&live, file))?
+        };
+        let existing_secrets = if secrets.is_empty() {
+            Vec::new()
+        } else {
+            let live = raw_requests::list_secrets(self.id, names(&secrets)).send(client)?;
+            existing_files("Secret", &secrets, |file| Ok(secret_unchanged(&live, file)))?
+        };
+
+        // external volumes and networks are not created by docker when a stack is deployed
+        let file = ComposeFile::parse(&compose)?;
+        let (volumes, networks) = (file.external_volumes(), file.external_networks());
+        let missing_volumes = if volumes.is_empty() {
+            Vec::new()
+        } else {
+            let live = raw_requests::list_volumes(self.id).send(client)?.volumes();
+            volumes
+                .into_iter()
+                .filter(|name| !live.iter().any(|v| &v.name == name))
+                .collect()
+        };
+        let missing_networks = if networks.is_empty() {
+            Vec::new()
+        } else {
+            let live = raw_requests::list_networks(self.id).send(client)?;
+            networks
+                .into_iter()
+                .filter(|name| !live.iter().any(|n| &n.name == name))
+                .collect()
+        };
+
         let stack_plan = match all_stacks.iter().find(|s| s.name == stack) {
             None => StackPlan::Create {
                 name: stack,
                 swarm_id,
             },
-            Some(s) => StackPlan::Update(s.id),
+            Some(s) => StackPlan::Update {
+                name: s.name.to_string(),
+                id: s.id,
+            },
         };
         let definition = PlanDef::Deploy {
             stack_plan,
@@ -143,58 +634,599 @@ impl Endpoint {
             inline_vars,
             configs,
             secrets,
+            existing_configs,
+            existing_secrets,
+            registries,
+            missing_volumes,
+            missing_networks,
+            create_externals: false,
         };
 
         Ok(Plan {
             definition,
             endpoint: self.id,
             client: self.client,
+            protection: self.protection,
         })
     }
 
-    pub fn destroy(
-        self,
-        stacks: Vec<String>,
-        configs: Vec<String>,
-        secrets: Vec<String>,
-    ) -> Res<Plan> {
+    pub fn destroy(self, selection: DestroySelection) -> Res<Plan> {
         let client = self.client.as_ref();
+        let DestroySelection {
+            stacks: stack_names,
+            configs: config_names,
+            secrets: secret_names,
+            volumes: volume_names,
+            networks: network_names,
+            labels,
+            orphans,
+        } = selection;
+        let matches =
+            |patterns: &[glob::Pattern], name: &str| patterns.iter().any(|p| p.matches(name));
+        let labeled = |object: &HashMap<String, String>| {
+            !labels.is_empty()
+                && labels
+                    .iter()
+                    .all(|Label(key, value)| object.get(key) == Some(value))
+        };
 
         let all_stacks = raw_requests::list_stacks(Some(self.id), None).send(client)?;
+        let mut stacks: Vec<Stack> = Vec::new();
+        let labeled_stacks = if labels.is_empty() {
+            Vec::new()
+        } else {
+            labeled_stacks(client, all_stacks.clone(), &labels)?
+        };
+        for stack in all_stacks {
+            if matches(&stack_names, &stack.name) || labeled_stacks.iter().any(|s| s.id == stack.id)
+            {
+                stacks.push(stack);
+            }
+        }
 
-        let stacks = all_stacks
-            .iter()
-            .filter(|s| stacks.contains(&s.name))
-            .map(|s| s.clone())
-            .collect();
+        // docker lists are only fetched when something of their kind may be selected, orphans
+        // without patterns are looked for among all configs and secrets
+        let by_labels = !labels.is_empty();
+        let any_orphan =
+            orphans && config_names.is_empty() && secret_names.is_empty() && !by_labels;
+        let mut configs = Vec::new();
+        if !config_names.is_empty() || by_labels || orphans {
+            configs = raw_requests::list_configs(self.id, Vec::new())
+                .send(client)?
+                .into_iter()
+                .filter(|c| matches(&config_names, c.name()) || labeled(c.labels()) || any_orphan)
+                .collect();
+        }
+        let mut secrets = Vec::new();
+        if !secret_names.is_empty() || by_labels || orphans {
+            secrets = raw_requests::list_secrets(self.id, Vec::new())
+                .send(client)?
+                .into_iter()
+                .filter(|s| matches(&secret_names, s.name()) || labeled(s.labels()) || any_orphan)
+                .collect();
+        }
+        if orphans {
+            // services of removed stacks do not count as users
+            let services: Vec<Service> = raw_requests::list_services(self.id)
+                .send(client)?
+                .into_iter()
+                .filter(|service| {
+                    service
+                        .stack()
+                        .is_none_or(|stack| !stacks.iter().any(|s| &s.name == stack))
+                })
+                .collect();
+            configs.retain(|c| {
+                !services
+                    .iter()
+                    .any(|s| s.config_ids().any(|id| *id == c.id))
+            });
+            secrets.retain(|c| {
+                !services
+                    .iter()
+                    .any(|s| s.secret_ids().any(|id| *id == c.id))
+            });
+        }
 
-        let configs = raw_requests::list_configs(self.id, None, Some(configs)).send(client)?;
-        let secrets = raw_requests::list_secrets(self.id, None, Some(secrets)).send(client)?;
+        let volumes = if volume_names.is_empty() && !by_labels {
+            Vec::new()
+        } else {
+            raw_requests::list_volumes(self.id)
+                .send(client)?
+                .volumes()
+                .into_iter()
+                .filter(|v| matches(&volume_names, &v.name) || labeled(&v.labels()))
+                .collect()
+        };
+        let networks = if network_names.is_empty() && !by_labels {
+            Vec::new()
+        } else {
+            raw_requests::list_networks(self.id)
+                .send(client)?
+                .into_iter()
+                .filter(|n| !n.is_predefined())
+                .filter(|n| matches(&network_names, &n.name) || labeled(&n.labels()))
+                .collect()
+        };
 
         let definition = PlanDef::Destroy {
             stacks,
             configs,
             secrets,
+            volumes,
+            networks,
         };
 
         Ok(Plan {
             definition,
             endpoint: self.id,
             client: self.client,
+            protection: self.protection,
         })
     }
+
+    /// Destroys previews whose TTL expired or, when open ids are given, whose id is not
+    /// open, with their configs and secrets
+    pub fn preview_gc(self, open: Option<Vec<String>>) -> Res<Plan> {
+        let client = self.client.as_ref();
+        let now = preview::now();
+        let open = open.as_deref();
+
+        let mut live = Vec::new();
+        let mut collected = Vec::new();
+        let mut stacks = Vec::new();
+        for stack in raw_requests::list_stacks(Some(self.id), None).send(client)? {
+            let file = raw_requests::get_stack_file(stack.id).send(client)?;
+            let labels = ComposeFile::parse(&file.content)?.service_labels();
+            let Some(preview) = Preview::from_labels(labels.iter().map(|(k, v)| (k, v))) else {
+                continue;
+            };
+            if preview.is_garbage(open, now) {
+                collected.push(preview.id);
+                stacks.push(stack);
+            } else {
+                live.push(preview.id);
+            }
+        }
+
+        // files keep the expiry of the deploy creating them, those of live previews stay
+        let garbage = |labels: &HashMap<String, String>| match Preview::from_labels(labels) {
+            Some(preview) if collected.contains(&preview.id) => true,
+            Some(preview) => !live.contains(&preview.id) && preview.is_garbage(open, now),
+            None => false,
+        };
+        let configs = raw_requests::list_configs(self.id, Vec::new())
+            .send(client)?
+            .into_iter()
+            .filter(|c| garbage(c.labels()))
+            .collect();
+        let secrets = raw_requests::list_secrets(self.id, Vec::new())
+            .send(client)?
+            .into_iter()
+            .filter(|s| garbage(s.labels()))
+            .collect();
+
+        let definition = PlanDef::Destroy {
+            stacks,
+            configs,
+            secrets,
+            volumes: Vec::new(),
+            networks: Vec::new(),
+        };
+
+        Ok(Plan {
+            definition,
+            endpoint: self.id,
+            client: self.client,
+            protection: self.protection,
+        })
+    }
+
+    fn select_stacks(&self, selector: StackSelector) -> Res<Vec<Stack>> {
+        let client = self.client.as_ref();
+        let all_stacks = raw_requests::list_stacks(Some(self.id), None).send(client)?;
+
+        match selector {
+            StackSelector::ByNames(names) => names
+                .iter()
+                .map(|name| {
+                    all_stacks
+                        .iter()
+                        .find(|s| &s.name == name)
+                        .cloned()
+                        .ok_or_else(|| format!("Stack '{}' does not exist", name))
+                })
+                .collect(),
+            StackSelector::ByLabels(labels) => labeled_stacks(client, all_stacks, &labels),
+        }
+    }
+
+    pub fn volumes(&self) -> Res<Vec<VolumeView>> {
+        let volumes = raw_requests::list_volumes(self.id)
+            .send(self.client.as_ref())?
+            .volumes();
+        Ok(volumes.iter().map(VolumeView::from).collect())
+    }
+
+    pub fn volume(&self, name: &str) -> Res<VolumeView> {
+        let volume = raw_requests::get_volume(self.id, name).send(self.client.as_ref())?;
+        Ok(VolumeView::from(&volume))
+    }
+
+    pub fn create_volume(&self, spec: VolumeSpec) -> Res<VolumeView> {
+        let volume =
+            raw_requests::create_volume(self.id, spec.name, spec.driver, spec.options, spec.labels)
+                .send(self.client.as_ref())?;
+        Ok(VolumeView::from(&volume))
+    }
+
+    pub fn networks(&self) -> Res<Vec<NetworkView>> {
+        let networks = raw_requests::list_networks(self.id).send(self.client.as_ref())?;
+        Ok(networks.iter().map(NetworkView::from).collect())
+    }
+
+    pub fn create_network(&self, spec: NetworkSpec) -> Res<NetworkView> {
+        let client = self.client.as_ref();
+        let created = raw_requests::create_network(
+            self.id,
+            spec.name,
+            Some(spec.driver),
+            spec.attachable,
+            spec.internal,
+            spec.labels,
+        )
+        .send(client)?;
+        let network = raw_requests::get_network(self.id, &created.id).send(client)?;
+        Ok(NetworkView::from(&network))
+    }
+
+    pub fn inspect(&self) -> Res<EndpointView> {
+        let client = self.client.as_ref();
+        let endpoint = raw_requests::get_endpoint(self.id).send(client)?;
+        endpoint_view(client, &endpoint)
+    }
+
+    pub fn update(&self, name: Option<String>, settings: EndpointSettings) -> Res<EndpointView> {
+        let client = self.client.as_ref();
+        let tag_ids = match &settings.tags {
+            Some(tags) => Some(resolve_tags(client, tags)?),
+            None => None,
+        };
+        let endpoint = raw_requests::update_endpoint(
+            self.id,
+            name,
+            settings.url,
+            settings.public_url,
+            settings.group,
+            tag_ids,
+        )
+        .send(client)?;
+        endpoint_view(client, &endpoint)
+    }
+
+    /// Adds or removes tags of the endpoint, only updates the endpoint when tags change
+    pub fn tag(&self, names: Vec<String>, add: bool) -> Res<EndpointView> {
+        let client = self.client.as_ref();
+        let endpoint = raw_requests::get_endpoint(self.id).send(client)?;
+        let ids = resolve_tags(client, &names)?;
+
+        let current = endpoint.tag_ids();
+        let updated: Vec<i32> = if add {
+            let mut updated = current.clone();
+            updated.extend(ids.iter().filter(|id| !current.contains(id)));
+            updated
+        } else {
+            current
+                .iter()
+                .filter(|id| !ids.contains(id))
+                .copied()
+                .collect()
+        };
+
+        let endpoint = if &updated == current {
+            endpoint
+        } else {
+            raw_requests::update_endpoint(self.id, None, None, None, None, Some(updated))
+                .send(client)?
+        };
+        endpoint_view(client, &endpoint)
+    }
+
+    pub fn remove(self) -> Res<Plan> {
+        let endpoint = raw_requests::get_endpoint(self.id).send(self.client.as_ref())?;
+
+        Ok(Plan {
+            definition: PlanDef::RemoveEndpoint {
+                name: endpoint.name().to_string(),
+            },
+            endpoint: self.id,
+            client: self.client,
+            protection: self.protection,
+        })
+    }
+
+    pub fn stacks(self) -> Res<Vec<StackView>> {
+        let stacks = raw_requests::list_stacks(Some(self.id), None).send(self.client.as_ref())?;
+        Ok(stacks.iter().map(StackView::from).collect())
+    }
+
+    pub fn start(self, stacks: StackSelector, wait: Option<Duration>) -> Res<Plan> {
+        let stacks = self.select_stacks(stacks)?;
+
+        Ok(Plan {
+            definition: PlanDef::Start { stacks, wait },
+            endpoint: self.id,
+            client: self.client,
+            protection: self.protection,
+        })
+    }
+
+    pub fn stop(self, stacks: StackSelector, wait: Option<Duration>) -> Res<Plan> {
+        let stacks = self.select_stacks(stacks)?;
+
+        Ok(Plan {
+            definition: PlanDef::Stop { stacks, wait },
+            endpoint: self.id,
+            client: self.client,
+            protection: self.protection,
+        })
+    }
+
+    pub fn exec(self, stack: &str, service: &str, command: Vec<String>, tty: bool) -> Action {
+        let client = self.client.as_ref();
+        let service = raw_requests::list_stack_services(self.id, stack)
+            .send(client)?
+            .into_iter()
+            .find(|s| *s.name() == format!("{}_{}", stack, service) || s.name() == service)
+            .ok_or_else(|| format!("Service '{}' does not exist in stack '{}'", service, stack))?;
+
+        let mut tasks: Vec<Task> =
+            raw_requests::list_running_tasks(self.id, vec![service.id.to_string()])
+                .send(client)?
+                .into_iter()
+                .filter(|t| t.is_running() && t.container_id().is_some())
+                .collect();
+        tasks.sort_by_key(|t| t.slot);
+        let task = tasks
+            .first()
+            .ok_or_else(|| format!("Service '{}' has no running tasks", service.name()))?;
+        let container = task.container_id().unwrap();
+        let node = match &task.node_id {
+            Some(node) => Some(
+                raw_requests::get_node(self.id, node)
+                    .send(client)?
+                    .hostname()
+                    .to_string(),
+            ),
+            None => None,
+        };
+
+        let tty = tty && exec::tty_available();
+        let instance = raw_requests::create_exec(self.id, node.as_deref(), container, command, tty)
+            .send(client)?;
+        let exec = Exec {
+            client,
+            endpoint: self.id,
+            node,
+            id: instance.id,
+            tty,
+        };
+
+        let mut channel = client.connect(&raw_requests::attach_exec(
+            self.id,
+            exec.node.as_deref(),
+            &exec.id,
+        ))?;
+        exec.run(channel.as_mut())?;
+
+        match exec.exit_code()? {
+            Some(code) if code != 0 => Err(format!("Command exited with code {}", code)),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn logs(self, stack: &str, service: Option<String>, options: LogOptions) -> Action {
+        let client = self.client.as_ref();
+        let services: Vec<Service> = raw_requests::list_stack_services(self.id, stack)
+            .send(client)?
+            .into_iter()
+            .filter(|s| match &service {
+                // services are named `stack_service` by docker
+                Some(name) => s.name() == name || *s.name() == format!("{}_{}", stack, name),
+                None => true,
+            })
+            .collect();
+        if services.is_empty() {
+            return Err(match service {
+                Some(name) => format!("Service '{}' does not exist in stack '{}'", name, stack),
+                None => format!("Stack '{}' has no services", stack),
+            });
+        }
+
+        let ids = services.iter().map(|s| s.id.to_string()).collect();
+        let tasks = raw_requests::list_service_tasks(self.id, ids).send(client)?;
+
+        let mut printers = Vec::new();
+        for service in &services {
+            let names = tasks
+                .iter()
+                .filter(|t| t.service_id == service.id)
+                .map(|t| (t.id.to_string(), t.name(service.name())))
+                .collect();
+            let stream = raw_requests::service_logs(
+                self.id,
+                &service.id,
+                options.follow,
+                options.since,
+                options.tail.clone(),
+                options.timestamps,
+            )
+            .stream(client)?;
+            printers.push((
+                LogPrinter::new(service.name().to_string(), names, options.timestamps),
+                LogReader::new(stream, !service.tty()),
+            ));
+        }
+
+        thread::scope(|scope| {
+            let handles: Vec<_> = printers
+                .into_iter()
+                .map(|(printer, reader)| scope.spawn(move || printer.print(reader)))
+                .collect();
+            handles.into_iter().try_for_each(|h| {
+                h.join()
+                    .unwrap_or_else(|_| Err("Log reader panicked".to_string()))
+            })
+        })
+    }
+}
+
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+/// Guards against servers answering every page with new items
+const MAX_PAGES: usize = 10_000;
+
+fn service_converged(service: &Service, tasks: &[Task]) -> bool {
+    let tasks: Vec<&Task> = tasks
+        .iter()
+        .filter(|t| t.service_id == service.id)
+        .collect();
+    let running = tasks.iter().filter(|t| t.is_running()).count() as u64;
+
+    match service.replicas() {
+        Some(replicas) => running >= replicas,
+        None => !tasks.is_empty() && tasks.iter().all(|t| t.is_running()),
+    }
+}
+
+fn stack_running(client: &dyn PortainerClient, endpoint: i32, stack: &str) -> Res<bool> {
+    let services = raw_requests::list_stack_services(endpoint, stack).send(client)?;
+    if services.is_empty() {
+        return Ok(false);
+    }
+    let ids = services.iter().map(|s| s.id.to_string()).collect();
+    let tasks = raw_requests::list_running_tasks(endpoint, ids).send(client)?;
+
+    Ok(services.iter().all(|s| service_converged(s, &tasks)))
+}
+
+fn stack_stopped(client: &dyn PortainerClient, endpoint: i32, stack: &str) -> Res<bool> {
+    raw_requests::list_stack_services(endpoint, stack)
+        .send(client)
+        .map(|services| services.is_empty())
+}
+
+fn wait_for<F>(timeout: Duration, description: &str, mut done: F) -> Action
+where
+    F: FnMut() -> Res<bool>,
+{
+    // timeouts too large for a deadline never expire
+    let deadline = Instant::now().checked_add(timeout);
+    while !done()? {
+        if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            return Err(format!(
+                "Timed out after {}s waiting for {}",
+                timeout.as_secs(),
+                description
+            ));
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+    Ok(())
+}
+
+/// Asks on stderr whether to apply the printed plan, no by default. Protected targets
+/// must be confirmed by typing their names, `-Y` only confirms them when forced and the
+/// override is logged.
+fn confirm(confirmation: Confirmation, protected: &[Protected]) -> Res<bool> {
+    if protected.is_empty() {
+        return Ok(
+            confirmation.assumed || prompt::confirm("Do you want to apply this plan?", false)?
+        );
+    }
+    if confirmation.assumed || prompt::assume_yes() {
+        let names: Vec<String> = protected
+            .iter()
+            .map(|p| format!("{} '{}'", p.kind, p.name))
+            .collect();
+        if !confirmation.force_protected {
+            return Err(format!(
+                "Plan changes protected {}, confirm it interactively or pass --force-protected with -Y",
+                names.join(", ")
+            ));
+        }
+        let user = std::env::var("USER").unwrap_or_else(|_| "unknown user".to_string());
+        eprintln!(
+            "Protection of {} overridden with --force-protected by {}",
+            names.join(", "),
+            user
+        );
+        return Ok(true);
+    }
+    for target in protected {
+        let answer = prompt::typed(&format!(
+            "{} '{}' is protected, type its name to apply this plan:",
+            target.kind, target.name
+        ))
+        .map_err(|err| format!("{}. Pass -Y with --force-protected", err))?;
+        if answer != target.name {
+            eprintln!("Name did not match, nothing was applied");
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+fn missing_externals(volumes: &[String], networks: &[String]) -> Action {
+    let mut missing: Vec<String> = volumes.iter().map(|v| format!("volume '{}'", v)).collect();
+    missing.extend(networks.iter().map(|n| format!("network '{}'", n)));
+    if missing.is_empty() {
+        return Ok(());
+    }
+    Err(format!(
+        "External {} missing on the endpoint, create them or pass --create-externals",
+        missing.join(", ")
+    ))
+}
+
+/// Plans of several endpoints, possibly on different servers, confirmed once and
+/// executed in order
+pub struct PlanGraph(pub Vec<Plan>);
+impl PlanGraph {
+    pub fn view(&self) -> PlanGraphView {
+        PlanGraphView(self.0.iter().map(Plan::view).collect())
+    }
+
+    pub fn prompt(self, confirmation: Confirmation, printer: &Printer) -> Action {
+        printer.print(&self.view())?;
+        let mut protected = Vec::new();
+        for plan in &self.0 {
+            plan.check_externals()?;
+            protected.extend(plan.protected()?);
+        }
+        if confirm(confirmation, &protected)? {
+            for plan in self.0 {
+                plan.execute()?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn diff(&self) -> Res<DiffView> {
+        let mut diffs = Vec::new();
+        for plan in &self.0 {
+            diffs.extend(plan.diff()?);
+        }
+        Ok(DiffView(diffs))
+    }
 }
 
 pub struct Plan {
     definition: PlanDef,
     endpoint: i32,
-    client: Box<dyn PortainerClient>,
+    client: Rc<dyn PortainerClient>,
+    protection: Rc<Protection>,
 }
 impl Plan {
-    fn read(path: Box<Path>) -> Res<String> {
-        fs::read_to_string(path).map_err(|x| x.to_string())
-    }
-
     pub fn execute(self) -> Action {
         match self.definition {
             PlanDef::Deploy {
@@ -203,16 +1235,59 @@ impl Plan {
                 inline_vars,
                 configs,
                 secrets,
+                existing_configs,
+                existing_secrets,
+                registries,
+                missing_volumes,
+                missing_networks,
+                create_externals,
             } => {
                 let client = self.client.as_ref();
+                if !create_externals {
+                    missing_externals(&missing_volumes, &missing_networks)?;
+                }
 
-                for FileMapping(name, path) in configs {
-                    let content = Plan::read(path)?;
-                    raw_requests::create_config(self.endpoint, name, content).send(client)?;
+                for name in missing_volumes {
+                    raw_requests::create_volume(
+                        self.endpoint,
+                        name,
+                        None,
+                        HashMap::new(),
+                        HashMap::new(),
+                    )
+                    .send(client)?;
+                }
+                // stacks are deployed on swarm, their networks have to span the cluster
+                for name in missing_networks {
+                    raw_requests::create_network(
+                        self.endpoint,
+                        name,
+                        Some("overlay".to_string()),
+                        true,
+                        false,
+                        HashMap::new(),
+                    )
+                    .send(client)?;
                 }
-                for FileMapping(name, path) in secrets {
-                    let content = Plan::read(path)?;
-                    raw_requests::create_secret(self.endpoint, name, content).send(client)?;
+                for access in registries.iter().filter(|r| !r.granted) {
+                    raw_requests::grant_registry_access(self.endpoint, access.registry.id)
+                        .send(client)?;
+                }
+                for file in configs {
+                    if existing_configs.contains(&file.name) {
+                        continue;
+                    }
+                    let labels = file.created_labels();
+                    raw_requests::create_config(self.endpoint, file.name, labels, file.content)
+                        .send(client)?;
+                }
+                for file in secrets {
+                    if existing_secrets.contains(&file.name) {
+                        continue;
+                    }
+                    let labels = file.created_labels();
+                    raw_requests::create_secret(self.endpoint, file.name, labels, file.content)
+                        .send(client)?;
                 }
 
                 let env = inline_vars
@@ -225,8 +1300,8 @@ impl Plan {
                         raw_requests::create_stacks(self.endpoint, swarm_id, name, compose, env)
                             .send(self.client.as_ref())
                     }
-                    StackPlan::Update(stack_id) => {
-                        raw_requests::update_stacks(self.endpoint, stack_id, compose, env, true)
+                    StackPlan::Update { id, .. } => {
+                        raw_requests::update_stacks(self.endpoint, id, compose, env, true)
                             .send(client)
                     }
                 }?;
@@ -237,6 +1312,8 @@ impl Plan {
                 stacks,
                 configs,
                 secrets,
+                volumes,
+                networks,
             } => {
                 let client = self.client.as_ref();
 
@@ -249,83 +1326,392 @@ impl Plan {
                 for secret in secrets {
                     raw_requests::delete_secret(self.endpoint, secret.id).send(client)?;
                 }
+                for volume in volumes {
+                    raw_requests::delete_volume(self.endpoint, &volume.name).send(client)?;
+                }
+                for network in networks {
+                    raw_requests::delete_network(self.endpoint, &network.id).send(client)?;
+                }
 
                 Ok(())
             }
+            PlanDef::RemoveEndpoint { .. } => {
+                raw_requests::delete_endpoint(self.endpoint).send(self.client.as_ref())?;
+                Ok(())
+            }
+            PlanDef::Start { stacks, wait } => {
+                let client = self.client.as_ref();
+
+                for stack in stacks.iter().filter(|s| !s.is_active()) {
+                    raw_requests::start_stack(self.endpoint, stack.id).send(client)?;
+                }
+                if let Some(timeout) = wait {
+                    for stack in &stacks {
+                        wait_for(timeout, &format!("stack '{}' to start", stack.name), || {
+                            stack_running(client, self.endpoint, &stack.name)
+                        })?;
+                    }
+                }
+
+                Ok(())
+            }
+            PlanDef::Stop { stacks, wait } => {
+                let client = self.client.as_ref();
+
+                for stack in stacks.iter().filter(|s| s.is_active()) {
+                    raw_requests::stop_stack(self.endpoint, stack.id).send(client)?;
+                }
+                if let Some(timeout) = wait {
+                    for stack in &stacks {
+                        wait_for(timeout, &format!("stack '{}' to stop", stack.name), || {
+                            stack_stopped(client, self.endpoint, &stack.name)
+                        })?;
+                    }
+                }
+
+                Ok(())
+            }
+        }
+    }
+
+    pub fn prompt(self, confirmation: Confirmation, printer: &Printer) -> Action {
+        printer.print(&self.view())?;
+        self.check_externals()?;
+        if confirm(confirmation, &self.protected()?)? {
+            self.execute()?;
+        }
+        Ok(())
+    }
+
+    /// Lets a deploy create the external volumes and networks it is missing
+    pub fn create_externals(mut self, create: bool) -> Plan {
+        if let PlanDef::Deploy {
+            create_externals, ..
+        } = &mut self.definition
+        {
+            *create_externals = create;
         }
+        self
     }
 
-    pub fn prompt(self, confirmed: bool) -> Action {
-        if confirmed {
-            self.execute()
+    /// Fails before confirmation when a deploy lacks external volumes or networks
+    fn check_externals(&self) -> Action {
+        match &self.definition {
+            PlanDef::Deploy {
+                missing_volumes,
+                missing_networks,
+                create_externals: false,
+                ..
+            } => missing_externals(missing_volumes, missing_networks),
+            _ => Ok(()),
+        }
+    }
+
+    /// Protected endpoint or stacks this plan destroys, or removes services of
+    pub fn protected(&self) -> Res<Vec<Protected>> {
+        if self.protection.is_empty() {
+            return Ok(Vec::new());
+        }
+        let stacks: Vec<&str> = match &self.definition {
+            PlanDef::Destroy { stacks, .. } => stacks.iter().map(|s| s.name.as_str()).collect(),
+            PlanDef::Deploy {
+                stack_plan: StackPlan::Update { name, .. },
+                ..
+            } if !self.removed_services()?.is_empty() => vec![name.as_str()],
+            PlanDef::RemoveEndpoint { .. } => Vec::new(),
+            _ => return Ok(Vec::new()),
+        };
+
+        if self.protection.has_endpoint_rules() {
+            let client = self.client.as_ref();
+            let endpoint = raw_requests::get_endpoint(self.endpoint).send(client)?;
+            let tags = tag_names(client)?;
+            let tags: Vec<String> = endpoint
+                .tag_ids()
+                .iter()
+                .filter_map(|id| tags.get(id).cloned())
+                .collect();
+            if self.protection.protects_endpoint(endpoint.name(), &tags)? {
+                return Ok(vec![Protected {
+                    kind: "endpoint",
+                    name: endpoint.name().to_string(),
+                }]);
+            }
+        }
+        let mut protected = Vec::new();
+        for stack in stacks {
+            if self.protection.protects_stack(stack)? {
+                protected.push(Protected {
+                    kind: "stack",
+                    name: stack.to_string(),
+                });
+            }
+        }
+        Ok(protected)
+    }
+
+    /// Compares the live stack with the local definition, only deploy plans have a diff
+    pub fn diff(&self) -> Res<Option<StackDiff>> {
+        let PlanDef::Deploy {
+            stack_plan,
+            compose,
+            inline_vars,
+            configs,
+            secrets,
+            ..
+        } = &self.definition
+        else {
+            return Ok(None);
+        };
+        let client = self.client.as_ref();
+        let (stack, live_compose, live_env) = match stack_plan {
+            StackPlan::Create { name, .. } => (name, String::new(), BTreeMap::new()),
+            StackPlan::Update { name, id } => {
+                let file = raw_requests::get_stack_file(*id).send(client)?;
+                let env = raw_requests::get_stack(*id)
+                    .send(client)?
+                    .env
+                    .into_iter()
+                    .map(|e| (e.name, e.value))
+                    .collect();
+                (name, file.content, env)
+            }
+        };
+        let local_env = inline_vars
+            .iter()
+            .map(|InlineEnv(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+        let names = |files: &[FileContent]| files.iter().map(|f| f.name.to_string()).collect();
+
+        let live_configs = if configs.is_empty() {
+            Vec::new()
         } else {
-            self.print();
-            for line in std::io::stdin().lines() {
-                let line = line.map_err(|x| x.to_string()).map(|s| s.to_lowercase())?;
-                if line == "yes" {
-                    self.execute()?;
-                    break;
-                } else if line == "no" {
-                    break;
-                } else {
-                    println!("You must answer 'yes' or 'no'");
-                    continue;
-                }
+            raw_requests::list_configs(self.endpoint, names(configs)).send(client)?
+        };
+        let mut config_changes = Vec::new();
+        for file in configs {
+            match config_unchanged(&live_configs, file)? {
+                None => config_changes.push(format!("+{}", file.name)),
+                Some(false) => config_changes.push(format!("~{}", file.name)),
+                Some(true) => (),
             }
-            Ok(())
         }
+
+        let live_secrets = if secrets.is_empty() {
+            Vec::new()
+        } else {
+            raw_requests::list_secrets(self.endpoint, names(secrets)).send(client)?
+        };
+        let secret_changes = secrets
+            .iter()
+            .filter_map(|file| match secret_unchanged(&live_secrets, file) {
+                None => Some(format!("+{}", file.name)),
+                Some(false) => Some(format!("~{}", file.name)),
+                Some(true) => None,
+            })
+            .collect();
+
+        Ok(Some(StackDiff {
+            endpoint: self.endpoint,
+            stack: stack.to_string(),
+            compose: diff::unified(&live_compose, compose, "live", "local"),
+            env: diff::env_changes(&live_env, &local_env),
+            configs: config_changes,
+            secrets: secret_changes,
+        }))
     }
 
-    pub fn print(&self) {
-        match &self.definition {
+    /// Services of the live stack missing from the local definition, these are removed
+    /// when the stack is updated
+    pub fn removed_services(&self) -> Res<Vec<String>> {
+        let PlanDef::Deploy {
+            stack_plan: StackPlan::Update { id, .. },
+            compose,
+            ..
+        } = &self.definition
+        else {
+            return Ok(Vec::new());
+        };
+        let live = raw_requests::get_stack_file(*id).send(self.client.as_ref())?;
+        let local = ComposeFile::parse(compose)?.service_names();
+        Ok(ComposeFile::parse(&live.content)?
+            .service_names()
+            .into_iter()
+            .filter(|name| !local.contains(name))
+            .collect())
+    }
+
+    pub fn view(&self) -> PlanView {
+        let (plan, steps) = match &self.definition {
             PlanDef::Deploy {
                 stack_plan,
                 compose: _,
                 inline_vars: _,
                 configs,
                 secrets,
+                existing_configs,
+                existing_secrets,
+                registries,
+                missing_volumes,
+                missing_networks,
+                create_externals,
             } => {
-                println!("Deploy plan:");
-                match stack_plan {
-                    StackPlan::Create { name, swarm_id } => println!(
-                        "Create a new stack with name {} on swarm cluster '{}'",
-                        name, swarm_id
-                    ),
-                    StackPlan::Update(id) => println!("Update existing stack with id {}", id),
+                let registry_auth = if registries.is_empty() {
+                    None
+                } else {
+                    let names: Vec<&str> = registries
+                        .iter()
+                        .map(|r| r.registry.name.as_str())
+                        .collect();
+                    Some(format!("with registry auth from {}", names.join(", ")))
                 };
-
-                for FileMapping(name, _) in configs {
-                    println!("Try to create a new config with name {}", name);
+                let stack_step = match stack_plan {
+                    StackPlan::Create { name, swarm_id } => {
+                        let mut detail = format!("on swarm cluster '{}'", swarm_id);
+                        if let Some(auth) = registry_auth {
+                            detail = format!("{}, {}", detail, auth);
+                        }
+                        PlanStep::new("create", "stack", name).with_detail(detail)
+                    }
+                    StackPlan::Update { name, id } => {
+                        let step = PlanStep::new("update", "stack", name).with_id(id);
+                        match registry_auth {
+                            Some(auth) => step.with_detail(auth),
+                            None => step,
+                        }
+                    }
+                };
+                let mut steps = vec![stack_step];
+                for RegistryAccess { registry, granted } in registries {
+                    let step = if *granted {
+                        PlanStep::new("skip", "registry", &registry.name)
+                            .with_detail("endpoint already has access".to_string())
+                    } else {
+                        PlanStep::new("grant", "registry", &registry.name)
+                            .with_detail(format!("pull access to '{}' on endpoint", registry.url))
+                    };
+                    steps.push(step.with_id(registry.id));
                 }
-
-                for FileMapping(name, _) in secrets {
-                    println!("Try to create a new secret with name {}", name);
+                // only sources are shown, contents of configs and secrets are never printed
+                let file_step = |kind, file: &FileContent, existing: &Vec<String>| {
+                    if existing.contains(&file.name) {
+                        PlanStep::new("skip", kind, &file.name)
+                            .with_detail("already exists, unchanged".to_string())
+                    } else {
+                        PlanStep::new("create", kind, &file.name)
+                            .with_detail(format!("from {}", file.source))
+                    }
+                };
+                for file in configs {
+                    steps.push(file_step("config", file, existing_configs));
+                }
+                for file in secrets {
+                    steps.push(file_step("secret", file, existing_secrets));
+                }
+                let external_step = |kind, name: &String| {
+                    if *create_externals {
+                        PlanStep::new("create", kind, name)
+                            .with_detail("external, missing on endpoint".to_string())
+                    } else {
+                        PlanStep::new("missing", kind, name).with_detail(
+                            "external, pass --create-externals to create it".to_string(),
+                        )
+                    }
+                };
+                for name in missing_volumes {
+                    steps.push(external_step("volume", name));
+                }
+                for name in missing_networks {
+                    steps.push(external_step("network", name));
                 }
+                ("deploy", steps)
             }
             PlanDef::Destroy {
                 stacks,
                 configs,
                 secrets,
+                volumes,
+                networks,
             } => {
-                println!("Destroy plan:");
+                let mut steps = Vec::new();
                 for stack in stacks {
-                    println!("Stack '{}' will be removed. (id: {})", stack.name, stack.id);
+                    steps.push(PlanStep::new("remove", "stack", &stack.name).with_id(stack.id));
                 }
                 for config in configs {
-                    println!(
-                        "Config '{}' will be removed. (id: {})",
-                        config.name(),
-                        config.id
-                    );
+                    steps
+                        .push(PlanStep::new("remove", "config", config.name()).with_id(&config.id));
                 }
                 for secret in secrets {
-                    println!(
-                        "Secret '{}' will be removed. (id: {})",
-                        secret.name(),
-                        secret.id
+                    steps
+                        .push(PlanStep::new("remove", "secret", secret.name()).with_id(&secret.id));
+                }
+                for volume in volumes {
+                    steps.push(
+                        PlanStep::new("remove", "volume", &volume.name)
+                            .with_detail(format!("{} driver", volume.driver)),
+                    );
+                }
+                for network in networks {
+                    steps.push(
+                        PlanStep::new("remove", "network", &network.name).with_id(&network.id),
+                    );
+                }
+                ("destroy", steps)
+            }
+            PlanDef::RemoveEndpoint { name } => (
+                "remove endpoint",
+                vec![PlanStep::new("remove", "endpoint", name).with_id(self.endpoint)],
+            ),
+            PlanDef::Start { stacks, wait } => {
+                let mut steps: Vec<PlanStep> = stacks
+                    .iter()
+                    .map(|stack| {
+                        if stack.is_active() {
+                            PlanStep::new("skip", "stack", &stack.name)
+                                .with_id(stack.id)
+                                .with_detail("already running".to_string())
+                        } else {
+                            PlanStep::new("start", "stack", &stack.name).with_id(stack.id)
+                        }
+                    })
+                    .collect();
+                if let Some(timeout) = wait {
+                    steps.push(
+                        PlanStep::new("wait", "services", "").with_detail(format!(
+                            "up to {}s for services to run",
+                            timeout.as_secs()
+                        )),
                     );
                 }
+                ("start", steps)
+            }
+            PlanDef::Stop { stacks, wait } => {
+                let mut steps: Vec<PlanStep> = stacks
+                    .iter()
+                    .map(|stack| {
+                        if stack.is_active() {
+                            PlanStep::new("stop", "stack", &stack.name).with_id(stack.id)
+                        } else {
+                            PlanStep::new("skip", "stack", &stack.name)
+                                .with_id(stack.id)
+                                .with_detail("already stopped".to_string())
+                        }
+                    })
+                    .collect();
+                if let Some(timeout) = wait {
+                    steps.push(PlanStep::new("wait", "services", "").with_detail(format!(
+                        "up to {}s for services to be removed",
+                        timeout.as_secs()
+                    )));
+                }
+                ("stop", steps)
             }
+        };
+
+        PlanView {
+            plan,
+            endpoint: self.endpoint,
+            steps,
         }
     }
 }
@@ -335,17 +1721,530 @@ enum PlanDef {
         stack_plan: StackPlan,
         compose: String,
         inline_vars: Vec<InlineEnv>,
-        configs: Vec<FileMapping>,
-        secrets: Vec<FileMapping>,
+        configs: Vec<FileContent>,
+        secrets: Vec<FileContent>,
+        existing_configs: Vec<String>,
+        existing_secrets: Vec<String>,
+        registries: Vec<RegistryAccess>,
+        /// External volumes and networks of the compose file missing on the endpoint
+        missing_volumes: Vec<String>,
+        missing_networks: Vec<String>,
+        create_externals: bool,
     },
     Destroy {
         stacks: Vec<Stack>,
         configs: Vec<Config>,
         secrets: Vec<Secret>,
+        volumes: Vec<Volume>,
+        networks: Vec<Network>,
+    },
+    RemoveEndpoint {
+        name: String,
+    },
+    Start {
+        stacks: Vec<Stack>,
+        wait: Option<Duration>,
     },
+    Stop {
+        stacks: Vec<Stack>,
+        wait: Option<Duration>,
+    },
+}
+
+/// A docker config or secret resolved from its source before planning, so that
+/// decrypted files and secrets from providers stay in memory
+#[derive(Clone)]
+pub struct FileContent {
+    pub name: String,
+    /// File or provider reference the content comes from, shown instead of the content
+    pub source: String,
+    pub content: String,
+    /// Labels of the created config or secret
+    pub labels: HashMap<String, String>,
+}
+
+impl FileContent {
+    fn digest(&self) -> String {
+        Sha256::digest(self.content.as_bytes())
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect()
+    }
+
+    /// Labels of the created config or secret along with the digest of its content
+    fn created_labels(&self) -> HashMap<String, String> {
+        let mut labels = self.labels.clone();
+        labels.insert(CONTENT_LABEL.to_string(), self.digest());
+        labels
+    }
+}
+
+/// Label of created configs and secrets holding the sha256 digest of their content, as
+/// secrets cannot be read back
+const CONTENT_LABEL: &str = "pctl.content.sha256";
+
+/// Whether a live config has the content of the file, `None` when there is none
+fn config_unchanged(live: &[Config], file: &FileContent) -> Res<Option<bool>> {
+    live.iter()
+        .find(|c| *c.name() == file.name)
+        .map(|c| c.content().map(|content| content == file.content))
+        .transpose()
+}
+
+/// Whether a live secret has the content of the file, `None` when there is none. Secrets
+/// without a digest label are taken as changed.
+fn secret_unchanged(live: &[Secret], file: &FileContent) -> Option<bool> {
+    live.iter()
+        .find(|s| *s.name() == file.name)
+        .map(|s| s.labels().get(CONTENT_LABEL) == Some(&file.digest()))
+}
+
+/// Names of files already on the endpoint with the same content. Changed files fail the
+/// plan as docker cannot update configs and secrets.
+fn existing_files(
+    kind: &str,
+    files: &[FileContent],
+    unchanged: impl Fn(&FileContent) -> Res<Option<bool>>,
+) -> Res<Vec<String>> {
+    let mut existing = Vec::new();
+    for file in files {
+        match unchanged(file)? {
+            None => (),
+            Some(true) => existing.push(file.name.to_string()),
+            Some(false) => {
+                return Err(format!(
+                    "{} '{}' already exists with other content and docker cannot update it, give it a new name, e.g. '{}_v2'",
+                    kind, file.name, file.name
+                ))
+            }
+        }
+    }
+    Ok(existing)
+}
+
+/// A registry used by a deploy, access is granted to the endpoint when missing
+struct RegistryAccess {
+    registry: Registry,
+    granted: bool,
 }
 
 enum StackPlan {
     Create { name: String, swarm_id: String },
-    Update(i32),
+    Update { name: String, id: i32 },
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::io::Read;
+
+    use serde_json::json;
+
+    use super::*;
+    use crate::portainer::client::Duplex;
+    use crate::portainer::output::Output;
+
+    struct OfflineClient;
+    impl PortainerClient for OfflineClient {
+        fn send(&self, _: &PortainerRequestRaw) -> Res<Json> {
+            Err("Offline".to_string())
+        }
+        fn stream(&self, _: &PortainerRequestRaw) -> Res<Box<dyn Read + Send>> {
+            Err("Offline".to_string())
+        }
+        fn connect(&self, _: &PortainerRequestRaw) -> Res<Box<dyn Duplex>> {
+            Err("Offline".to_string())
+        }
+    }
+
+    /// Keeps the form of the last request, then fails like an offline client
+    #[derive(Default)]
+    struct FormClient(RefCell<Vec<(String, String)>>);
+    impl PortainerClient for FormClient {
+        fn send(&self, req: &PortainerRequestRaw) -> Res<Json> {
+            let fields = ["TLS", "TLSSkipVerify"]
+                .iter()
+                .filter_map(|key| Some((key.to_string(), req.field(key)?.to_string())))
+                .collect();
+            self.0.replace(fields);
+            Err("Offline".to_string())
+        }
+        fn stream(&self, _: &PortainerRequestRaw) -> Res<Box<dyn Read + Send>> {
+            Err("Offline".to_string())
+        }
+        fn connect(&self, _: &PortainerRequestRaw) -> Res<Box<dyn Duplex>> {
+            Err("Offline".to_string())
+        }
+    }
+
+    /// Serves a swarm endpoint with two stacks, `web` and `db`, and a preview of `web`
+    struct SwarmClient;
+    impl SwarmClient {
+        fn compose(team: &str) -> Json {
+            let content = format!(
+                "services:\n  app:\n    image: nginx\n    deploy:\n      labels:\n        team: {}\n",
+                team
+            );
+            json!({ "StackFileContent": content })
+        }
+
+        fn service(name: &str, stack: &str, configs: &[&str], secrets: &[&str]) -> Json {
+            let configs: Vec<Json> = configs.iter().map(|id| json!({ "ConfigID": id })).collect();
+            let secrets: Vec<Json> = secrets.iter().map(|id| json!({ "SecretID": id })).collect();
+            json!({
+                "ID": name,
+                "Spec": {
+                    "Name": name,
+                    "Mode": { "Replicated": { "Replicas": 1 } },
+                    "Labels": { "com.docker.stack.namespace": stack },
+                    "TaskTemplate": { "ContainerSpec": { "Configs": configs, "Secrets": secrets } },
+                },
+            })
+        }
+
+        fn file(name: &str, labels: Json) -> Json {
+            json!({ "ID": name, "Spec": { "Name": name, "Labels": labels } })
+        }
+    }
+    impl PortainerClient for SwarmClient {
+        fn send(&self, req: &PortainerRequestRaw) -> Res<Json> {
+            let stack = |id: i32, name: &str| json!({ "Id": id, "Name": name, "SwarmId": "swarm", "EndpointId": 1, "Status": 1 });
+            let web = json!({ "team": "web" });
+            Ok(match req.path() {
+                "/stacks" => json!([stack(1, "web"), stack(2, "db"), stack(3, "web-preview")]),
+                "/stacks/1/file" | "/stacks/3/file" => SwarmClient::compose("web"),
+                "/stacks/2/file" => SwarmClient::compose("data"),
+                "/endpoints/1/docker/services" => json!([
+                    SwarmClient::service("web_app", "web", &["web_conf"], &[]),
+                    SwarmClient::service("db_pg", "db", &["db_conf"], &["db_pw"]),
+                ]),
+                "/endpoints/1/docker/configs" => json!([
+                    SwarmClient::file("web_conf", web.clone()),
+                    SwarmClient::file("db_conf", json!({})),
+                    SwarmClient::file("old_conf", json!({})),
+                ]),
+                "/endpoints/1/docker/secrets" => json!([
+                    SwarmClient::file("web_key", web.clone()),
+                    SwarmClient::file("db_pw", json!({})),
+                ]),
+                "/endpoints/1/docker/volumes" => json!({ "Volumes": [
+                    { "Name": "web_data", "Driver": "local", "Labels": web.clone() },
+                    { "Name": "db_data", "Driver": "local", "Labels": null },
+                ]}),
+                "/endpoints/1/docker/networks" => json!([
+                    { "Id": "n1", "Name": "web_net", "Labels": web.clone() },
+                    { "Id": "n2", "Name": "db_net" },
+                    { "Id": "n3", "Name": "ingress", "Labels": web },
+                ]),
+                path => return Err(format!("Unexpected request {}", path)),
+            })
+        }
+        fn stream(&self, _: &PortainerRequestRaw) -> Res<Box<dyn Read + Send>> {
+            Err("Offline".to_string())
+        }
+        fn connect(&self, _: &PortainerRequestRaw) -> Res<Box<dyn Duplex>> {
+            Err("Offline".to_string())
+        }
+    }
+
+    fn patterns(globs: &[&str]) -> Vec<glob::Pattern> {
+        globs
+            .iter()
+            .map(|glob| glob::Pattern::new(glob).unwrap())
+            .collect()
+    }
+
+    /// Kind and name of everything a destroy selects, in the order of the plan
+    fn destroyed(selection: DestroySelection) -> Vec<String> {
+        let endpoint = Endpoint {
+            client: Rc::new(SwarmClient),
+            id: 1,
+            protection: Rc::new(Protection::default()),
+        };
+        let PlanDef::Destroy {
+            stacks,
+            configs,
+            secrets,
+            volumes,
+            networks,
+        } = endpoint.destroy(selection).unwrap().definition
+        else {
+            panic!("Not a destroy plan");
+        };
+        let named = |kind: &str, name: &str| format!("{} {}", kind, name);
+        let mut selected: Vec<String> = stacks.iter().map(|s| named("stack", &s.name)).collect();
+        selected.extend(configs.iter().map(|c| named("config", c.name())));
+        selected.extend(secrets.iter().map(|s| named("secret", s.name())));
+        selected.extend(volumes.iter().map(|v| named("volume", &v.name)));
+        selected.extend(networks.iter().map(|n| named("network", &n.name)));
+        selected
+    }
+
+    fn selection() -> DestroySelection {
+        DestroySelection {
+            stacks: Vec::new(),
+            configs: Vec::new(),
+            secrets: Vec::new(),
+            volumes: Vec::new(),
+            networks: Vec::new(),
+            labels: Vec::new(),
+            orphans: false,
+        }
+    }
+
+    fn file(name: &str, source: &str, content: &str) -> FileContent {
+        FileContent {
+            name: name.to_string(),
+            source: source.to_string(),
+            content: content.to_string(),
+            labels: HashMap::new(),
+        }
+    }
+
+    #[test]
+    fn deploy_plan_shows_sources_but_never_contents() {
+        let plan = Plan {
+            definition: PlanDef::Deploy {
+                stack_plan: StackPlan::Update {
+                    name: "app".to_string(),
+                    id: 3,
+                },
+                compose: "services: {}".to_string(),
+                inline_vars: Vec::new(),
+                configs: vec![file("app_conf", "conf/app.toml", "debug = true")],
+                secrets: vec![
+                    file("db_password", "vault://secret/db#password", "hunter2"),
+                    file("api_key", "env:API_KEY", "sk-123456"),
+                ],
+                existing_configs: Vec::new(),
+                existing_secrets: vec!["api_key".to_string()],
+                registries: Vec::new(),
+                missing_volumes: Vec::new(),
+                missing_networks: Vec::new(),
+                create_externals: false,
+            },
+            endpoint: 1,
+            client: Rc::new(OfflineClient),
+            protection: Rc::new(Protection::default()),
+        };
+
+        let view = plan.view();
+        let cells: Vec<String> = view
+            .table()
+            .rows
+            .into_iter()
+            .flatten()
+            .map(|cell| cell.0)
+            .collect();
+        let json = serde_json::to_string(&view).unwrap();
+        let yaml = serde_yaml::to_string(&view).unwrap();
+
+        assert!(cells.contains(&"from vault://secret/db#password".to_string()));
+        assert!(cells.contains(&"from conf/app.toml".to_string()));
+        for printed in [cells.join(" "), json, yaml] {
+            for content in ["hunter2", "sk-123456", "debug = true"] {
+                assert!(!printed.contains(content), "'{}' is printed", content);
+            }
+        }
+    }
+
+    fn secret(name: &str, labels: Json) -> Secret {
+        serde_json::from_value(json!({
+            "ID": "s1",
+            "Spec": {"Name": name, "Labels": labels}
+        }))
+        .unwrap()
+    }
+
+    fn config(name: &str, content: &str) -> Config {
+        use base64::engine::general_purpose;
+        use base64::Engine as _;
+        serde_json::from_value(json!({
+            "ID": "c1",
+            "Spec": {"Name": name, "Data": general_purpose::STANDARD.encode(content)}
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn configs_are_compared_by_content() {
+        let live = vec![config("app_conf", "debug = true")];
+
+        assert_eq!(
+            config_unchanged(&live, &file("app_conf", "", "debug = true")),
+            Ok(Some(true))
+        );
+        assert_eq!(
+            config_unchanged(&live, &file("app_conf", "", "debug = false")),
+            Ok(Some(false))
+        );
+        assert_eq!(config_unchanged(&live, &file("other", "", "")), Ok(None));
+    }
+
+    #[test]
+    fn secrets_are_compared_by_digest_label() {
+        let password = file("db_password", "", "hunter2");
+        let labels = password.created_labels();
+        let live = vec![
+            secret("db_password", json!(labels)),
+            secret("api_key", json!({})),
+        ];
+
+        assert_eq!(secret_unchanged(&live, &password), Some(true));
+        assert_eq!(
+            secret_unchanged(&live, &file("db_password", "", "hunter3")),
+            Some(false)
+        );
+        // without a digest the content is unknown
+        assert_eq!(
+            secret_unchanged(&live, &file("api_key", "", "sk-1")),
+            Some(false)
+        );
+        assert_eq!(secret_unchanged(&live, &file("other", "", "")), None);
+    }
+
+    #[test]
+    fn changed_files_fail_instead_of_being_skipped() {
+        let files = vec![file("a", "", "1"), file("b", "", "2"), file("c", "", "3")];
+        let unchanged = |file: &FileContent| {
+            Ok(match file.name.as_str() {
+                "a" => Some(true),
+                _ => None,
+            })
+        };
+        assert_eq!(
+            existing_files("Config", &files, unchanged),
+            Ok(vec!["a".to_string()])
+        );
+
+        let changed = |file: &FileContent| Ok((file.name == "b").then_some(false));
+        assert_eq!(
+            existing_files("Secret", &files, changed),
+            Err("Secret 'b' already exists with other content and docker cannot update it, give it a new name, e.g. 'b_v2'".to_string())
+        );
+    }
+
+    #[test]
+    fn agent_endpoints_skip_tls_verification_by_default() {
+        let tls_fields = |kind: EndpointKind, tls_skip_verify: Option<bool>| {
+            let client = Rc::new(FormClient::default());
+            let session = Session {
+                client: client.clone(),
+                credential: Credential::APIToken("token".to_string()),
+                url: "http://portainer/api".to_string(),
+                protection: Rc::new(Protection::default()),
+            };
+            let settings = EndpointSettings {
+                url: Some("10.0.0.1:9001".to_string()),
+                public_url: None,
+                group: None,
+                tags: None,
+                tls_skip_verify,
+            };
+            assert!(session
+                .create_endpoint("node".to_string(), kind, settings)
+                .is_err());
+            client.0.take()
+        };
+        let fields = |pairs: &[(&str, &str)]| -> Vec<(String, String)> {
+            pairs
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect()
+        };
+
+        assert_eq!(
+            tls_fields(EndpointKind::Agent, None),
+            fields(&[("TLS", "true"), ("TLSSkipVerify", "true")])
+        );
+        assert_eq!(
+            tls_fields(EndpointKind::Agent, Some(false)),
+            fields(&[("TLS", "true"), ("TLSSkipVerify", "false")])
+        );
+        assert_eq!(tls_fields(EndpointKind::Docker, None), fields(&[]));
+        assert_eq!(
+            tls_fields(EndpointKind::Docker, Some(true)),
+            fields(&[("TLS", "true"), ("TLSSkipVerify", "true")])
+        );
+        assert_eq!(
+            tls_fields(EndpointKind::Docker, Some(false)),
+            fields(&[("TLS", "true"), ("TLSSkipVerify", "false")])
+        );
+    }
+
+    #[test]
+    fn destroy_selects_by_globs() {
+        assert_eq!(
+            destroyed(DestroySelection {
+                stacks: patterns(&["web*"]),
+                configs: patterns(&["*_conf"]),
+                volumes: patterns(&["db_*"]),
+                networks: patterns(&["*"]),
+                ..selection()
+            }),
+            vec![
+                "stack web",
+                "stack web-preview",
+                "config web_conf",
+                "config db_conf",
+                "config old_conf",
+                "volume db_data",
+                "network web_net",
+                "network db_net",
+            ]
+        );
+        assert_eq!(destroyed(selection()), Vec::<String>::new());
+    }
+
+    #[test]
+    fn destroy_selects_by_labels_of_every_kind() {
+        assert_eq!(
+            destroyed(DestroySelection {
+                labels: vec![Label("team".to_string(), "web".to_string())],
+                ..selection()
+            }),
+            vec![
+                "stack web",
+                "stack web-preview",
+                "config web_conf",
+                "secret web_key",
+                "volume web_data",
+                "network web_net",
+            ]
+        );
+    }
+
+    #[test]
+    fn destroy_selects_orphans_unused_by_remaining_services() {
+        assert_eq!(
+            destroyed(DestroySelection {
+                orphans: true,
+                ..selection()
+            }),
+            vec!["config old_conf", "secret web_key"]
+        );
+        // services of destroyed stacks leave their files orphaned
+        assert_eq!(
+            destroyed(DestroySelection {
+                stacks: patterns(&["web"]),
+                orphans: true,
+                ..selection()
+            }),
+            vec![
+                "stack web",
+                "config web_conf",
+                "config old_conf",
+                "secret web_key"
+            ]
+        );
+        // patterns restrict orphans to their kind
+        assert_eq!(
+            destroyed(DestroySelection {
+                configs: patterns(&["*_conf"]),
+                orphans: true,
+                ..selection()
+            }),
+            vec!["config old_conf"]
+        );
+    }
 }
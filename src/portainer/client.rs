@@ -1,17 +1,33 @@
-use std::{fmt::Display, marker::PhantomData, ops::Deref};
+use std::{fmt::Display, io::Read, marker::PhantomData, ops::Deref};
 
 use serde::{de::DeserializeOwned, Serialize};
 use serde_json::Value as Json;
 
 pub trait PortainerClient {
     fn send(&self, req: &PortainerRequestRaw) -> Res<Json>;
+    /// Sends request and returns the raw response body, used for long running streams like logs
+    fn stream(&self, req: &PortainerRequestRaw) -> Res<Box<dyn Read + Send>>;
+    /// Opens a websocket to the given path, e.g. for attaching to exec instances
+    fn connect(&self, req: &PortainerRequestRaw) -> Res<Box<dyn Duplex>>;
+}
+
+pub enum Received {
+    Data(Vec<u8>),
+    Idle,
+    Closed,
+}
+
+/// Bidirectional message channel, reads return `Idle` after a short timeout when there is no data
+pub trait Duplex {
+    fn receive(&mut self) -> Res<Received>;
+    fn send(&mut self, data: &[u8]) -> Action;
 }
 
 pub struct PortainerRequest<O: DeserializeOwned>(PortainerRequestRaw, PhantomData<O>);
 
 impl<O: DeserializeOwned> PortainerRequest<O> {
     pub fn new(request: PortainerRequestRaw) -> PortainerRequest<O> {
-        PortainerRequest(request, PhantomData::default())
+        PortainerRequest(request, PhantomData)
     }
     pub fn send(&self, client: &dyn PortainerClient) -> Res<O> {
         serde_json::from_value(client.send(&self.0)?).map_err(|x| x.to_string())
@@ -38,24 +54,40 @@ impl<O: DeserializeOwned> From<PortainerRequestRaw> for PortainerRequest<O> {
 
 pub struct PortainerRequestRaw {
     body: Option<Json>,
+    form: Option<Vec<(String, String)>>,
     path: String,
     queries: Option<Vec<(String, String)>>,
+    headers: Vec<(String, String)>,
     method: HttpMethod,
 }
 impl PortainerRequestRaw {
+    pub fn new(method: HttpMethod, path: &str, body: Option<Json>) -> PortainerRequestRaw {
+        PortainerRequestRaw {
+            body,
+            form: None,
+            path: path.to_string(),
+            queries: None,
+            headers: Vec::new(),
+            method,
+        }
+    }
     pub fn get(path: &str) -> PortainerRequestRaw {
         PortainerRequestRaw {
             body: None,
+            form: None,
             path: path.to_string(),
             queries: None,
+            headers: Vec::new(),
             method: HttpMethod::GET,
         }
     }
     pub fn delete(path: &str) -> PortainerRequestRaw {
         PortainerRequestRaw {
             body: None,
+            form: None,
             path: path.to_string(),
             queries: None,
+            headers: Vec::new(),
             method: HttpMethod::DELETE,
         }
     }
@@ -63,8 +95,10 @@ impl PortainerRequestRaw {
         let body = serde_json::to_value(body).expect("Invalid body!");
         PortainerRequestRaw {
             body: Some(body),
+            form: None,
             path: path.to_string(),
             queries: None,
+            headers: Vec::new(),
             method: HttpMethod::POST,
         }
     }
@@ -72,12 +106,26 @@ impl PortainerRequestRaw {
         let body = serde_json::to_value(body).expect("Invalid body!");
         PortainerRequestRaw {
             body: Some(body),
+            form: None,
             path: path.to_string(),
             queries: None,
+            headers: Vec::new(),
             method: HttpMethod::PUT,
         }
     }
 
+    /// Post request with a multipart form body, some portainer endpoints don't accept json
+    pub fn post_form(path: &str, fields: Vec<(String, String)>) -> PortainerRequestRaw {
+        PortainerRequestRaw {
+            body: None,
+            form: Some(fields),
+            path: path.to_string(),
+            queries: None,
+            headers: Vec::new(),
+            method: HttpMethod::POST,
+        }
+    }
+
     pub fn with_query(mut self, key: &str, value: &str) -> Self {
         let tpl = (key.to_string(), value.to_string());
         if let Some(qs) = &mut self.queries {
@@ -87,6 +135,16 @@ impl PortainerRequestRaw {
         }
         self
     }
+    pub fn with_header(mut self, key: &str, value: &str) -> Self {
+        self.headers.push((key.to_string(), value.to_string()));
+        self
+    }
+    pub fn with_header_opt(self, key: &str, value: Option<&str>) -> Self {
+        match value {
+            Some(value) => self.with_header(key, value),
+            None => self,
+        }
+    }
     pub fn with_query_opt<T: Display>(self, key: &str, value: Option<T>) -> Self {
         if let Some(v) = value {
             self.with_query(key, &format!("{}", v))
@@ -111,14 +169,34 @@ impl PortainerRequestRaw {
     pub fn send(&self, client: &dyn PortainerClient) -> Res<Json> {
         client.send(self)
     }
+    pub fn stream(&self, client: &dyn PortainerClient) -> Res<Box<dyn Read + Send>> {
+        client.stream(self)
+    }
 }
 
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Clone, Copy)]
 pub enum HttpMethod {
     GET,
     POST,
     PUT,
+    PATCH,
     DELETE,
 }
+impl std::str::FromStr for HttpMethod {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_uppercase().as_str() {
+            "GET" => Ok(HttpMethod::GET),
+            "POST" => Ok(HttpMethod::POST),
+            "PUT" => Ok(HttpMethod::PUT),
+            "PATCH" => Ok(HttpMethod::PATCH),
+            "DELETE" => Ok(HttpMethod::DELETE),
+            other => Err(format!("Unsupported http method '{}'", other)),
+        }
+    }
+}
 
 #[derive(Clone)]
 pub enum Credential {
@@ -127,21 +205,67 @@ pub enum Credential {
     JwtToken(String),
 }
 
-use reqwest::blocking::Client as HttpClient;
+use std::net::TcpStream;
+use std::time::Duration;
 
-use super::Res;
+use reqwest::blocking::multipart::Form;
+use reqwest::blocking::{Client as HttpClient, RequestBuilder};
+use tungstenite::client::IntoClientRequest;
+use tungstenite::stream::MaybeTlsStream;
+use tungstenite::{Message, WebSocket};
+
+use super::{Action, Res};
+
+#[cfg(test)]
+impl<O: DeserializeOwned> PortainerRequest<O> {
+    pub fn raw(&self) -> &PortainerRequestRaw {
+        &self.0
+    }
+}
+#[cfg(test)]
+impl PortainerRequestRaw {
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+    pub fn query(&self, key: &str) -> Option<&str> {
+        self.queries
+            .iter()
+            .flatten()
+            .find(|(k, _)| k == key)
+            .map(|(_, value)| value.as_str())
+    }
+    pub fn header(&self, key: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, value)| value.as_str())
+    }
+    pub fn field(&self, key: &str) -> Option<&str> {
+        self.form
+            .iter()
+            .flatten()
+            .find(|(k, _)| k == key)
+            .map(|(_, value)| value.as_str())
+    }
+}
 
 pub struct DefaultClient {
     credential: Credential,
     client: HttpClient,
+    streaming: HttpClient,
     server: String,
 }
 impl DefaultClient {
     pub fn new(credential: Credential, server: &str) -> DefaultClient {
         let client = HttpClient::new();
+        let streaming = HttpClient::builder()
+            .timeout(None)
+            .build()
+            .expect("Cannot build http client!");
         DefaultClient {
             credential,
             client,
+            streaming,
             server: String::from(server),
         }
     }
@@ -150,30 +274,139 @@ impl DefaultClient {
         base.push_str(path);
         base
     }
-}
 
-impl PortainerClient for DefaultClient {
-    fn send(&self, req: &PortainerRequestRaw) -> Res<Json> {
+    fn prepare(&self, client: &HttpClient, req: &PortainerRequestRaw) -> RequestBuilder {
         let url = self.url_for(&req.path);
-        let client = &self.client;
         let preq = match req.method {
             HttpMethod::GET => client.get(url),
             HttpMethod::POST => client.post(url),
             HttpMethod::PUT => client.put(url),
+            HttpMethod::PATCH => client.patch(url),
             HttpMethod::DELETE => client.delete(url),
         };
+        let preq = match &req.queries {
+            Some(queries) => preq.query(queries),
+            None => preq,
+        };
         let preq = match &req.body {
             Some(value) => preq.json(&value),
             None => preq,
         };
+        let preq = match &req.form {
+            Some(fields) => {
+                preq.multipart(fields.iter().fold(Form::new(), |form, (key, value)| {
+                    form.text(key.to_string(), value.to_string())
+                }))
+            }
+            None => preq,
+        };
+        let preq = req
+            .headers
+            .iter()
+            .fold(preq, |preq, (key, value)| preq.header(key, value));
 
-        let authorized = match &self.credential {
+        match &self.credential {
             Credential::Public => preq,
             Credential::APIToken(value) => preq.header("x-api-key", value),
             Credential::JwtToken(value) => preq.header("Authorization", format!("Bearer {value}")),
+        }
+    }
+}
+
+impl PortainerClient for DefaultClient {
+    fn send(&self, req: &PortainerRequestRaw) -> Res<Json> {
+        let resp = self
+            .prepare(&self.client, req)
+            .send()
+            .map_err(|x| x.to_string())?;
+        let status = resp.status();
+        let body = resp.text().map_err(|x| x.to_string())?;
+        if !status.is_success() {
+            Err(format!("{}: {}", status, body))
+        } else if body.trim().is_empty() {
+            Ok(Json::Null)
+        } else {
+            serde_json::from_str(&body).map_err(|x| x.to_string())
+        }
+    }
+
+    fn stream(&self, req: &PortainerRequestRaw) -> Res<Box<dyn Read + Send>> {
+        let resp = self
+            .prepare(&self.streaming, req)
+            .send()
+            .map_err(|x| x.to_string())?;
+        if resp.status().is_success() {
+            Ok(Box::new(resp))
+        } else {
+            let status = resp.status();
+            Err(format!("{}: {}", status, resp.text().unwrap_or_default()))
+        }
+    }
+
+    fn connect(&self, req: &PortainerRequestRaw) -> Res<Box<dyn Duplex>> {
+        let url = self
+            .url_for(&req.path)
+            .replacen("http", "ws", 1)
+            .to_string();
+        let mut queries = req.queries.clone().unwrap_or_default();
+        if let Credential::JwtToken(value) = &self.credential {
+            queries.push(("token".to_string(), value.to_string()));
+        }
+        let url = reqwest::Url::parse_with_params(&url, queries).map_err(|x| x.to_string())?;
+
+        let mut request = url
+            .as_str()
+            .into_client_request()
+            .map_err(|x| x.to_string())?;
+        if let Credential::APIToken(value) = &self.credential {
+            let value = value
+                .parse()
+                .map_err(|_| "Invalid API token!".to_string())?;
+            request.headers_mut().insert("x-api-key", value);
+        }
+
+        let (mut socket, _) = tungstenite::connect(request).map_err(|x| x.to_string())?;
+        let stream = match socket.get_mut() {
+            MaybeTlsStream::Plain(stream) => stream,
+            MaybeTlsStream::NativeTls(stream) => stream.get_mut(),
+            _ => return Err("Unsupported websocket stream!".to_string()),
         };
-        let resp = authorized.send().map_err(|x| x.to_string())?;
-        resp.json().map_err(|x| x.to_string())
+        stream
+            .set_read_timeout(Some(SOCKET_POLL))
+            .map_err(|x| x.to_string())?;
+
+        Ok(Box::new(WebSocketDuplex(socket)))
+    }
+}
+
+const SOCKET_POLL: Duration = Duration::from_millis(50);
+
+struct WebSocketDuplex(WebSocket<MaybeTlsStream<TcpStream>>);
+impl Duplex for WebSocketDuplex {
+    fn receive(&mut self) -> Res<Received> {
+        use tungstenite::Error;
+        match self.0.read() {
+            Ok(Message::Binary(data)) => Ok(Received::Data(data)),
+            Ok(Message::Text(text)) => Ok(Received::Data(text.into_bytes())),
+            Ok(Message::Close(_)) => Ok(Received::Closed),
+            Ok(_) => Ok(Received::Idle),
+            Err(Error::ConnectionClosed | Error::AlreadyClosed) => Ok(Received::Closed),
+            Err(Error::Io(err))
+                if matches!(
+                    err.kind(),
+                    std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+                ) =>
+            {
+                Ok(Received::Idle)
+            }
+            Err(err) => Err(err.to_string()),
+        }
+    }
+
+    fn send(&mut self, data: &[u8]) -> Action {
+        self.0
+            .send(Message::Binary(data.to_vec()))
+            .map_err(|x| x.to_string())
     }
 }
 
@@ -187,3 +420,55 @@ impl ClientFactory for DefaultClientFactory {
 pub trait ClientFactory {
     fn build(&self, credential: Credential, server: &str) -> Box<dyn PortainerClient>;
 }
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+    use std::thread;
+    use std::time::Instant;
+
+    use super::*;
+
+    /// Skips idle polls, the socket read timeout is short
+    fn next(channel: &mut dyn Duplex) -> Received {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while Instant::now() < deadline {
+            match channel.receive().unwrap() {
+                Received::Idle => continue,
+                received => return received,
+            }
+        }
+        panic!("Nothing received");
+    }
+
+    #[test]
+    fn websocket_duplex_exchanges_messages_until_closed() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut socket = tungstenite::accept(stream).unwrap();
+            socket.send(Message::Binary(b"hello".to_vec())).unwrap();
+            let received = socket.read().unwrap();
+            socket.send(Message::Text("echo".to_string())).unwrap();
+            socket.close(None).unwrap();
+            while socket.read().is_ok() {}
+            received
+        });
+
+        let client = DefaultClient::new(
+            Credential::APIToken("token".to_string()),
+            &format!("http://127.0.0.1:{}/api", port),
+        );
+        let mut channel = client
+            .connect(&PortainerRequestRaw::get("/websocket/exec").with_query("id", "abc"))
+            .unwrap();
+
+        assert!(matches!(next(channel.as_mut()), Received::Data(data) if data == b"hello"));
+        channel.send(b"input").unwrap();
+        assert!(matches!(next(channel.as_mut()), Received::Data(data) if data == b"echo"));
+        assert!(matches!(next(channel.as_mut()), Received::Closed));
+        drop(channel);
+        assert_eq!(server.join().unwrap(), Message::Binary(b"input".to_vec()));
+    }
+}
@@ -1,7 +1,32 @@
-use clap::{arg, Command, Arg};
+use clap::{arg, value_parser, Arg, ArgAction, Command};
 use clap::{ArgGroup, ArgMatches};
 
-use std::path::Path;
+use serde_json::Value as Json;
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use super::client::HttpMethod;
+use super::compose::ComposeFile;
+use super::decrypt::Keyring;
+use super::env::{self, EnvSource};
+use super::logs;
+use super::output::OutputFormat;
+use super::preview::{self, PreviewSpec};
+use super::project::{Project, ProjectEndpoint, PROJECT_FILE};
+use super::prompt;
+use super::protect::Protection;
+use super::query::Selector;
+
+pub struct GlobalOptions {
+    pub output: OutputFormat,
+    pub age_identity: Option<PathBuf>,
+    pub credential_helper: Option<String>,
+}
 
 pub enum CLICommand {
     Deploy {
@@ -9,18 +34,216 @@ pub enum CLICommand {
         compose: String,
         stack: String,
         endpoint: EndpointSelector,
-        confirmed: bool,
+        confirmed: Confirmation,
+        inline_vars: Vec<InlineEnv>,
+        configs: Vec<FileMapping>,
+        secrets: Vec<FileMapping>,
+        registries: Vec<String>,
+        create_externals: bool,
+    },
+    Apply {
+        server: ManifestServers,
+        manifest: PathBuf,
+        confirmed: Confirmation,
+        create_externals: bool,
+    },
+    Diff {
+        server: ManifestServers,
+        manifest: PathBuf,
+    },
+    Drift {
+        server: ServerConfig,
+        compose: String,
+        stack: String,
+        endpoint: EndpointSelector,
         inline_vars: Vec<InlineEnv>,
         configs: Vec<FileMapping>,
         secrets: Vec<FileMapping>,
     },
+    DriftManifest {
+        server: ManifestServers,
+        manifest: PathBuf,
+    },
+    Reconcile {
+        server: ManifestServers,
+        repo_dir: PathBuf,
+        manifest: PathBuf,
+        interval: Duration,
+        pull: bool,
+        allow_destructive: bool,
+        listen: Option<String>,
+        once: bool,
+    },
+    DestroyManifest {
+        server: ManifestServers,
+        manifest: PathBuf,
+        confirmed: Confirmation,
+    },
     Destroy {
         server: ServerConfig,
-        stacks: Vec<String>,
         endpoint: EndpointSelector,
-        confirmed: bool,
-        configs: Vec<String>,
-        secrets: Vec<String>,
+        confirmed: Confirmation,
+        selection: DestroySelection,
+    },
+    PreviewUp {
+        server: ServerConfig,
+        compose: String,
+        stack: String,
+        endpoint: EndpointSelector,
+        confirmed: Confirmation,
+        inline_vars: Vec<InlineEnv>,
+        configs: Vec<FileMapping>,
+        secrets: Vec<FileMapping>,
+        registries: Vec<String>,
+        labels: Vec<(String, String)>,
+        create_externals: bool,
+    },
+    PreviewGc {
+        server: ServerConfig,
+        endpoint: EndpointSelector,
+        confirmed: Confirmation,
+        open: Option<Vec<String>>,
+    },
+    Start {
+        server: ServerConfig,
+        stacks: StackSelector,
+        endpoint: EndpointSelector,
+        confirmed: Confirmation,
+        wait: Option<Duration>,
+    },
+    Stop {
+        server: ServerConfig,
+        stacks: StackSelector,
+        endpoint: EndpointSelector,
+        confirmed: Confirmation,
+        wait: Option<Duration>,
+    },
+    ListStacks {
+        server: ServerConfig,
+        endpoint: EndpointSelector,
+    },
+    ListEndpoints {
+        server: ServerConfig,
+        endpoint: Option<EndpointSelector>,
+    },
+    InspectEndpoint {
+        server: ServerConfig,
+        endpoint: EndpointSelector,
+    },
+    CreateEndpoint {
+        server: ServerConfig,
+        name: String,
+        kind: EndpointKind,
+        settings: EndpointSettings,
+    },
+    UpdateEndpoint {
+        server: ServerConfig,
+        endpoint: EndpointSelector,
+        name: Option<String>,
+        settings: EndpointSettings,
+    },
+    RemoveEndpoint {
+        server: ServerConfig,
+        endpoint: EndpointSelector,
+        confirmed: Confirmation,
+    },
+    TagEndpoint {
+        server: ServerConfig,
+        endpoint: EndpointSelector,
+        tags: Vec<String>,
+        add: bool,
+    },
+    ListVolumes {
+        server: ServerConfig,
+        endpoint: EndpointSelector,
+    },
+    InspectVolume {
+        server: ServerConfig,
+        endpoint: EndpointSelector,
+        name: String,
+    },
+    CreateVolume {
+        server: ServerConfig,
+        endpoint: EndpointSelector,
+        volume: VolumeSpec,
+    },
+    ListNetworks {
+        server: ServerConfig,
+        endpoint: EndpointSelector,
+    },
+    CreateNetwork {
+        server: ServerConfig,
+        endpoint: EndpointSelector,
+        network: NetworkSpec,
+    },
+    ListTags {
+        server: ServerConfig,
+    },
+    CreateTags {
+        server: ServerConfig,
+        names: Vec<String>,
+    },
+    RemoveTags {
+        server: ServerConfig,
+        names: Vec<String>,
+    },
+    ListRegistries {
+        server: ServerConfig,
+    },
+    AddRegistry {
+        server: ServerConfig,
+        name: String,
+        kind: RegistryKind,
+        url: Option<String>,
+        credential: Option<RegistryCredential>,
+    },
+    UpdateRegistry {
+        server: ServerConfig,
+        name: String,
+        rename: Option<String>,
+        url: Option<String>,
+        credential: Option<RegistryCredential>,
+    },
+    RemoveRegistries {
+        server: ServerConfig,
+        names: Vec<String>,
+    },
+    ListGroups {
+        server: ServerConfig,
+    },
+    CreateGroup {
+        server: ServerConfig,
+        name: String,
+        description: String,
+        tags: Vec<String>,
+    },
+    RemoveGroups {
+        server: ServerConfig,
+        names: Vec<String>,
+    },
+    AddToGroup {
+        server: ServerConfig,
+        group: String,
+        endpoint: EndpointSelector,
+    },
+    Logs {
+        server: ServerConfig,
+        stack: String,
+        service: Option<String>,
+        endpoint: EndpointSelector,
+        options: LogOptions,
+    },
+    Exec {
+        server: ServerConfig,
+        stack: String,
+        service: String,
+        endpoint: EndpointSelector,
+        command: Vec<String>,
+        tty: bool,
+    },
+    Api {
+        server: ServerConfig,
+        call: ApiCall,
     },
     Login {
         server: String,
@@ -28,23 +251,179 @@ pub enum CLICommand {
         credential: LoginCredential,
     },
     Logout(String),
+    EncryptSessions {
+        recipients: Vec<String>,
+    },
+    DecryptSessions,
+    UnlockSessions,
+    LockSessions,
+    ProtectSession {
+        name: String,
+        protection: Protection,
+    },
 }
 
+#[derive(Clone, Debug, PartialEq)]
 pub struct InlineEnv(pub String, pub String);
+#[derive(Clone)]
 pub struct FileMapping(pub String, pub Box<Path>);
+#[derive(Clone)]
+pub struct Label(pub String, pub String);
 
+#[allow(clippy::enum_variant_names)]
 pub enum EndpointSelector {
+    /// Exact name match, unlike portainer's `name` query which searches
     ByName(String),
     ById(i32),
-    ByTags(Vec<String>),
-    ByTagIds(Vec<i32>),
+    ByNameGlob(glob::Pattern),
+    ByNameRegex(regex::Regex),
+    /// Endpoints having all of the tags, or any of them when `any` is set
+    ByTags {
+        tags: Vec<String>,
+        any: bool,
+    },
+    ByTagIds {
+        ids: Vec<i32>,
+        any: bool,
+    },
+    /// Endpoints in the endpoint group
+    ByGroup(String),
+    ByGroupId(i32),
+}
+
+pub enum StackSelector {
+    ByNames(Vec<String>),
+    ByLabels(Vec<Label>),
+}
+
+/// Resources to destroy, everything matching any of the criteria is selected. Labels
+/// select stacks by service labels and other resources by their docker labels, orphans
+/// are configs and secrets no remaining service uses.
+#[derive(Default)]
+pub struct DestroySelection {
+    pub stacks: Vec<glob::Pattern>,
+    pub configs: Vec<glob::Pattern>,
+    pub secrets: Vec<glob::Pattern>,
+    pub volumes: Vec<glob::Pattern>,
+    pub networks: Vec<glob::Pattern>,
+    pub labels: Vec<Label>,
+    pub orphans: bool,
+}
+impl DestroySelection {
+    /// Selects exactly the named stacks, configs and secrets
+    pub fn exact(stacks: &[String], configs: &[String], secrets: &[String]) -> DestroySelection {
+        DestroySelection {
+            stacks: exact(stacks),
+            configs: exact(configs),
+            secrets: exact(secrets),
+            ..Default::default()
+        }
+    }
+}
+
+/// Patterns matching exactly the names
+fn exact(names: &[String]) -> Vec<glob::Pattern> {
+    names
+        .iter()
+        .filter_map(|name| glob::Pattern::new(&glob::Pattern::escape(name)).ok())
+        .collect()
+}
+
+#[derive(Clone, Copy)]
+pub enum EndpointKind {
+    Docker,
+    Agent,
+    Edge,
+    Kubernetes,
+}
+
+pub enum RegistryKind {
+    DockerHub,
+    Gitlab { instance_url: String },
+    Ecr { region: String },
+    Custom,
+}
+
+/// Registry login, the password is prompted when missing
+pub struct RegistryCredential {
+    pub username: String,
+    pub password: Option<String>,
+}
+
+pub struct EndpointSettings {
+    pub url: Option<String>,
+    pub public_url: Option<String>,
+    pub group: Option<i32>,
+    /// Tag names, replaces existing tags on update
+    pub tags: Option<Vec<String>>,
+    /// Agents skip verification unless told otherwise, other endpoints only use TLS when set
+    pub tls_skip_verify: Option<bool>,
+}
+
+pub struct VolumeSpec {
+    pub name: String,
+    /// Docker's default driver when missing
+    pub driver: Option<String>,
+    /// Driver options
+    pub options: HashMap<String, String>,
+    pub labels: HashMap<String, String>,
+}
+
+pub struct NetworkSpec {
+    pub name: String,
+    pub driver: String,
+    /// Whether standalone containers may attach to a swarm network
+    pub attachable: bool,
+    /// Whether the network has no external access
+    pub internal: bool,
+    pub labels: HashMap<String, String>,
+}
+
+pub struct LogOptions {
+    pub follow: bool,
+    /// Seconds since the unix epoch
+    pub since: Option<u64>,
+    pub tail: Option<String>,
+    pub timestamps: bool,
+}
+
+pub struct ApiCall {
+    pub method: HttpMethod,
+    pub path: String,
+    pub queries: Vec<InlineEnv>,
+    pub body: Option<Json>,
+    pub select: Option<Selector>,
+    pub raw: bool,
+    /// Fetches all pages using `start` and `limit` queries when set
+    pub page_size: Option<u32>,
+}
+
+/// Answer to plan confirmations, `-Y` does not apply to protected endpoints and stacks
+/// unless protection is forced
+#[derive(Clone, Copy)]
+pub struct Confirmation {
+    pub assumed: bool,
+    pub force_protected: bool,
 }
 
 pub enum LoginCredential {
-    ByUserPass { username: String, password: String },
+    ByUserPass {
+        username: String,
+        password: Option<String>,
+    },
     ByAPIToken(String),
 }
 
+/// Servers of commands working on manifests
+#[derive(Clone)]
+pub struct ManifestServers {
+    /// Given by flags, overrides the sessions of all stacks
+    pub fixed: Option<ServerConfig>,
+    /// From the environment or the project file, used by stacks without a session
+    pub fallback: Option<ServerConfig>,
+}
+
+#[derive(Clone)]
 pub enum ServerConfig {
     InlineToken {
         address: String,
@@ -60,106 +439,1552 @@ pub enum ServerConfig {
 
 type ParseResult<T> = Result<T, String>;
 
+fn parse_file_mapping(value: &str) -> ParseResult<FileMapping> {
+    let (name, file) = value
+        .split_once(':')
+        .ok_or_else(|| format!("Invalid file mapping '{}', format is `name:file`", value))?;
+    Ok(FileMapping(name.to_string(), Box::from(Path::new(file))))
+}
+
+fn parse_inline_env(value: &str) -> ParseResult<InlineEnv> {
+    let (key, value) = value
+        .split_once('=')
+        .ok_or_else(|| format!("Invalid variable '{}', format is `KEY=VALUE`", value))?;
+    Ok(InlineEnv(key.to_string(), value.to_string()))
+}
+
+fn parse_label(value: &str) -> ParseResult<Label> {
+    let (key, value) = value
+        .split_once('=')
+        .ok_or_else(|| format!("Invalid label '{}', format is `key=value`", value))?;
+    Ok(Label(key.to_string(), value.to_string()))
+}
+
+fn parse_option(value: &str) -> ParseResult<(String, String)> {
+    let (key, value) = value
+        .split_once('=')
+        .ok_or_else(|| format!("Invalid option '{}', format is `key=value`", value))?;
+    Ok((key.to_string(), value.to_string()))
+}
+
+fn many<T: Clone + Send + Sync + 'static>(matches: &ArgMatches, id: &str) -> Vec<T> {
+    matches
+        .get_many::<T>(id)
+        .map(|values| values.cloned().collect())
+        .unwrap_or_default()
+}
+
+/// Session used when no server is given by flags, environment or project file
+pub const DEFAULT_SESSION: &str = "default";
+
+/// Project file of the working directory, read once
+fn project() -> ParseResult<&'static Project> {
+    static PROJECT: OnceLock<ParseResult<Project>> = OnceLock::new();
+    PROJECT
+        .get_or_init(|| {
+            let dir = std::env::current_dir().map_err(|err| err.to_string())?;
+            Project::discover(&dir)
+        })
+        .as_ref()
+        .map_err(|err| err.to_string())
+}
+
+/// Server settings of one source: flags, the environment or the project file
+#[derive(Default)]
+struct ServerSettings {
+    session: Option<String>,
+    address: Option<String>,
+    token: Option<String>,
+    username: Option<String>,
+}
+impl ServerSettings {
+    fn flags(matches: &ArgMatches) -> ServerSettings {
+        let flag = |id: &str| matches.get_one::<String>(id).cloned();
+        ServerSettings {
+            session: flag("session"),
+            address: flag("address"),
+            token: flag("token"),
+            username: flag("username"),
+        }
+    }
+
+    fn env() -> ServerSettings {
+        let var = |name: &str| std::env::var(name).ok();
+        ServerSettings {
+            session: var("PCTL_SESSION"),
+            address: var("PORTAINER_URL"),
+            token: var("PORTAINER_TOKEN"),
+            username: var("PORTAINER_USERNAME"),
+        }
+    }
+
+    fn project() -> ParseResult<ServerSettings> {
+        let project = project()?;
+        Ok(ServerSettings {
+            session: project.session.clone(),
+            address: project.url.clone(),
+            token: None,
+            username: project.username.clone(),
+        })
+    }
+
+    fn has_server(&self) -> bool {
+        self.session.is_some() || self.address.is_some()
+    }
+
+    fn has_credentials(&self) -> bool {
+        self.token.is_some() || self.username.is_some()
+    }
+}
+
+/// Resolves the server from flags and the environment, reading the project file only
+/// when they leave the server or its credentials unset, so an invalid project file does
+/// not break commands that don't need it
+fn server_config_lookup(
+    flags: ServerSettings,
+    env: ServerSettings,
+    project: impl FnOnce() -> ParseResult<ServerSettings>,
+    password: Option<String>,
+) -> ParseResult<Option<ServerConfig>> {
+    let sources = [flags, env];
+    let complete = match sources.iter().find(|s| s.has_server()) {
+        Some(source) => source.session.is_some() || sources.iter().any(|s| s.has_credentials()),
+        None => false,
+    };
+    if complete {
+        return server_config_resolve(&sources, password);
+    }
+    let [flags, env] = sources;
+    server_config_resolve(&[flags, env, project()?], password)
+}
+
+/// Server of the first source naming a session or an address. Credentials of an address
+/// come from the first source having a token or a username, so `--token` combines with
+/// `PORTAINER_URL`. Sources are given in precedence order, flags first.
+///
+/// A token or username given by flags is never dropped for a session of another source,
+/// it uses the address of that source or fails.
+fn server_config_resolve(
+    sources: &[ServerSettings],
+    password: Option<String>,
+) -> ParseResult<Option<ServerConfig>> {
+    let credentials = sources.iter().find(|s| s.has_credentials());
+    let flag_credentials = sources.first().is_some_and(ServerSettings::has_credentials);
+    let Some(source) = sources.iter().find(|s| s.has_server()) else {
+        if flag_credentials {
+            return Err(
+                "A token or username needs a server address, pass --address or set PORTAINER_URL"
+                    .to_string(),
+            );
+        }
+        return Ok(None);
+    };
+    if let Some(session) = &source.session {
+        if !flag_credentials {
+            return Ok(Some(ServerConfig::Session(session.to_string())));
+        }
+        if source.address.is_none() {
+            return Err(format!(
+                "A token or username cannot be used with session '{}', pass --address too or unset the session",
+                session
+            ));
+        }
+    }
+    let address = source.address.clone().unwrap_or_default();
+    match credentials {
+        Some(ServerSettings {
+            token: Some(token), ..
+        }) => Ok(Some(ServerConfig::InlineToken {
+            address,
+            token: token.to_string(),
+        })),
+        Some(ServerSettings {
+            username: Some(username),
+            ..
+        }) => Ok(Some(ServerConfig::InlineLogin {
+            address,
+            username: username.to_string(),
+            password,
+        })),
+        _ => Err(format!(
+            "Server '{}' needs a token or a username, pass --token or --username, or set PORTAINER_TOKEN or PORTAINER_USERNAME",
+            address
+        )),
+    }
+}
+
+/// Password given by `--password`, `--password-stdin` or `--password-file`
+fn password_parse(matches: &ArgMatches) -> ParseResult<Option<String>> {
+    if let Some(password) = matches.get_one::<String>("password") {
+        Ok(Some(password.to_string()))
+    } else if matches.get_flag("password-stdin") {
+        prompt::password_stdin().map(Some)
+    } else if let Some(path) = matches.get_one::<PathBuf>("password-file") {
+        prompt::password_file(path).map(Some)
+    } else {
+        Ok(None)
+    }
+}
+
+/// Password flags, a password that is not given is prompted on a terminal
+fn password_args(cmd: Command) -> Command {
+    cmd.arg(arg!(-p --password <password> "password for login"))
+        .arg(arg!(--"password-stdin" "reads the password from stdin").conflicts_with("password"))
+        .arg(
+            arg!(--"password-file" <FILE> "reads the password from a file")
+                .value_parser(value_parser!(PathBuf))
+                .conflicts_with_all(["password", "password-stdin"]),
+        )
+}
+
+/// `--password`, `--password-stdin` or `--password-file`, then `PORTAINER_PASSWORD`
+fn server_password_parse(matches: &ArgMatches) -> ParseResult<Option<String>> {
+    Ok(match password_parse(matches)? {
+        Some(password) => Some(password),
+        None => std::env::var("PORTAINER_PASSWORD").ok(),
+    })
+}
+
+/// Server from flags, then `PCTL_SESSION` or `PORTAINER_URL` with `PORTAINER_TOKEN` or
+/// `PORTAINER_USERNAME`, then the project file, falling back to the default session
 fn server_config_parse(matches: &ArgMatches) -> ParseResult<ServerConfig> {
-    if let Some(session) = matches.get_one::<String>("session") {
-        Ok(ServerConfig::Session(session.to_string()))
+    let server = server_config_lookup(
+        ServerSettings::flags(matches),
+        ServerSettings::env(),
+        ServerSettings::project,
+        server_password_parse(matches)?,
+    )?;
+    Ok(server.unwrap_or_else(|| ServerConfig::Session(DEFAULT_SESSION.to_string())))
+}
+
+/// Servers of manifest commands. Only `--session` or `--address` override sessions of
+/// stacks, the environment and the project file only serve stacks without a session.
+fn manifest_servers_parse(matches: &ArgMatches) -> ParseResult<ManifestServers> {
+    let flags = ServerSettings::flags(matches);
+    let fixed = flags.has_server();
+    let server = server_config_lookup(
+        flags,
+        ServerSettings::env(),
+        ServerSettings::project,
+        server_password_parse(matches)?,
+    )?;
+    Ok(if fixed {
+        ManifestServers {
+            fixed: server,
+            fallback: None,
+        }
     } else {
-        let address = matches.get_one::<String>("address");
-        let token = matches.get_one::<String>("token");
-
-        let username = matches.get_one::<String>("username");
-        token
-            .zip(address)
-            .map(|(token, address)| ServerConfig::InlineToken {
-                address: address.to_string(),
-                token: token.to_string(),
-            })
-            .or_else(|| {
-                address
-                    .zip(username)
-                    .map(|(address, username)| -> ServerConfig {
-                        ServerConfig::InlineLogin {
-                            address: address.to_string(),
-                            username: username.to_string(),
-                            password: matches.get_one::<String>("password").cloned(),
-                        }
-                    })
-            })
-            .ok_or_else(|| "You must enter either session or address and token".to_string())
+        ManifestServers {
+            fixed: None,
+            fallback: server,
+        }
+    })
+}
+
+fn endpoint_parse_opt(matches: &ArgMatches) -> Option<EndpointSelector> {
+    if let Some(name) = matches.get_one::<String>("name") {
+        Some(EndpointSelector::ByName(name.to_string()))
+    } else if let Some(id) = matches.get_one::<i32>("id") {
+        Some(EndpointSelector::ById(*id))
+    } else if let Some(pattern) = matches.get_one::<glob::Pattern>("name-glob") {
+        Some(EndpointSelector::ByNameGlob(pattern.clone()))
+    } else if let Some(regex) = matches.get_one::<regex::Regex>("name-regex") {
+        Some(EndpointSelector::ByNameRegex(regex.clone()))
+    } else if let Some(group) = matches.get_one::<String>("in-group") {
+        Some(EndpointSelector::ByGroup(group.to_string()))
+    } else if let Some(id) = matches.get_one::<i32>("in-group-id") {
+        Some(EndpointSelector::ByGroupId(*id))
+    } else if matches.contains_id("tag") {
+        Some(EndpointSelector::ByTags {
+            tags: many(matches, "tag"),
+            any: matches.get_flag("any-tag"),
+        })
+    } else if matches.contains_id("tagid") {
+        Some(EndpointSelector::ByTagIds {
+            ids: many(matches, "tagid"),
+            any: matches.get_flag("any-tag"),
+        })
+    } else {
+        None
+    }
+}
+
+/// Endpoint from flags, then `PCTL_ENDPOINT`, a name or a numeric id, then the project file
+fn endpoint_parse(matches: &ArgMatches) -> ParseResult<EndpointSelector> {
+    if let Some(endpoint) = endpoint_parse_opt(matches) {
+        return Ok(endpoint);
+    }
+    if let Ok(endpoint) = std::env::var("PCTL_ENDPOINT") {
+        return Ok(match endpoint.parse::<i32>() {
+            Ok(id) => EndpointSelector::ById(id),
+            Err(_) => EndpointSelector::ByName(endpoint),
+        });
     }
+    match &project()?.endpoint {
+        Some(ProjectEndpoint::Id(id)) => Ok(EndpointSelector::ById(*id)),
+        Some(ProjectEndpoint::Name(name)) => Ok(EndpointSelector::ByName(name.to_string())),
+        None => Err(format!(
+            "You must select an endpoint, or set PCTL_ENDPOINT or `endpoint` in {}",
+            PROJECT_FILE
+        )),
+    }
+}
+
+fn stack_selector_parse(matches: &ArgMatches) -> ParseResult<StackSelector> {
+    let names: Vec<String> = many(matches, "STACK");
+    let labels: Vec<Label> = many(matches, "selector");
+    if !names.is_empty() {
+        Ok(StackSelector::ByNames(names))
+    } else if !labels.is_empty() {
+        Ok(StackSelector::ByLabels(labels))
+    } else {
+        Err("You must select stacks either by name or by label".to_string())
+    }
+}
+
+/// Compose file, env and files of a stack, shared by commands that work on local definitions
+fn stack_definition_args(cmd: Command) -> Command {
+    cmd.arg(arg!(-f --compose <FILE> "compose file to deploy"))
+        .arg(arg!(-s --stack <NAME> "stack name"))
+        .arg(arg!(--config <FILEMAPPING> "docker config to create, format `name:source`, the source is a file or a `vault://`, `env:` or `exec:` reference").action(ArgAction::Append).value_parser(parse_file_mapping))
+        .arg(arg!(--secret <FILEMAPPING> "docker secret to create, format `name:source`, the source is a file or a `vault://`, `env:` or `exec:` reference").action(ArgAction::Append).value_parser(parse_file_mapping))
+        .arg(arg!(-e --env <ENVVAR> "environment variables to add to stack, format `KEY=VALUE`, these take precedence over all other sources").action(ArgAction::Append).value_parser(parse_inline_env))
+        .arg(arg!(--envfile <FILE> "dotenv file to add to stack, repeatable, later files take precedence over earlier ones").action(ArgAction::Append).value_parser(value_parser!(PathBuf)))
+        .arg(arg!(--"env-from-prefix" <PREFIX> "adds process environment variables starting with the prefix, these take precedence over envfiles").action(ArgAction::Append))
+        .arg(arg!(--"strict-env" "fails when sources set a variable to different values, instead of using the one taking precedence"))
+}
+
+fn create_externals_arg() -> Arg {
+    arg!(--"create-externals" "creates `external` volumes and networks of compose files missing on the endpoint, instead of failing")
 }
 
 fn deploy_command() -> Command {
     app_args(
-        Command::new("deploy").about("deploys stack and its dependencies")
-        .arg(arg!(-f --compose <FILE> "compose file to deploy").required(true))
-        .arg(arg!(--config <FILEMAPPING> "file mapping to be created as docker config, format `name:file`"))
-        .arg(arg!(--secret <FILEMAPPING> "file mapping to be created as docker secret, format `name:file`"))
-        .arg(arg!(-e --env <ENVVAR> "environment variables to add to stack, format `KEY=VALUE`, these take precedence over envfile"))
-        .arg(arg!(--envfile <FILE> "dotenv file to add to stack, values are merged with other inline vars"))
-        .arg(arg!(-Y --confirm "confirms automatically and do not ask for prompts"))
+        stack_definition_args(Command::new("deploy").about("deploys stack and its dependencies"))
+        .mut_arg("compose", |arg| arg.required(true))
+        .mut_arg("stack", |arg| arg.required(true))
+        .arg(arg!(--registry <NAME> "registry whose credentials are used to pull images, granting the endpoint access when missing").action(ArgAction::Append))
+        .arg(create_externals_arg())
+        .args(confirm_args())
     )
 }
 
+fn drift_command() -> Command {
+    server_config_args(endpoint_selector_args(
+        stack_definition_args(
+            Command::new("drift")
+                .about("compares deployed stacks with local definitions, fails when they differ"),
+        )
+        .mut_arg("compose", |arg| arg.required_unless_present("manifest"))
+        .mut_arg("stack", |arg| arg.required_unless_present("manifest"))
+        .arg(
+            arg!(-m --manifest <FILE> "checks all stacks of a manifest")
+                .value_parser(value_parser!(PathBuf))
+                .conflicts_with_all([
+                    "endpoint-selector",
+                    "compose",
+                    "stack",
+                    "config",
+                    "secret",
+                    "env",
+                    "envfile",
+                    "env-from-prefix",
+                    "strict-env",
+                ]),
+        ),
+    ))
+}
+
 fn server_config_args(cmd: Command) -> Command {
+    // an address and its credentials may come from different sources, the merged
+    // result is checked once parsed
     let session = ArgGroup::new("from-session")
         .arg("session")
-        .requires("session")
-        .conflicts_with_all(["from-token", "from-userpass"]);
+        .conflicts_with_all(["address", "from-token", "from-userpass"]);
     let token = ArgGroup::new("from-token")
         .arg("token")
-        .requires_all(["address", "token"])
         .conflicts_with_all(["from-session", "from-userpass"]);
     let userpass = ArgGroup::new("from-userpass")
         .arg("username")
-        .requires_all(["address", "username"])
         .conflicts_with_all(["from-token", "from-session"]);
 
-    cmd.arg(arg!(--token <token> "API token"))
+    password_args(cmd)
+        .arg(arg!(--token <token> "API token"))
         .arg(arg!(-u --username <username> "username to login"))
-        .arg(arg!(-p --password <password> "password for login"))
         .arg(arg!(-H --address <url> "Server address"))
         .arg(arg!(-S --session <name> "Existing session name"))
         .groups([session, token, userpass])
 }
 
-fn endpoint_args(cmd: Command) -> Command {
-    let endpoint = ArgGroup::new("endpoint-selector")
-        .args(["name", "id", "tag", "tagid"])
-        .required(true);
-    let arg= Arg::new("").value_parser(parser);
+fn parse_glob(value: &str) -> ParseResult<glob::Pattern> {
+    glob::Pattern::new(value).map_err(|x| format!("Invalid glob '{}': {}", value, x))
+}
 
-    cmd.arg(arg!(-N --name <ENDPOINT> "endpoint name"))
-        .arg(arg!(-E --id <ENDPOINT_ID> "endpoint id"))
-        .arg(arg!(-t --tag <TAG> "endpoint tag"))
-        .arg(arg!(-T --tagid <TAG_ID> "endpoint tag id"))
+fn parse_regex(value: &str) -> ParseResult<regex::Regex> {
+    regex::Regex::new(value).map_err(|x| format!("Invalid regex '{}': {}", value, x))
+}
+
+/// Endpoint selection flags, optional since listings filter by them and other commands
+/// may select the endpoint from the environment or the project file
+fn endpoint_selector_args(cmd: Command) -> Command {
+    let endpoint = ArgGroup::new("endpoint-selector").args([
+        "name",
+        "id",
+        "name-glob",
+        "name-regex",
+        "tag",
+        "tagid",
+        "in-group",
+        "in-group-id",
+    ]);
+
+    cmd.arg(arg!(-N --name <ENDPOINT> "endpoint name, matched exactly"))
+        .arg(arg!(-E --id <ENDPOINT_ID> "endpoint id").value_parser(value_parser!(i32)))
+        .arg(
+            arg!(--"name-glob" <PATTERN> "endpoint name glob, e.g. `prod-*`")
+                .value_parser(parse_glob),
+        )
+        .arg(
+            arg!(--"name-regex" <REGEX> "endpoint name regular expression, e.g. `^prod-(eu|us)$`")
+                .value_parser(parse_regex),
+        )
+        .arg(
+            arg!(-t --tag <TAG> "endpoint tag, repeat to require all tags")
+                .action(ArgAction::Append),
+        )
+        .arg(
+            arg!(-T --tagid <TAG_ID> "endpoint tag id")
+                .action(ArgAction::Append)
+                .value_parser(value_parser!(i32)),
+        )
+        .arg(
+            arg!(--"any-tag" "selects endpoints having any of the tags, instead of all of them")
+                .conflicts_with_all(["name", "id", "name-glob", "name-regex"]),
+        )
+        .arg(arg!(--"in-group" <GROUP> "endpoint group name"))
+        .arg(arg!(--"in-group-id" <GROUP_ID> "endpoint group id").value_parser(value_parser!(i32)))
         .group(endpoint)
 }
 
+fn stack_selector_args(cmd: Command) -> Command {
+    let stacks = ArgGroup::new("stack-selector")
+        .args(["STACK", "selector"])
+        .required(true);
+
+    cmd.arg(arg!([STACK] ... "stack names"))
+        .arg(
+            arg!(--selector <LABEL> "select stacks having a service label, format `key=value`")
+                .action(ArgAction::Append)
+                .value_parser(parse_label),
+        )
+        .group(stacks)
+}
+
+fn confirm_args() -> [Arg; 2] {
+    [
+        arg!(-Y --confirm "confirms automatically and do not ask for prompts"),
+        arg!(--"force-protected" "lets -Y confirm plans destroying or changing protected endpoints and stacks")
+            .requires("confirm"),
+    ]
+}
+
+fn confirm_parse(matches: &ArgMatches) -> Confirmation {
+    Confirmation {
+        assumed: matches.get_flag("confirm"),
+        force_protected: matches.get_flag("force-protected"),
+    }
+}
+
+fn wait_args(cmd: Command) -> Command {
+    cmd.arg(arg!(--wait "waits for services to reach the desired state"))
+        .arg(
+            arg!(--timeout <SECONDS> "maximum time to wait for services")
+                .value_parser(value_parser!(u64))
+                .default_value("300"),
+        )
+}
+
 fn app_args(cmd: Command) -> Command {
-    server_config_args(endpoint_args(cmd))
+    server_config_args(endpoint_selector_args(cmd))
+}
+
+/// Reads the compose file and env of a stack, envfiles in order, then prefixed process
+/// variables and last inline vars take precedence
+fn stack_definition_parse(matches: &ArgMatches) -> ParseResult<(String, Vec<InlineEnv>)> {
+    let compose = matches.get_one::<String>("compose").unwrap();
+    let compose = fs::read_to_string(compose)
+        .map_err(|err| format!("Cannot read compose file '{}': {}", compose, err))?;
+
+    let keys = Keyring::new(matches.get_one::<PathBuf>("age-identity").cloned());
+    let mut sources = Vec::new();
+    for path in many::<PathBuf>(matches, "envfile") {
+        sources.push(EnvSource::read(&path, &keys)?);
+    }
+    for prefix in many::<String>(matches, "env-from-prefix") {
+        sources.push(EnvSource::from_prefix(&prefix));
+    }
+    sources.push(EnvSource::new("--env", many(matches, "env")));
+    let inline_vars = env::merge(sources, matches.get_flag("strict-env"))?;
+    Ok((compose, inline_vars))
 }
 
 fn deploy_parse(matches: &ArgMatches) -> ParseResult<CLICommand> {
+    let (compose, inline_vars) = stack_definition_parse(matches)?;
+
     Ok(CLICommand::Deploy {
         server: server_config_parse(matches)?,
-        compose: todo!(),
-        stack: todo!(),
-        endpoint: todo!(),
-        confirmed: todo!(),
-        inline_vars: todo!(),
-        configs: todo!(),
-        secrets: todo!(),
+        compose,
+        stack: matches.get_one::<String>("stack").unwrap().to_string(),
+        endpoint: endpoint_parse(matches)?,
+        confirmed: confirm_parse(matches),
+        inline_vars,
+        configs: many(matches, "config"),
+        secrets: many(matches, "secret"),
+        registries: many(matches, "registry"),
+        create_externals: matches.get_flag("create-externals"),
     })
 }
+
+fn drift_parse(matches: &ArgMatches) -> ParseResult<CLICommand> {
+    if let Some(manifest) = matches.get_one::<PathBuf>("manifest") {
+        return Ok(CLICommand::DriftManifest {
+            server: manifest_servers_parse(matches)?,
+            manifest: manifest.clone(),
+        });
+    }
+    let (compose, inline_vars) = stack_definition_parse(matches)?;
+
+    Ok(CLICommand::Drift {
+        server: server_config_parse(matches)?,
+        compose,
+        stack: matches.get_one::<String>("stack").unwrap().to_string(),
+        endpoint: endpoint_parse(matches)?,
+        inline_vars,
+        configs: many(matches, "config"),
+        secrets: many(matches, "secret"),
+    })
+}
+
 fn destroy_command() -> Command {
-    app_args(Command::new("destroy").about("destroy stacks, configs, secrets"))
+    let selection = ArgGroup::new("selection")
+        .args([
+            "STACK", "config", "secret", "volume", "network", "selector", "orphans", "manifest",
+        ])
+        .multiple(true)
+        .required(true);
+
+    server_config_args(endpoint_selector_args(
+        Command::new("destroy")
+            .about("destroy stacks, configs, secrets, volumes and networks selected by name, glob or label")
+            .arg(arg!([STACK] ... "stack names or globs to remove, e.g. `preview-*`").value_parser(parse_glob))
+            .arg(arg!(--config <NAME> "config name or glob to remove").action(ArgAction::Append).value_parser(parse_glob))
+            .arg(arg!(--secret <NAME> "secret name or glob to remove").action(ArgAction::Append).value_parser(parse_glob))
+            .arg(arg!(--volume <NAME> "volume name or glob to remove").action(ArgAction::Append).value_parser(parse_glob))
+            .arg(arg!(--network <NAME> "network name or glob to remove").action(ArgAction::Append).value_parser(parse_glob))
+            .arg(
+                arg!(--selector <LABEL> "removes stacks having a service label and configs, secrets, volumes and networks having the label, format `key=value`")
+                    .action(ArgAction::Append)
+                    .value_parser(parse_label),
+            )
+            .arg(arg!(--orphans "removes configs and secrets no service uses once selected stacks are removed, only among selected configs and secrets when some are selected"))
+            .arg(
+                arg!(-m --manifest <FILE> "destroys all stacks, configs and secrets of a manifest, dependents first")
+                    .value_parser(value_parser!(PathBuf))
+                    .conflicts_with_all([
+                        "endpoint-selector", "STACK", "config", "secret", "volume", "network", "selector", "orphans",
+                    ]),
+            )
+            .args(confirm_args())
+            .group(selection),
+    ))
+}
+
+fn destroy_parse(matches: &ArgMatches) -> ParseResult<CLICommand> {
+    if let Some(manifest) = matches.get_one::<PathBuf>("manifest") {
+        return Ok(CLICommand::DestroyManifest {
+            server: manifest_servers_parse(matches)?,
+            manifest: manifest.clone(),
+            confirmed: confirm_parse(matches),
+        });
+    }
+    Ok(CLICommand::Destroy {
+        server: server_config_parse(matches)?,
+        endpoint: endpoint_parse(matches)?,
+        confirmed: confirm_parse(matches),
+        selection: DestroySelection {
+            stacks: many(matches, "STACK"),
+            configs: many(matches, "config"),
+            secrets: many(matches, "secret"),
+            volumes: many(matches, "volume"),
+            networks: many(matches, "network"),
+            labels: many(matches, "selector"),
+            orphans: matches.get_flag("orphans"),
+        },
+    })
+}
+
+fn manifest_command(name: &'static str, about: &'static str) -> Command {
+    server_config_args(
+        Command::new(name).about(about).arg(
+            arg!(-m --manifest <FILE> "manifest describing stacks")
+                .value_parser(value_parser!(PathBuf))
+                .default_value("pctl.yaml"),
+        ),
+    )
+}
+
+fn apply_command() -> Command {
+    manifest_command(
+        "apply",
+        "deploys all stacks of a manifest, dependencies first, after a single confirmation",
+    )
+    .arg(create_externals_arg())
+    .args(confirm_args())
+}
+
+fn apply_parse(matches: &ArgMatches) -> ParseResult<CLICommand> {
+    Ok(CLICommand::Apply {
+        server: manifest_servers_parse(matches)?,
+        manifest: matches.get_one::<PathBuf>("manifest").unwrap().clone(),
+        confirmed: confirm_parse(matches),
+        create_externals: matches.get_flag("create-externals"),
+    })
+}
+
+fn diff_command() -> Command {
+    manifest_command(
+        "diff",
+        "shows the plan of a manifest and how deployed stacks differ from it",
+    )
+}
+
+fn diff_parse(matches: &ArgMatches) -> ParseResult<CLICommand> {
+    Ok(CLICommand::Diff {
+        server: manifest_servers_parse(matches)?,
+        manifest: matches.get_one::<PathBuf>("manifest").unwrap().clone(),
+    })
+}
+
+/// Durations like `90`, `30s`, `5m`, `1h` or `7d`, plain numbers are seconds
+fn parse_interval(value: &str) -> ParseResult<Duration> {
+    let (number, unit) = match value.find(|c: char| !c.is_ascii_digit()) {
+        Some(index) => value.split_at(index),
+        None => (value, "s"),
+    };
+    let number: u64 = number
+        .parse()
+        .map_err(|_| format!("Invalid interval '{}', use e.g. 30s, 5m, 1h or 7d", value))?;
+    let multiplier: u64 = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 3600,
+        "d" => 86400,
+        _ => {
+            return Err(format!(
+                "Invalid interval unit '{}', use s, m, h or d",
+                unit
+            ))
+        }
+    };
+    let seconds = number
+        .checked_mul(multiplier)
+        .ok_or_else(|| format!("Interval '{}' is too large", value))?;
+    if seconds == 0 {
+        return Err("Interval must be positive".to_string());
+    }
+    Ok(Duration::from_secs(seconds))
+}
+
+fn reconcile_command() -> Command {
+    manifest_command(
+        "reconcile",
+        "keeps endpoints in sync with a manifest in a git repository, applying non-destructive changes",
+    )
+    .mut_arg("manifest", |arg| arg.help("manifest describing stacks, relative to the repository"))
+    .arg(
+        arg!(--"repo-dir" <DIR> "checked out repository holding the manifest")
+            .value_parser(value_parser!(PathBuf))
+            .default_value("."),
+    )
+    .arg(
+        arg!(--interval <DURATION> "time between syncs, e.g. 30s, 5m or 1h")
+            .value_parser(parse_interval)
+            .default_value("1m"),
+    )
+    .arg(arg!(--"no-pull" "do not run `git pull` before syncs, for repositories updated otherwise"))
+    .arg(arg!(--"allow-destructive" "also applies updates removing services"))
+    .arg(arg!(--listen <ADDRESS> "serves /healthz, /status and /metrics, e.g. 127.0.0.1:9090"))
+    .arg(arg!(--once "syncs once and exits, failing when the sync fails"))
+}
+
+fn reconcile_parse(matches: &ArgMatches) -> ParseResult<CLICommand> {
+    Ok(CLICommand::Reconcile {
+        server: manifest_servers_parse(matches)?,
+        repo_dir: matches.get_one::<PathBuf>("repo-dir").unwrap().clone(),
+        manifest: matches.get_one::<PathBuf>("manifest").unwrap().clone(),
+        interval: *matches.get_one::<Duration>("interval").unwrap(),
+        pull: !matches.get_flag("no-pull"),
+        allow_destructive: matches.get_flag("allow-destructive"),
+        listen: matches.get_one::<String>("listen").cloned(),
+        once: matches.get_flag("once"),
+    })
+}
+
+fn preview_command() -> Command {
+    Command::new("preview")
+        .about("manages ephemeral preview environments, e.g. of pull requests")
+        .subcommand_required(true)
+        .subcommand(
+            app_args(
+                stack_definition_args(Command::new("up").about(
+                    "deploys or updates the preview of a stack, replacing `{{id}}`, `{{stack}}` and `{{name}}`, the preview stack name, in the compose file, env values and config and secret names",
+                ))
+                .mut_arg("compose", |arg| arg.required(true))
+                .mut_arg("stack", |arg| arg.required(true).help("stack the preview is made of")),
+            )
+            // `--id` is the preview id here, the endpoint id is given with -E
+            .mut_arg("id", |arg| arg.long("endpoint-id"))
+            .arg(arg!(--id <ID> "preview id, e.g. `pr-123`").id("preview-id").required(true).value_parser(preview::parse_id))
+            .arg(arg!(--"name-template" <TEMPLATE> "name of the preview stack").default_value("{{stack}}-{{id}}"))
+            .arg(arg!(--ttl <DURATION> "time after which `preview gc` destroys the preview, e.g. 12h or 7d, renewed by each `up`").value_parser(parse_interval))
+            .arg(arg!(--registry <NAME> "registry whose credentials are used to pull images, granting the endpoint access when missing").action(ArgAction::Append))
+            .arg(create_externals_arg())
+            .args(confirm_args()),
+        )
+        .subcommand(app_args(
+            Command::new("gc")
+                .about("destroys previews whose TTL expired, or whose id is not open when open ids are given, with their configs and secrets")
+                .arg(arg!(--open <ID> "id of a preview to keep, e.g. of an open pull request").action(ArgAction::Append))
+                .arg(arg!(--"open-file" <FILE> "file listing ids of previews to keep, one per line, `-` reads stdin").value_parser(value_parser!(PathBuf)))
+                .args(confirm_args()),
+        ))
+}
+
+/// Open ids from flags and the open file, none when neither is given
+fn open_previews_parse(matches: &ArgMatches) -> ParseResult<Option<Vec<String>>> {
+    let file = matches.get_one::<PathBuf>("open-file");
+    if file.is_none() && !matches.contains_id("open") {
+        return Ok(None);
+    }
+    let mut open: Vec<String> = many(matches, "open");
+    if let Some(file) = file {
+        let content = if file.as_os_str() == "-" {
+            let mut content = String::new();
+            std::io::stdin()
+                .read_to_string(&mut content)
+                .map_err(|err| format!("Cannot read open ids from stdin: {}", err))?;
+            content
+        } else {
+            fs::read_to_string(file)
+                .map_err(|err| format!("Cannot read '{}': {}", file.display(), err))?
+        };
+        open.extend(
+            content
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                .map(str::to_string),
+        );
+    }
+    Ok(Some(open))
+}
+
+fn preview_parse(matches: &ArgMatches) -> ParseResult<CLICommand> {
+    match matches.subcommand() {
+        Some(("up", matches)) => {
+            let spec = PreviewSpec {
+                id: matches.get_one::<String>("preview-id").unwrap().to_string(),
+                name_template: matches
+                    .get_one::<String>("name-template")
+                    .unwrap()
+                    .to_string(),
+                ttl: matches.get_one::<Duration>("ttl").copied(),
+            };
+            let stack = matches.get_one::<String>("stack").unwrap();
+            let (compose, inline_vars) = stack_definition_parse(matches)?;
+            let labels = spec.labels();
+            let mut compose = ComposeFile::parse(&spec.render(&compose, stack))?;
+            compose.add_deploy_labels(&labels);
+            let files = |id: &str| -> Vec<FileMapping> {
+                many(matches, id)
+                    .into_iter()
+                    .map(|FileMapping(name, source)| FileMapping(spec.render(&name, stack), source))
+                    .collect()
+            };
+
+            Ok(CLICommand::PreviewUp {
+                server: server_config_parse(matches)?,
+                compose: compose.to_yaml()?,
+                stack: spec.stack_name(stack),
+                endpoint: endpoint_parse(matches)?,
+                confirmed: confirm_parse(matches),
+                inline_vars: inline_vars
+                    .into_iter()
+                    .map(|InlineEnv(key, value)| InlineEnv(key, spec.render(&value, stack)))
+                    .collect(),
+                configs: files("config"),
+                secrets: files("secret"),
+                registries: many(matches, "registry"),
+                labels,
+                create_externals: matches.get_flag("create-externals"),
+            })
+        }
+        Some(("gc", matches)) => Ok(CLICommand::PreviewGc {
+            server: server_config_parse(matches)?,
+            endpoint: endpoint_parse(matches)?,
+            confirmed: confirm_parse(matches),
+            open: open_previews_parse(matches)?,
+        }),
+        Some((cmd, _)) => Err(format!("Unknown command 'preview {}'", cmd)),
+        None => Err("Command is required!".to_string()),
+    }
+}
+
+fn lifecycle_command(name: &'static str, about: &'static str) -> Command {
+    app_args(wait_args(stack_selector_args(
+        Command::new(name).about(about).args(confirm_args()),
+    )))
+}
+
+fn wait_parse(matches: &ArgMatches) -> Option<Duration> {
+    if matches.get_flag("wait") {
+        matches
+            .get_one::<u64>("timeout")
+            .map(|secs| Duration::from_secs(*secs))
+    } else {
+        None
+    }
+}
+
+fn start_parse(matches: &ArgMatches) -> ParseResult<CLICommand> {
+    Ok(CLICommand::Start {
+        server: server_config_parse(matches)?,
+        stacks: stack_selector_parse(matches)?,
+        endpoint: endpoint_parse(matches)?,
+        confirmed: confirm_parse(matches),
+        wait: wait_parse(matches),
+    })
+}
+
+fn stop_parse(matches: &ArgMatches) -> ParseResult<CLICommand> {
+    Ok(CLICommand::Stop {
+        server: server_config_parse(matches)?,
+        stacks: stack_selector_parse(matches)?,
+        endpoint: endpoint_parse(matches)?,
+        confirmed: confirm_parse(matches),
+        wait: wait_parse(matches),
+    })
+}
+
+fn stack_command() -> Command {
+    Command::new("stack")
+        .about("manages stacks")
+        .subcommand_required(true)
+        .subcommand(app_args(
+            Command::new("ls").about("lists stacks of an endpoint"),
+        ))
+}
+
+fn stack_parse(matches: &ArgMatches) -> ParseResult<CLICommand> {
+    match matches.subcommand() {
+        Some(("ls", matches)) => Ok(CLICommand::ListStacks {
+            server: server_config_parse(matches)?,
+            endpoint: endpoint_parse(matches)?,
+        }),
+        Some((cmd, _)) => Err(format!("Unknown command 'stack {}'", cmd)),
+        None => Err("Command is required!".to_string()),
+    }
+}
+
+fn endpoint_settings_args(cmd: Command) -> Command {
+    cmd.arg(arg!(--url <URL> "endpoint url, e.g. tcp://10.0.0.1:2375 or 10.0.0.1:9001 for agents"))
+        .arg(arg!(--"public-url" <URL> "public url used to access published ports"))
+        .arg(arg!(-g --group <GROUP_ID> "endpoint group id").value_parser(value_parser!(i32)))
+        .arg(arg!(--tags <TAGS> "comma separated tag names").value_delimiter(','))
+        .arg(arg!(--"tls-skip-verify" "skips verification of the endpoint's TLS certificate, the default for agents"))
+        .arg(arg!(--"tls-verify" "verifies the endpoint's TLS certificate").conflicts_with("tls-skip-verify"))
+}
+
+fn endpoint_settings_parse(matches: &ArgMatches) -> EndpointSettings {
+    EndpointSettings {
+        url: matches.get_one::<String>("url").cloned(),
+        public_url: matches.get_one::<String>("public-url").cloned(),
+        group: matches.get_one::<i32>("group").copied(),
+        tags: matches
+            .get_many::<String>("tags")
+            .map(|tags| tags.cloned().collect()),
+        tls_skip_verify: if matches.get_flag("tls-skip-verify") {
+            Some(true)
+        } else if matches.get_flag("tls-verify") {
+            Some(false)
+        } else {
+            None
+        },
+    }
+}
+
+fn endpoint_command() -> Command {
+    Command::new("endpoint")
+        .about("manages endpoints (environments)")
+        .subcommand_required(true)
+        .subcommand(server_config_args(endpoint_selector_args(
+            Command::new("ls").about("lists endpoints, optionally filtered by a selector"),
+        )))
+        .subcommand(app_args(
+            Command::new("inspect").about("shows details of an endpoint"),
+        ))
+        .subcommand(server_config_args(endpoint_settings_args(
+            Command::new("create")
+                .about("creates an endpoint")
+                .arg(arg!(<NAME> "endpoint name"))
+                .arg(
+                    arg!(--type <TYPE> "endpoint type")
+                        .value_parser(["docker", "agent", "edge", "kubernetes"])
+                        .required(true),
+                ),
+        )))
+        .subcommand(app_args(endpoint_settings_args(
+            Command::new("update")
+                .about("updates an endpoint")
+                .arg(arg!(--rename <NAME> "new endpoint name")),
+        )))
+        .subcommand(app_args(
+            Command::new("rm")
+                .about("removes an endpoint")
+                .args(confirm_args()),
+        ))
+        .subcommand(
+            Command::new("tag")
+                .about("manages tags of an endpoint")
+                .subcommand_required(true)
+                .subcommand(app_args(
+                    Command::new("add")
+                        .about("adds tags to an endpoint, existing tags are kept")
+                        .arg(arg!(<TAG> ... "tag names")),
+                ))
+                .subcommand(app_args(
+                    Command::new("remove")
+                        .about("removes tags from an endpoint")
+                        .arg(arg!(<TAG> ... "tag names")),
+                )),
+        )
+}
+
+fn tag_command() -> Command {
+    Command::new("tag")
+        .about("manages tags")
+        .subcommand_required(true)
+        .subcommand(server_config_args(Command::new("ls").about("lists tags")))
+        .subcommand(server_config_args(
+            Command::new("create")
+                .about("creates tags, existing tags are left untouched")
+                .arg(arg!(<NAME> ... "tag names")),
+        ))
+        .subcommand(server_config_args(
+            Command::new("rm")
+                .about("removes tags, missing tags are ignored")
+                .arg(arg!(<NAME> ... "tag names")),
+        ))
+}
+
+fn volume_command() -> Command {
+    Command::new("volume")
+        .about("manages docker volumes of an endpoint")
+        .subcommand_required(true)
+        .subcommand(app_args(Command::new("ls").about("lists volumes")))
+        .subcommand(app_args(
+            Command::new("inspect")
+                .about("shows a volume")
+                .arg(arg!(<NAME> "volume name")),
+        ))
+        .subcommand(app_args(
+            Command::new("create")
+                .about("creates a volume, an existing volume is left untouched")
+                .arg(arg!(<NAME> "volume name"))
+                .arg(arg!(-d --driver <DRIVER> "volume driver, docker's default when missing"))
+                .arg(
+                    arg!(--opt <OPTION> "driver option, format `key=value`")
+                        .action(ArgAction::Append)
+                        .value_parser(parse_option),
+                )
+                .arg(
+                    arg!(-l --label <LABEL> "volume label, format `key=value`")
+                        .action(ArgAction::Append)
+                        .value_parser(parse_label),
+                ),
+        ))
+        .subcommand(app_args(
+            Command::new("rm")
+                .about("removes volumes, missing volumes are ignored")
+                .arg(arg!(<NAME> ... "volume names"))
+                .args(confirm_args()),
+        ))
+}
+
+fn labels_parse(matches: &ArgMatches) -> HashMap<String, String> {
+    many::<Label>(matches, "label")
+        .into_iter()
+        .map(|Label(key, value)| (key, value))
+        .collect()
 }
+
+fn volume_parse(matches: &ArgMatches) -> ParseResult<CLICommand> {
+    match matches.subcommand() {
+        Some(("ls", matches)) => Ok(CLICommand::ListVolumes {
+            server: server_config_parse(matches)?,
+            endpoint: endpoint_parse(matches)?,
+        }),
+        Some(("inspect", matches)) => Ok(CLICommand::InspectVolume {
+            server: server_config_parse(matches)?,
+            endpoint: endpoint_parse(matches)?,
+            name: matches.get_one::<String>("NAME").unwrap().to_string(),
+        }),
+        Some(("create", matches)) => Ok(CLICommand::CreateVolume {
+            server: server_config_parse(matches)?,
+            endpoint: endpoint_parse(matches)?,
+            volume: VolumeSpec {
+                name: matches.get_one::<String>("NAME").unwrap().to_string(),
+                driver: matches.get_one::<String>("driver").cloned(),
+                options: many(matches, "opt").into_iter().collect(),
+                labels: labels_parse(matches),
+            },
+        }),
+        Some(("rm", matches)) => Ok(CLICommand::Destroy {
+            server: server_config_parse(matches)?,
+            endpoint: endpoint_parse(matches)?,
+            confirmed: confirm_parse(matches),
+            selection: DestroySelection {
+                volumes: exact(&many(matches, "NAME")),
+                ..Default::default()
+            },
+        }),
+        Some((cmd, _)) => Err(format!("Unknown command 'volume {}'", cmd)),
+        None => Err("Command is required!".to_string()),
+    }
+}
+
+fn network_command() -> Command {
+    Command::new("network")
+        .about("manages docker networks of an endpoint")
+        .subcommand_required(true)
+        .subcommand(app_args(Command::new("ls").about("lists networks")))
+        .subcommand(app_args(
+            Command::new("create")
+                .about("creates a network")
+                .arg(arg!(<NAME> "network name"))
+                .arg(arg!(-d --driver <DRIVER> "network driver").default_value("overlay"))
+                .arg(arg!(--attachable "lets standalone containers attach to the network"))
+                .arg(arg!(--internal "restricts external access to the network"))
+                .arg(
+                    arg!(-l --label <LABEL> "network label, format `key=value`")
+                        .action(ArgAction::Append)
+                        .value_parser(parse_label),
+                ),
+        ))
+        .subcommand(app_args(
+            Command::new("rm")
+                .about("removes networks, missing and predefined networks are ignored")
+                .arg(arg!(<NAME> ... "network names"))
+                .args(confirm_args()),
+        ))
+}
+
+fn network_parse(matches: &ArgMatches) -> ParseResult<CLICommand> {
+    match matches.subcommand() {
+        Some(("ls", matches)) => Ok(CLICommand::ListNetworks {
+            server: server_config_parse(matches)?,
+            endpoint: endpoint_parse(matches)?,
+        }),
+        Some(("create", matches)) => Ok(CLICommand::CreateNetwork {
+            server: server_config_parse(matches)?,
+            endpoint: endpoint_parse(matches)?,
+            network: NetworkSpec {
+                name: matches.get_one::<String>("NAME").unwrap().to_string(),
+                driver: matches.get_one::<String>("driver").unwrap().to_string(),
+                attachable: matches.get_flag("attachable"),
+                internal: matches.get_flag("internal"),
+                labels: labels_parse(matches),
+            },
+        }),
+        Some(("rm", matches)) => Ok(CLICommand::Destroy {
+            server: server_config_parse(matches)?,
+            endpoint: endpoint_parse(matches)?,
+            confirmed: confirm_parse(matches),
+            selection: DestroySelection {
+                networks: exact(&many(matches, "NAME")),
+                ..Default::default()
+            },
+        }),
+        Some((cmd, _)) => Err(format!("Unknown command 'network {}'", cmd)),
+        None => Err("Command is required!".to_string()),
+    }
+}
+
+fn registry_credential_args(cmd: Command) -> Command {
+    cmd.arg(arg!(--"registry-user" <USER> "registry username, or access key id for ecr"))
+        .arg(
+            arg!(--"registry-password" <PASSWORD> "registry password, or secret access key for ecr, prompted when missing")
+                .requires("registry-user"),
+        )
+}
+
+fn registry_credential_parse(matches: &ArgMatches) -> Option<RegistryCredential> {
+    matches
+        .get_one::<String>("registry-user")
+        .map(|username| RegistryCredential {
+            username: username.to_string(),
+            password: matches.get_one::<String>("registry-password").cloned(),
+        })
+}
+
+fn registry_command() -> Command {
+    Command::new("registry")
+        .about("manages registries")
+        .subcommand_required(true)
+        .subcommand(server_config_args(
+            Command::new("ls").about("lists registries"),
+        ))
+        .subcommand(server_config_args(registry_credential_args(
+            Command::new("add")
+                .about("adds a registry")
+                .arg(arg!(<NAME> "registry name"))
+                .arg(
+                    arg!(--type <TYPE> "registry type")
+                        .value_parser(["dockerhub", "gitlab", "ecr", "custom"])
+                        .required(true),
+                )
+                .arg(arg!(--url <URL> "registry url, required for ecr and custom registries"))
+                .arg(
+                    arg!(--"instance-url" <URL> "gitlab instance url")
+                        .default_value("https://gitlab.com"),
+                )
+                .arg(arg!(--region <REGION> "ecr region")),
+        )))
+        .subcommand(server_config_args(registry_credential_args(
+            Command::new("update")
+                .about("updates a registry")
+                .arg(arg!(<NAME> "registry name"))
+                .arg(arg!(--rename <NAME> "new registry name"))
+                .arg(arg!(--url <URL> "registry url")),
+        )))
+        .subcommand(server_config_args(
+            Command::new("rm")
+                .about("removes registries, missing registries are ignored")
+                .arg(arg!(<NAME> ... "registry names")),
+        ))
+}
+
+fn registry_kind_parse(matches: &ArgMatches) -> ParseResult<RegistryKind> {
+    let url = matches.get_one::<String>("url");
+    match matches.get_one::<String>("type").unwrap().as_str() {
+        "dockerhub" => Ok(RegistryKind::DockerHub),
+        "gitlab" => Ok(RegistryKind::Gitlab {
+            instance_url: matches
+                .get_one::<String>("instance-url")
+                .unwrap()
+                .to_string(),
+        }),
+        "ecr" => match (url, matches.get_one::<String>("region")) {
+            (Some(_), Some(region)) => Ok(RegistryKind::Ecr {
+                region: region.to_string(),
+            }),
+            _ => Err("ECR registries require --url and --region".to_string()),
+        },
+        _ => match url {
+            Some(_) => Ok(RegistryKind::Custom),
+            None => Err("Custom registries require --url".to_string()),
+        },
+    }
+}
+
+fn registry_parse(matches: &ArgMatches) -> ParseResult<CLICommand> {
+    match matches.subcommand() {
+        Some(("ls", matches)) => Ok(CLICommand::ListRegistries {
+            server: server_config_parse(matches)?,
+        }),
+        Some(("add", matches)) => Ok(CLICommand::AddRegistry {
+            server: server_config_parse(matches)?,
+            name: matches.get_one::<String>("NAME").unwrap().to_string(),
+            kind: registry_kind_parse(matches)?,
+            url: matches.get_one::<String>("url").cloned(),
+            credential: registry_credential_parse(matches),
+        }),
+        Some(("update", matches)) => Ok(CLICommand::UpdateRegistry {
+            server: server_config_parse(matches)?,
+            name: matches.get_one::<String>("NAME").unwrap().to_string(),
+            rename: matches.get_one::<String>("rename").cloned(),
+            url: matches.get_one::<String>("url").cloned(),
+            credential: registry_credential_parse(matches),
+        }),
+        Some(("rm", matches)) => Ok(CLICommand::RemoveRegistries {
+            server: server_config_parse(matches)?,
+            names: many(matches, "NAME"),
+        }),
+        Some((cmd, _)) => Err(format!("Unknown command 'registry {}'", cmd)),
+        None => Err("Command is required!".to_string()),
+    }
+}
+
+fn group_command() -> Command {
+    Command::new("group")
+        .about("manages endpoint groups")
+        .subcommand_required(true)
+        .subcommand(server_config_args(
+            Command::new("ls").about("lists endpoint groups"),
+        ))
+        .subcommand(server_config_args(
+            Command::new("create")
+                .about("creates an endpoint group")
+                .arg(arg!(<NAME> "group name"))
+                .arg(arg!(--description <TEXT> "group description").default_value(""))
+                .arg(
+                    arg!(--tags <TAGS> "comma separated tag names of the group")
+                        .value_delimiter(','),
+                ),
+        ))
+        .subcommand(server_config_args(
+            Command::new("rm")
+                .about("removes endpoint groups, their endpoints move to Unassigned")
+                .arg(arg!(<NAME> ... "group names")),
+        ))
+        .subcommand(app_args(
+            Command::new("add-endpoint")
+                .about("moves all selected endpoints into a group")
+                .arg(arg!(<GROUP> "group name")),
+        ))
+}
+
+fn group_parse(matches: &ArgMatches) -> ParseResult<CLICommand> {
+    match matches.subcommand() {
+        Some(("ls", matches)) => Ok(CLICommand::ListGroups {
+            server: server_config_parse(matches)?,
+        }),
+        Some(("create", matches)) => Ok(CLICommand::CreateGroup {
+            server: server_config_parse(matches)?,
+            name: matches.get_one::<String>("NAME").unwrap().to_string(),
+            description: matches
+                .get_one::<String>("description")
+                .unwrap()
+                .to_string(),
+            tags: many(matches, "tags"),
+        }),
+        Some(("rm", matches)) => Ok(CLICommand::RemoveGroups {
+            server: server_config_parse(matches)?,
+            names: many(matches, "NAME"),
+        }),
+        Some(("add-endpoint", matches)) => Ok(CLICommand::AddToGroup {
+            server: server_config_parse(matches)?,
+            group: matches.get_one::<String>("GROUP").unwrap().to_string(),
+            endpoint: endpoint_parse(matches)?,
+        }),
+        Some((cmd, _)) => Err(format!("Unknown command 'group {}'", cmd)),
+        None => Err("Command is required!".to_string()),
+    }
+}
+
+fn tag_parse(matches: &ArgMatches) -> ParseResult<CLICommand> {
+    match matches.subcommand() {
+        Some(("ls", matches)) => Ok(CLICommand::ListTags {
+            server: server_config_parse(matches)?,
+        }),
+        Some(("create", matches)) => Ok(CLICommand::CreateTags {
+            server: server_config_parse(matches)?,
+            names: many(matches, "NAME"),
+        }),
+        Some(("rm", matches)) => Ok(CLICommand::RemoveTags {
+            server: server_config_parse(matches)?,
+            names: many(matches, "NAME"),
+        }),
+        Some((cmd, _)) => Err(format!("Unknown command 'tag {}'", cmd)),
+        None => Err("Command is required!".to_string()),
+    }
+}
+
+fn endpoint_kind_parse(value: &str) -> EndpointKind {
+    match value {
+        "agent" => EndpointKind::Agent,
+        "edge" => EndpointKind::Edge,
+        "kubernetes" => EndpointKind::Kubernetes,
+        _ => EndpointKind::Docker,
+    }
+}
+
+fn endpoint_subcommand_parse(matches: &ArgMatches) -> ParseResult<CLICommand> {
+    match matches.subcommand() {
+        Some(("ls", matches)) => Ok(CLICommand::ListEndpoints {
+            server: server_config_parse(matches)?,
+            endpoint: endpoint_parse_opt(matches),
+        }),
+        Some(("inspect", matches)) => Ok(CLICommand::InspectEndpoint {
+            server: server_config_parse(matches)?,
+            endpoint: endpoint_parse(matches)?,
+        }),
+        Some(("create", matches)) => Ok(CLICommand::CreateEndpoint {
+            server: server_config_parse(matches)?,
+            name: matches.get_one::<String>("NAME").unwrap().to_string(),
+            kind: endpoint_kind_parse(matches.get_one::<String>("type").unwrap()),
+            settings: endpoint_settings_parse(matches),
+        }),
+        Some(("update", matches)) => Ok(CLICommand::UpdateEndpoint {
+            server: server_config_parse(matches)?,
+            endpoint: endpoint_parse(matches)?,
+            name: matches.get_one::<String>("rename").cloned(),
+            settings: endpoint_settings_parse(matches),
+        }),
+        Some(("rm", matches)) => Ok(CLICommand::RemoveEndpoint {
+            server: server_config_parse(matches)?,
+            endpoint: endpoint_parse(matches)?,
+            confirmed: confirm_parse(matches),
+        }),
+        Some(("tag", matches)) => match matches.subcommand() {
+            Some((action @ ("add" | "remove"), matches)) => Ok(CLICommand::TagEndpoint {
+                server: server_config_parse(matches)?,
+                endpoint: endpoint_parse(matches)?,
+                tags: many(matches, "TAG"),
+                add: action == "add",
+            }),
+            Some((cmd, _)) => Err(format!("Unknown command 'endpoint tag {}'", cmd)),
+            None => Err("Command is required!".to_string()),
+        },
+        Some((cmd, _)) => Err(format!("Unknown command 'endpoint {}'", cmd)),
+        None => Err("Command is required!".to_string()),
+    }
+}
+
+fn logs_command() -> Command {
+    app_args(
+        Command::new("logs")
+            .about("shows logs of stack services")
+            .arg(arg!(<STACK> "stack name"))
+            .arg(arg!([SERVICE] "service name, all services of the stack if omitted"))
+            .arg(arg!(-f --follow "follows log output"))
+            .arg(arg!(--since <TIMESTAMP> "shows logs since a duration ago, e.g. 42m, an RFC3339 timestamp or unix seconds"))
+            .arg(arg!(-n --tail <LINES> "number of lines to show from the end of the logs"))
+            .arg(arg!(--timestamps "shows timestamps")),
+    )
+}
+
+fn logs_parse(matches: &ArgMatches) -> ParseResult<CLICommand> {
+    Ok(CLICommand::Logs {
+        server: server_config_parse(matches)?,
+        stack: matches.get_one::<String>("STACK").unwrap().to_string(),
+        service: matches.get_one::<String>("SERVICE").cloned(),
+        endpoint: endpoint_parse(matches)?,
+        options: LogOptions {
+            follow: matches.get_flag("follow"),
+            since: matches
+                .get_one::<String>("since")
+                .map(|since| since_parse(since, preview::now()))
+                .transpose()?,
+            tail: matches.get_one::<String>("tail").cloned(),
+            timestamps: matches.get_flag("timestamps"),
+        },
+    })
+}
+
+/// Docker only takes unix timestamps, relative durations are counted back from now
+fn since_parse(value: &str, now: u64) -> ParseResult<u64> {
+    let invalid = || {
+        format!(
+            "Invalid --since '{}', use a duration like 42m, an RFC3339 timestamp like 2024-05-01T12:00:00Z or unix seconds",
+            value
+        )
+    };
+    if value.bytes().all(|b| b.is_ascii_digit()) {
+        return value.parse().map_err(|_| invalid());
+    }
+    if let Some(timestamp) = logs::parse_timestamp(value) {
+        return u64::try_from(timestamp).map_err(|_| invalid());
+    }
+    if value.starts_with(|c: char| c.is_ascii_digit()) {
+        let ago = parse_interval(value).map_err(|_| invalid())?;
+        return Ok(now.saturating_sub(ago.as_secs()));
+    }
+    Err(invalid())
+}
+
+fn exec_command() -> Command {
+    app_args(
+        Command::new("exec")
+            .about("runs a command in a running container of a service")
+            .arg(arg!(<TARGET> "service to run command in, format `stack/service`"))
+            .arg(arg!(<COMMAND> ... "command to run").last(true))
+            .arg(arg!(--"no-tty" "disables pseudo-TTY allocation, e.g. for scripts")),
+    )
+}
+
+fn exec_parse(matches: &ArgMatches) -> ParseResult<CLICommand> {
+    let target = matches.get_one::<String>("TARGET").unwrap();
+    let (stack, service) = target
+        .split_once('/')
+        .ok_or_else(|| format!("Invalid target '{}', format is `stack/service`", target))?;
+
+    Ok(CLICommand::Exec {
+        server: server_config_parse(matches)?,
+        stack: stack.to_string(),
+        service: service.to_string(),
+        endpoint: endpoint_parse(matches)?,
+        command: many(matches, "COMMAND"),
+        tty: !matches.get_flag("no-tty"),
+    })
+}
+
+fn api_command() -> Command {
+    server_config_args(
+        Command::new("api")
+            .about("sends an arbitrary request to portainer api and prints the response")
+            .arg(arg!(<METHOD> "http method").value_parser(|s: &str| s.parse::<HttpMethod>()))
+            .arg(arg!(<PATH> "api path, e.g. /endpoints"))
+            .arg(
+                arg!(-q --query <QUERY> "query parameter, format `key=value`")
+                    .action(ArgAction::Append)
+                    .value_parser(parse_inline_env),
+            )
+            .arg(arg!(-d --data <BODY> "json body, use `@file` to read from a file or `@-` for stdin"))
+            .arg(arg!(--jq <SELECTOR> "selects fields from response, e.g. `.[].Name`"))
+            .arg(arg!(--raw "prints compact json, and strings without quotes"))
+            .arg(arg!(--paginate "fetches all pages of a list using `start` and `limit` queries"))
+            .arg(
+                arg!(--"page-size" <SIZE> "page size used for pagination")
+                    .value_parser(value_parser!(u32))
+                    .default_value("100"),
+            ),
+    )
+}
+
+fn read_body(value: &str) -> ParseResult<Json> {
+    let content = match value.strip_prefix('@') {
+        Some("-") => {
+            let mut content = String::new();
+            std::io::stdin()
+                .read_to_string(&mut content)
+                .map_err(|err| format!("Cannot read body from stdin: {}", err))?;
+            content
+        }
+        Some(path) => fs::read_to_string(path)
+            .map_err(|err| format!("Cannot read body file '{}': {}", path, err))?,
+        None => value.to_string(),
+    };
+    serde_json::from_str(&content).map_err(|err| format!("Invalid json body: {}", err))
+}
+
+fn api_parse(matches: &ArgMatches) -> ParseResult<CLICommand> {
+    let call = ApiCall {
+        method: *matches.get_one::<HttpMethod>("METHOD").unwrap(),
+        path: matches.get_one::<String>("PATH").unwrap().to_string(),
+        queries: many(matches, "query"),
+        body: matches
+            .get_one::<String>("data")
+            .map(|data| read_body(data))
+            .transpose()?,
+        select: matches
+            .get_one::<String>("jq")
+            .map(|expr| Selector::parse(expr))
+            .transpose()?,
+        raw: matches.get_flag("raw"),
+        page_size: if matches.get_flag("paginate") {
+            matches.get_one::<u32>("page-size").copied()
+        } else {
+            None
+        },
+    };
+
+    Ok(CLICommand::Api {
+        server: server_config_parse(matches)?,
+        call,
+    })
+}
+
 fn login_command() -> Command {
-    Command::new("login").about("login to server and adds it to sessions")
+    let credential = ArgGroup::new("credential")
+        .args(["token", "username"])
+        .required(true);
+
+    password_args(Command::new("login"))
+        .about("login to server and adds it to sessions")
+        .arg(arg!(<NAME> "session name"))
+        .arg(arg!(-H --address <url> "Server address").required(true))
+        .arg(arg!(--token <token> "API token"))
+        .arg(arg!(-u --username <username> "username to login"))
+        .mut_arg("password", |arg| arg.requires("username"))
+        .mut_arg("password-stdin", |arg| arg.requires("username"))
+        .mut_arg("password-file", |arg| arg.requires("username"))
+        .group(credential)
 }
+
+fn login_parse(matches: &ArgMatches) -> ParseResult<CLICommand> {
+    let credential = match matches.get_one::<String>("token") {
+        Some(token) => LoginCredential::ByAPIToken(token.to_string()),
+        None => LoginCredential::ByUserPass {
+            username: matches.get_one::<String>("username").unwrap().to_string(),
+            password: password_parse(matches)?,
+        },
+    };
+
+    Ok(CLICommand::Login {
+        server: matches.get_one::<String>("NAME").unwrap().to_string(),
+        address: matches.get_one::<String>("address").unwrap().to_string(),
+        credential,
+    })
+}
+
 fn logout_command() -> Command {
-    Command::new("logout").about("removes server from logged in sessions")
+    Command::new("logout")
+        .about("removes server from logged in sessions")
+        .arg(arg!(<NAME> "session name"))
+}
+
+fn logout_parse(matches: &ArgMatches) -> ParseResult<CLICommand> {
+    Ok(CLICommand::Logout(
+        matches.get_one::<String>("NAME").unwrap().to_string(),
+    ))
+}
+
+fn session_command() -> Command {
+    Command::new("session")
+        .about("manages the session store")
+        .subcommand_required(true)
+        .subcommand(
+            Command::new("encrypt")
+                .about("encrypts saved sessions with a passphrase, or with age recipients when given")
+                .arg(arg!(--recipient <RECIPIENT> "age recipient able to decrypt sessions, e.g. age1...").action(ArgAction::Append)),
+        )
+        .subcommand(Command::new("decrypt").about("stores saved sessions in plaintext again"))
+        .subcommand(Command::new("unlock").about(
+            "prints the key of a passphrase store for the shell, use as `eval $(pctl session unlock)`",
+        ))
+        .subcommand(Command::new("lock").about(
+            "prints the command forgetting the key of the shell, use as `eval $(pctl session lock)`",
+        ))
+        .subcommand(
+            Command::new("protect")
+                .about("sets the protected endpoints and stacks of a session, without rules protection is removed")
+                .arg(arg!(<NAME> "session name"))
+                .arg(arg!(--endpoint <GLOB> "protects endpoints whose name matches").action(ArgAction::Append))
+                .arg(arg!(--"endpoint-tag" <TAG> "protects endpoints having the tag").action(ArgAction::Append))
+                .arg(arg!(--stack <GLOB> "protects stacks whose name matches").action(ArgAction::Append)),
+        )
+}
+
+fn session_parse(matches: &ArgMatches) -> ParseResult<CLICommand> {
+    match matches.subcommand() {
+        Some(("encrypt", matches)) => Ok(CLICommand::EncryptSessions {
+            recipients: many(matches, "recipient"),
+        }),
+        Some(("decrypt", _)) => Ok(CLICommand::DecryptSessions),
+        Some(("unlock", _)) => Ok(CLICommand::UnlockSessions),
+        Some(("lock", _)) => Ok(CLICommand::LockSessions),
+        Some(("protect", matches)) => Ok(CLICommand::ProtectSession {
+            name: matches.get_one::<String>("NAME").unwrap().to_string(),
+            protection: Protection {
+                endpoints: many(matches, "endpoint"),
+                endpoint_tags: many(matches, "endpoint-tag"),
+                stacks: many(matches, "stack"),
+            },
+        }),
+        Some((cmd, _)) => Err(format!("Unknown command 'session {}'", cmd)),
+        None => Err("Command is required!".to_string()),
+    }
 }
 
 fn build_command() -> Command {
@@ -168,22 +1993,238 @@ fn build_command() -> Command {
         .version("0.1.0")
         .about("Save human time by using this client to automate workflows in CI/CD or other pipelines.")
         .color(clap::ColorChoice::Auto)
+        .arg(
+            arg!(-o --output <FORMAT> "output format: table, json, yaml or template=<tmpl>")
+                .global(true)
+                .value_parser(|s: &str| s.parse::<OutputFormat>())
+                .default_value("table"),
+        )
+        .arg(
+            arg!(--"age-identity" <FILE> "age identity file decrypting encrypted envfiles, configs and secrets, $PCTL_AGE_KEY may hold keys too")
+                .global(true)
+                .value_parser(value_parser!(PathBuf)),
+        )
+        .arg(
+            arg!(--"credential-helper" <NAME> "keeps sessions with the pctl-credential-<NAME> program instead of the sessions file, defaults to $PCTL_CREDENTIAL_HELPER")
+                .global(true),
+        )
         .subcommand(deploy_command())
+        .subcommand(apply_command())
+        .subcommand(diff_command())
+        .subcommand(drift_command())
+        .subcommand(reconcile_command())
         .subcommand(destroy_command())
+        .subcommand(preview_command())
+        .subcommand(lifecycle_command("start", "starts stopped stacks"))
+        .subcommand(lifecycle_command("stop", "stops running stacks"))
+        .subcommand(stack_command())
+        .subcommand(endpoint_command())
+        .subcommand(volume_command())
+        .subcommand(network_command())
+        .subcommand(tag_command())
+        .subcommand(group_command())
+        .subcommand(registry_command())
+        .subcommand(logs_command())
+        .subcommand(exec_command())
+        .subcommand(api_command())
         .subcommand(login_command())
         .subcommand(logout_command())
+        .subcommand(session_command())
 }
-pub fn parse_command() -> ParseResult<CLICommand> {
-    match build_command().get_matches().subcommand() {
-        Some(("deploy", matches)) => deploy_parse(&matches),
-        Some(("destroy", matches)) => deploy_parse(&matches),
-        Some(("login", matches)) => deploy_parse(&matches),
-        Some(("logout", matches)) => deploy_parse(&matches),
+pub fn parse_command() -> ParseResult<(GlobalOptions, CLICommand)> {
+    let matches = build_command().get_matches();
+    let options = GlobalOptions {
+        output: matches.get_one::<OutputFormat>("output").unwrap().clone(),
+        age_identity: matches.get_one::<PathBuf>("age-identity").cloned(),
+        credential_helper: matches
+            .get_one::<String>("credential-helper")
+            .cloned()
+            .or_else(|| std::env::var("PCTL_CREDENTIAL_HELPER").ok()),
+    };
+    let command = match matches.subcommand() {
+        Some(("deploy", matches)) => deploy_parse(matches),
+        Some(("apply", matches)) => apply_parse(matches),
+        Some(("diff", matches)) => diff_parse(matches),
+        Some(("drift", matches)) => drift_parse(matches),
+        Some(("reconcile", matches)) => reconcile_parse(matches),
+        Some(("destroy", matches)) => destroy_parse(matches),
+        Some(("preview", matches)) => preview_parse(matches),
+        Some(("start", matches)) => start_parse(matches),
+        Some(("stop", matches)) => stop_parse(matches),
+        Some(("stack", matches)) => stack_parse(matches),
+        Some(("endpoint", matches)) => endpoint_subcommand_parse(matches),
+        Some(("volume", matches)) => volume_parse(matches),
+        Some(("network", matches)) => network_parse(matches),
+        Some(("tag", matches)) => tag_parse(matches),
+        Some(("group", matches)) => group_parse(matches),
+        Some(("registry", matches)) => registry_parse(matches),
+        Some(("logs", matches)) => logs_parse(matches),
+        Some(("exec", matches)) => exec_parse(matches),
+        Some(("api", matches)) => api_parse(matches),
+        Some(("login", matches)) => login_parse(matches),
+        Some(("logout", matches)) => logout_parse(matches),
+        Some(("session", matches)) => session_parse(matches),
         Some((cmd, _)) => ParseResult::Err(format!("Unknown command '{}'", cmd)),
         None => {
             build_command().print_help().expect("cannot print help");
 
-            ParseResult::Err(format!("Command is required!"))
+            ParseResult::Err("Command is required!".to_string())
+        }
+    }?;
+    Ok((options, command))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn since_accepts_durations_timestamps_and_unix_seconds() {
+        let now = 1714564800;
+        assert_eq!(since_parse("42m", now), Ok(now - 42 * 60));
+        assert_eq!(since_parse("2h", now), Ok(now - 2 * 3600));
+        assert_eq!(since_parse("2024-05-01T11:00:00Z", now), Ok(now - 3600));
+        assert_eq!(since_parse("1700000000", now), Ok(1700000000));
+    }
+
+    #[test]
+    fn intervals_are_read_in_units() {
+        assert_eq!(parse_interval("90"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_interval("5m"), Ok(Duration::from_secs(300)));
+        assert_eq!(parse_interval("7d"), Ok(Duration::from_secs(604800)));
+        assert!(parse_interval("0s").is_err());
+        assert!(parse_interval("5w").is_err());
+    }
+
+    #[test]
+    fn large_intervals_fail_instead_of_overflowing() {
+        assert_eq!(
+            parse_interval("213503982334602d"),
+            Err("Interval '213503982334602d' is too large".to_string())
+        );
+        assert!(parse_interval("99999999999999999999s").is_err());
+    }
+
+    #[test]
+    fn since_rejects_invalid_values() {
+        for value in [
+            "yesterday",
+            "42x",
+            "-5m",
+            "2024-05-01",
+            "1969-12-31T23:59:59Z",
+        ] {
+            let err = since_parse(value, 0).unwrap_err();
+            assert!(err.starts_with(&format!("Invalid --since '{}'", value)));
+        }
+    }
+
+    fn settings(fields: &[(&str, &str)]) -> ServerSettings {
+        let field = |name: &str| {
+            fields
+                .iter()
+                .find(|(key, _)| *key == name)
+                .map(|(_, value)| value.to_string())
+        };
+        ServerSettings {
+            session: field("session"),
+            address: field("address"),
+            token: field("token"),
+            username: field("username"),
         }
     }
+
+    fn describe(server: ParseResult<Option<ServerConfig>>) -> ParseResult<String> {
+        Ok(match server? {
+            None => "none".to_string(),
+            Some(ServerConfig::Session(name)) => format!("session {}", name),
+            Some(ServerConfig::InlineToken { address, token }) => {
+                format!("token {} at {}", token, address)
+            }
+            Some(ServerConfig::InlineLogin {
+                address, username, ..
+            }) => format!("login {} at {}", username, address),
+        })
+    }
+
+    fn lookup(flags: &[(&str, &str)], env: &[(&str, &str)]) -> ParseResult<String> {
+        describe(server_config_lookup(
+            settings(flags),
+            settings(env),
+            || Err("Invalid .pctl.toml".to_string()),
+            None,
+        ))
+    }
+
+    #[test]
+    fn flag_credentials_are_not_dropped_for_sessions() {
+        assert_eq!(
+            lookup(&[("token", "t")], &[("session", "prod")]),
+            Err("A token or username cannot be used with session 'prod', pass --address too or unset the session".to_string())
+        );
+        assert_eq!(
+            lookup(
+                &[("token", "t")],
+                &[("session", "prod"), ("address", "http://env")]
+            ),
+            Ok("token t at http://env".to_string())
+        );
+        assert_eq!(
+            lookup(
+                &[("address", "http://flag"), ("token", "t")],
+                &[("session", "prod")]
+            ),
+            Ok("token t at http://flag".to_string())
+        );
+        assert_eq!(
+            lookup(&[], &[("session", "prod"), ("token", "env")]),
+            Ok("session prod".to_string())
+        );
+    }
+
+    #[test]
+    fn project_file_is_read_only_for_missing_settings() {
+        assert_eq!(
+            lookup(&[("session", "prod")], &[]),
+            Ok("session prod".to_string())
+        );
+        assert_eq!(
+            lookup(&[("address", "http://flag"), ("username", "admin")], &[]),
+            Ok("login admin at http://flag".to_string())
+        );
+        assert_eq!(
+            lookup(&[("token", "t")], &[("address", "http://env")]),
+            Ok("token t at http://env".to_string())
+        );
+        assert_eq!(lookup(&[], &[]), Err("Invalid .pctl.toml".to_string()));
+        assert_eq!(
+            lookup(&[("address", "http://flag")], &[]),
+            Err("Invalid .pctl.toml".to_string())
+        );
+
+        let project = || {
+            Ok(settings(&[
+                ("address", "http://project"),
+                ("username", "ops"),
+            ]))
+        };
+        assert_eq!(
+            describe(server_config_lookup(
+                settings(&[("token", "t")]),
+                settings(&[]),
+                project,
+                None
+            )),
+            Ok("token t at http://project".to_string())
+        );
+        assert_eq!(
+            describe(server_config_lookup(
+                settings(&[]),
+                settings(&[("address", "http://env")]),
+                project,
+                None
+            )),
+            Ok("login ops at http://env".to_string())
+        );
+    }
 }
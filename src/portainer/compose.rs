@@ -0,0 +1,152 @@
+use serde_yaml::Value as Yaml;
+
+use super::Res;
+
+pub struct ComposeFile(Yaml);
+
+fn labels_of(value: Option<&Yaml>) -> Vec<(String, String)> {
+    match value {
+        Some(Yaml::Mapping(map)) => map
+            .iter()
+            .filter_map(|(k, v)| Some((scalar(k)?, scalar(v).unwrap_or_default())))
+            .collect(),
+        Some(Yaml::Sequence(items)) => items
+            .iter()
+            .filter_map(|item| item.as_str())
+            .map(|item| match item.split_once('=') {
+                Some((k, v)) => (k.to_string(), v.to_string()),
+                None => (item.to_string(), String::new()),
+            })
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+pub fn scalar(value: &Yaml) -> Option<String> {
+    match value {
+        Yaml::String(s) => Some(s.to_string()),
+        Yaml::Number(n) => Some(n.to_string()),
+        Yaml::Bool(b) => Some(b.to_string()),
+        _ => None,
+    }
+}
+
+impl ComposeFile {
+    pub fn parse(content: &str) -> Res<ComposeFile> {
+        serde_yaml::from_str(content)
+            .map(ComposeFile)
+            .map_err(|err| format!("Invalid compose file: {}", err))
+    }
+
+    fn services(&self) -> Vec<&Yaml> {
+        self.0
+            .get("services")
+            .and_then(Yaml::as_mapping)
+            .map(|services| services.values().collect())
+            .unwrap_or_default()
+    }
+
+    pub fn service_names(&self) -> Vec<String> {
+        self.0
+            .get("services")
+            .and_then(Yaml::as_mapping)
+            .map(|services| services.keys().filter_map(scalar).collect())
+            .unwrap_or_default()
+    }
+
+    /// Labels of all services, both container and `deploy` labels
+    pub fn service_labels(&self) -> Vec<(String, String)> {
+        self.services()
+            .into_iter()
+            .flat_map(|service| {
+                let mut labels = labels_of(service.get("labels"));
+                labels.extend(labels_of(
+                    service.get("deploy").and_then(|d| d.get("labels")),
+                ));
+                labels
+            })
+            .collect()
+    }
+
+    /// Sets labels in the `deploy` section of every service, keeping the form labels use
+    pub fn add_deploy_labels(&mut self, labels: &[(String, String)]) {
+        let services = self
+            .0
+            .get_mut("services")
+            .and_then(Yaml::as_mapping_mut)
+            .into_iter()
+            .flat_map(|services| services.values_mut())
+            .filter_map(Yaml::as_mapping_mut);
+
+        for service in services {
+            let deploy = service
+                .entry("deploy".into())
+                .or_insert_with(|| Yaml::Mapping(Default::default()));
+            let Some(deploy) = deploy.as_mapping_mut() else {
+                continue;
+            };
+            let current = deploy
+                .entry("labels".into())
+                .or_insert_with(|| Yaml::Mapping(Default::default()));
+            match current {
+                Yaml::Sequence(items) => {
+                    for (key, value) in labels {
+                        let prefix = format!("{}=", key);
+                        items.retain(|item| {
+                            item.as_str()
+                                .is_none_or(|i| i != key && !i.starts_with(&prefix))
+                        });
+                        items.push(format!("{}={}", key, value).into());
+                    }
+                }
+                Yaml::Mapping(map) => {
+                    for (key, value) in labels {
+                        map.insert(key.as_str().into(), value.as_str().into());
+                    }
+                }
+                _ => (),
+            }
+        }
+    }
+
+    /// Names of top level `volumes` or `networks` declared `external`, which have to exist
+    /// on the endpoint before deploy. Names left to env interpolation are skipped.
+    fn externals(&self, kind: &str) -> Vec<String> {
+        let Some(definitions) = self.0.get(kind).and_then(Yaml::as_mapping) else {
+            return Vec::new();
+        };
+        definitions
+            .iter()
+            .filter_map(|(key, definition)| {
+                let external = definition.get("external")?;
+                // legacy form `external: {name: ...}`
+                let legacy = external.get("name").and_then(scalar);
+                if external.as_bool() != Some(true) && legacy.is_none() {
+                    return None;
+                }
+                legacy
+                    .or_else(|| definition.get("name").and_then(scalar))
+                    .or_else(|| scalar(key))
+            })
+            .filter(|name| !name.contains('$'))
+            .collect()
+    }
+
+    pub fn external_volumes(&self) -> Vec<String> {
+        self.externals("volumes")
+    }
+
+    pub fn external_networks(&self) -> Vec<String> {
+        self.externals("networks")
+    }
+
+    pub fn to_yaml(&self) -> Res<String> {
+        serde_yaml::to_string(&self.0).map_err(|err| err.to_string())
+    }
+
+    pub fn has_label(&self, key: &str, value: &str) -> bool {
+        self.service_labels()
+            .iter()
+            .any(|(k, v)| k == key && v == value)
+    }
+}
@@ -0,0 +1,521 @@
+use std::cell::OnceCell;
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use aes_gcm::aead::consts::U32;
+use aes_gcm::aead::{Aead, KeyInit, Payload};
+use aes_gcm::aes::Aes256;
+use aes_gcm::{AesGcm, Nonce};
+use age::armor::ArmoredReader;
+use age::{Decryptor, Identity, IdentityFile};
+use serde_yaml::{Mapping, Value as Yaml};
+use sha2::{Digest, Sha512};
+
+use super::compose::scalar;
+use super::Res;
+
+/// SOPS encrypts values with AES-256-GCM using 32 byte nonces
+type SopsCipher = AesGcm<Aes256, U32>;
+
+const AGE_BINARY: &[u8] = b"age-encryption.org/v1";
+const AGE_ARMORED: &[u8] = b"-----BEGIN AGE ENCRYPTED FILE-----";
+
+/// Format of a read file, structured formats only come from SOPS files
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    Text,
+    Dotenv,
+    Yaml,
+    Json,
+}
+
+/// Age identities used to decrypt inputs, loaded from `--age-identity` and
+/// `$PCTL_AGE_KEY` the first time an encrypted file is read
+pub struct Keyring {
+    file: Option<PathBuf>,
+    identities: OnceCell<Vec<Box<dyn Identity>>>,
+}
+impl Keyring {
+    pub fn new(file: Option<PathBuf>) -> Keyring {
+        Keyring {
+            file,
+            identities: OnceCell::new(),
+        }
+    }
+
+    fn identities(&self) -> Res<&[Box<dyn Identity>]> {
+        if let Some(identities) = self.identities.get() {
+            return Ok(identities);
+        }
+
+        let invalid =
+            |source: &str, err: String| format!("Invalid age identity {}: {}", source, err);
+        let mut identities = Vec::new();
+        if let Some(path) = &self.file {
+            let source = format!("file '{}'", path.display());
+            let file = IdentityFile::from_file(path.to_string_lossy().to_string())
+                .map_err(|err| invalid(&source, err.to_string()))?;
+            identities.extend(
+                file.into_identities()
+                    .map_err(|err| invalid(&source, err.to_string()))?,
+            );
+        }
+        if let Ok(key) = std::env::var("PCTL_AGE_KEY") {
+            let file = IdentityFile::from_buffer(key.as_bytes())
+                .map_err(|err| invalid("in $PCTL_AGE_KEY", err.to_string()))?;
+            identities.extend(
+                file.into_identities()
+                    .map_err(|err| invalid("in $PCTL_AGE_KEY", err.to_string()))?,
+            );
+        }
+        if identities.is_empty() {
+            return Err(
+                "Encrypted input needs an age identity, pass --age-identity or set PCTL_AGE_KEY"
+                    .to_string(),
+            );
+        }
+        Ok(self.identities.get_or_init(|| identities))
+    }
+
+    pub fn decrypt_age(&self, ciphertext: &[u8]) -> Res<Vec<u8>> {
+        let decryptor = Decryptor::new_buffered(ArmoredReader::new(ciphertext))
+            .map_err(|err| err.to_string())?;
+        let mut reader = decryptor
+            .decrypt(self.identities()?.iter().map(|i| i.as_ref()))
+            .map_err(|err| err.to_string())?;
+        let mut plaintext = Vec::new();
+        reader
+            .read_to_end(&mut plaintext)
+            .map_err(|err| err.to_string())?;
+        Ok(plaintext)
+    }
+
+    /// Reads a file, decrypting it in memory when it is age encrypted or a SOPS file.
+    /// SOPS files keep their format without the `sops` metadata.
+    pub fn read(&self, path: &Path) -> Res<(String, Format)> {
+        let context = |err: String| format!("Cannot read '{}': {}", path.display(), err);
+        let bytes = fs::read(path).map_err(|err| context(err.to_string()))?;
+
+        if bytes.starts_with(AGE_BINARY) || bytes.starts_with(AGE_ARMORED) {
+            let plaintext = self.decrypt_age(&bytes).map_err(context)?;
+            let text = String::from_utf8(plaintext)
+                .map_err(|_| context("decrypted content is not UTF-8".to_string()))?;
+            return Ok((text, Format::Text));
+        }
+
+        let text = String::from_utf8(bytes).map_err(|_| context("not UTF-8".to_string()))?;
+        let has_line = |prefix: &str| text.lines().any(|line| line.starts_with(prefix));
+        if has_line("sops_mac=") && (has_line("sops_version=") || has_line("sops_age__")) {
+            let (data, metadata) = sops_dotenv(&text);
+            let data = self.decrypt_sops(data, &metadata).map_err(context)?;
+            return Ok((dotenv_content(&data), Format::Dotenv));
+        }
+        if text.contains("sops") {
+            if let Ok(Yaml::Mapping(mut data)) = serde_yaml::from_str::<Yaml>(&text) {
+                if let Some(metadata) = data.remove("sops").filter(is_sops_metadata) {
+                    let data = self.decrypt_sops(data, &metadata).map_err(context)?;
+                    return if text.trim_start().starts_with('{') {
+                        serde_json::to_string_pretty(&data)
+                            .map(|json| (json, Format::Json))
+                            .map_err(|err| context(err.to_string()))
+                    } else {
+                        serde_yaml::to_string(&data)
+                            .map(|yaml| (yaml, Format::Yaml))
+                            .map_err(|err| context(err.to_string()))
+                    };
+                }
+            }
+        }
+        Ok((text, Format::Text))
+    }
+
+    fn sops_key(&self, metadata: &Yaml) -> Res<Vec<u8>> {
+        let encrypted_keys: Vec<&str> = metadata
+            .get("age")
+            .and_then(Yaml::as_sequence)
+            .into_iter()
+            .flatten()
+            .filter_map(|entry| entry.get("enc").and_then(Yaml::as_str))
+            .collect();
+        if encrypted_keys.is_empty() {
+            return Err("SOPS file has no age recipients".to_string());
+        }
+        encrypted_keys
+            .iter()
+            .find_map(|enc| self.decrypt_age(enc.as_bytes()).ok())
+            .ok_or_else(|| "No age identity can decrypt the SOPS data key".to_string())
+    }
+
+    /// Decrypts all `ENC[...]` values and verifies the MAC of the document
+    fn decrypt_sops(&self, mut data: Mapping, metadata: &Yaml) -> Res<Mapping> {
+        let key = self.sops_key(metadata)?;
+        let only_encrypted = metadata
+            .get("mac_only_encrypted")
+            .and_then(scalar)
+            .is_some_and(|v| v == "true");
+        let mut hash = Sha512::new();
+        let mut path = Vec::new();
+        for (name, value) in data.iter_mut() {
+            path.push(scalar(name).unwrap_or_default());
+            decrypt_tree(&key, value, &mut path, &mut hash, only_encrypted)?;
+            path.pop();
+        }
+
+        let mac = metadata
+            .get("mac")
+            .and_then(Yaml::as_str)
+            .ok_or("SOPS file has no MAC")?;
+        let modified = metadata
+            .get("lastmodified")
+            .and_then(scalar)
+            .unwrap_or_default();
+        let expected = decrypt_value(&key, mac, &modified)?;
+        let actual: String = hash
+            .finalize()
+            .iter()
+            .map(|b| format!("{:02X}", b))
+            .collect();
+        if scalar(&expected).unwrap_or_default() != actual {
+            return Err("SOPS MAC mismatch, the file was modified".to_string());
+        }
+        Ok(data)
+    }
+}
+
+/// Whether a top level `sops` key holds SOPS metadata, plain files may use the key too
+fn is_sops_metadata(metadata: &Yaml) -> bool {
+    metadata.get("mac").is_some()
+        && (metadata.get("age").is_some() || metadata.get("version").is_some())
+}
+
+/// Bytes of a value as SOPS hashes them for the MAC
+fn sops_bytes(value: &Yaml) -> Vec<u8> {
+    match value {
+        Yaml::Bool(true) => b"True".to_vec(),
+        Yaml::Bool(false) => b"False".to_vec(),
+        value => scalar(value).unwrap_or_default().into_bytes(),
+    }
+}
+
+fn decrypt_tree(
+    key: &[u8],
+    value: &mut Yaml,
+    path: &mut Vec<String>,
+    hash: &mut Sha512,
+    only_encrypted: bool,
+) -> Res<()> {
+    match value {
+        Yaml::Mapping(map) => {
+            for (name, value) in map.iter_mut() {
+                path.push(scalar(name).unwrap_or_default());
+                decrypt_tree(key, value, path, hash, only_encrypted)?;
+                path.pop();
+            }
+        }
+        Yaml::Sequence(items) => {
+            for item in items {
+                decrypt_tree(key, item, path, hash, only_encrypted)?;
+            }
+        }
+        Yaml::String(text) if text.starts_with("ENC[") => {
+            let aad = format!("{}:", path.join(":"));
+            *value = decrypt_value(key, text, &aad)?;
+            hash.update(sops_bytes(value));
+        }
+        value => {
+            if !only_encrypted {
+                hash.update(sops_bytes(value));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Decrypts `ENC[AES256_GCM,data:...,iv:...,tag:...,type:...]` into a value of its type
+fn decrypt_value(key: &[u8], value: &str, aad: &str) -> Res<Yaml> {
+    use base64::engine::general_purpose;
+    use base64::Engine as _;
+
+    let invalid = || format!("Invalid SOPS value '{}'", value);
+    let fields = value
+        .strip_prefix("ENC[AES256_GCM,")
+        .and_then(|v| v.strip_suffix(']'))
+        .ok_or_else(invalid)?;
+    let field = |name: &str| {
+        fields
+            .split(',')
+            .find_map(|f| f.strip_prefix(name)?.strip_prefix(':'))
+            .ok_or_else(invalid)
+    };
+    let decode = |name: &str| {
+        general_purpose::STANDARD
+            .decode(field(name)?)
+            .map_err(|_| invalid())
+    };
+
+    let mut ciphertext = decode("data")?;
+    ciphertext.extend(decode("tag")?);
+    let iv = decode("iv")?;
+    if iv.len() != 32 {
+        return Err(invalid());
+    }
+    let cipher = SopsCipher::new_from_slice(key).map_err(|_| "Invalid SOPS data key")?;
+    let plaintext = cipher
+        .decrypt(
+            Nonce::from_slice(&iv),
+            Payload {
+                msg: &ciphertext,
+                aad: aad.as_bytes(),
+            },
+        )
+        .map_err(|_| format!("Cannot decrypt SOPS value of '{}'", aad))?;
+    let plaintext = String::from_utf8(plaintext).map_err(|_| invalid())?;
+
+    Ok(match field("type")? {
+        "int" | "float" => serde_yaml::from_str(&plaintext).map_err(|_| invalid())?,
+        "bool" => Yaml::Bool(plaintext.eq_ignore_ascii_case("true")),
+        _ => Yaml::String(plaintext),
+    })
+}
+
+/// Splits a SOPS dotenv file into its values and metadata, metadata keys are flattened
+/// like `sops_age__list_0__map_enc`
+fn sops_dotenv(text: &str) -> (Mapping, Yaml) {
+    let mut data = Mapping::new();
+    let mut metadata = Mapping::new();
+    let mut recipients: Vec<Mapping> = Vec::new();
+    for line in text.lines() {
+        let Some((key, value)) = line.split_once('=') else {
+            continue;
+        };
+        if line.starts_with('#') {
+            continue;
+        }
+        let value = value.replace("\\n", "\n");
+        let Some(key) = key.strip_prefix("sops_") else {
+            data.insert(key.into(), value.into());
+            continue;
+        };
+        match key
+            .strip_prefix("age__list_")
+            .and_then(|rest| rest.split_once("__map_"))
+        {
+            Some((index, field)) => {
+                let index: usize = index.parse().unwrap_or_default();
+                if recipients.len() <= index {
+                    recipients.resize(index + 1, Mapping::new());
+                }
+                recipients[index].insert(field.into(), value.into());
+            }
+            None => {
+                metadata.insert(key.into(), value.into());
+            }
+        }
+    }
+    metadata.insert(
+        "age".into(),
+        Yaml::Sequence(recipients.into_iter().map(Yaml::Mapping).collect()),
+    );
+    (data, Yaml::Mapping(metadata))
+}
+
+/// Dotenv lines with double quoted values, as read by the envfile parser
+fn dotenv_content(data: &Mapping) -> String {
+    data.iter()
+        .map(|(key, value)| {
+            let value = scalar(value)
+                .unwrap_or_default()
+                .replace('\\', "\\\\")
+                .replace('"', "\\\"")
+                .replace('$', "\\$")
+                .replace('\n', "\\n");
+            format!("{}=\"{}\"\n", scalar(key).unwrap_or_default(), value)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::*;
+
+    fn fixture(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("testdata/sops")
+            .join(name)
+    }
+
+    fn keyring(key: &str) -> Keyring {
+        Keyring::new(Some(fixture(key)))
+    }
+
+    /// Writes a file to the temporary directory, unique per test process
+    fn temp_file(name: &str, content: &[u8]) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("pctl-{}-{}", std::process::id(), name));
+        fs::write(&path, content).unwrap();
+        path
+    }
+
+    /// Copy of a fixture with the first occurrence of `from` replaced
+    fn tampered(name: &str, copy: &str, from: &str, to: &str) -> PathBuf {
+        let content = fs::read_to_string(fixture(name)).unwrap();
+        assert!(content.contains(from));
+        temp_file(copy, content.replacen(from, to, 1).as_bytes())
+    }
+
+    #[test]
+    fn decrypts_sops_yaml() {
+        let (text, format) = keyring("key.txt").read(&fixture("secrets.yaml")).unwrap();
+        let data: Yaml = serde_yaml::from_str(&text).unwrap();
+
+        assert_eq!(format, Format::Yaml);
+        assert_eq!(data["db"]["user"], Yaml::from("app"));
+        assert_eq!(data["db"]["password"], Yaml::from("hunter2"));
+        assert_eq!(data["db"]["port"], Yaml::from(5432));
+        assert_eq!(data["db"]["tls"], Yaml::Bool(true));
+        assert_eq!(data["hosts"][1], Yaml::from("b.internal"));
+        assert_eq!(data["note_unencrypted"], Yaml::from("visible"));
+        assert!(data.get("sops").is_none());
+    }
+
+    #[test]
+    fn decrypts_sops_json() {
+        let (text, format) = keyring("key.txt").read(&fixture("secrets.json")).unwrap();
+        let data: serde_json::Value = serde_json::from_str(&text).unwrap();
+
+        assert_eq!(format, Format::Json);
+        assert_eq!(data["db"]["password"], "hunter2");
+        assert_eq!(data["db"]["port"], 5432);
+        assert_eq!(data["db"]["tls"], true);
+        assert_eq!(data["hosts"][0], "a.internal");
+        assert!(data.get("sops").is_none());
+    }
+
+    #[test]
+    fn decrypts_sops_dotenv() {
+        let (text, format) = keyring("key.txt").read(&fixture("secrets.env")).unwrap();
+
+        assert_eq!(format, Format::Dotenv);
+        assert_eq!(
+            text,
+            "DB_PASSWORD=\"hunter2\"\nGREETING=\"hello\\nworld\"\n"
+        );
+    }
+
+    #[test]
+    fn rejects_tampered_sops_values() {
+        let keys = keyring("key.txt");
+
+        // unencrypted values are only covered by the MAC
+        let path = tampered(
+            "secrets.yaml",
+            "unencrypted.yaml",
+            "note_unencrypted: visible",
+            "note_unencrypted: other",
+        );
+        let err = keys.read(&path).unwrap_err();
+        assert!(
+            err.ends_with("SOPS MAC mismatch, the file was modified"),
+            "{}",
+            err
+        );
+
+        // encrypted values are bound to their path
+        let content = fs::read_to_string(fixture("secrets.env")).unwrap();
+        let value = |key: &str| {
+            content
+                .lines()
+                .find_map(|line| line.strip_prefix(key))
+                .unwrap()
+                .to_string()
+        };
+        let swapped = content.replacen(&value("GREETING="), &value("DB_PASSWORD="), 1);
+        let path = temp_file("swapped.env", swapped.as_bytes());
+        let err = keys.read(&path).unwrap_err();
+        assert!(
+            err.ends_with("Cannot decrypt SOPS value of 'GREETING:'"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn rejects_tampered_sops_mac() {
+        let keys = keyring("key.txt");
+
+        let path = tampered(
+            "secrets.env",
+            "mac.env",
+            "sops_mac=ENC[AES256_GCM,data:",
+            "sops_mac=ENC[AES256_GCM,data:AAAA",
+        );
+        let err = keys.read(&path).unwrap_err();
+        assert!(
+            err.ends_with("Cannot decrypt SOPS value of '2024-05-01T12:00:00Z'"),
+            "{}",
+            err
+        );
+
+        // the MAC is bound to the modification time
+        let path = tampered(
+            "secrets.json",
+            "modified.json",
+            "2024-05-01T12:00:00Z",
+            "2024-05-01T12:00:01Z",
+        );
+        let err = keys.read(&path).unwrap_err();
+        assert!(
+            err.ends_with("Cannot decrypt SOPS value of '2024-05-01T12:00:01Z'"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn wrong_key_cannot_decrypt_sops_files() {
+        for name in ["secrets.yaml", "secrets.json", "secrets.env"] {
+            let err = keyring("other-key.txt").read(&fixture(name)).unwrap_err();
+            assert!(
+                err.ends_with("No age identity can decrypt the SOPS data key"),
+                "{}",
+                err
+            );
+        }
+    }
+
+    #[test]
+    fn plain_files_with_a_sops_key_are_not_decrypted() {
+        let yaml = "sops: true\nname: app\n";
+        let path = temp_file("plain.yaml", yaml.as_bytes());
+        assert_eq!(
+            keyring("key.txt").read(&path).map(|(text, _)| text),
+            Ok(yaml.to_string())
+        );
+
+        let dotenv = "sops_version=3\nNAME=app\n";
+        let path = temp_file("plain.env", dotenv.as_bytes());
+        assert_eq!(
+            keyring("key.txt").read(&path).map(|(text, _)| text),
+            Ok(dotenv.to_string())
+        );
+    }
+
+    #[test]
+    fn decrypts_age_files() {
+        let key = fs::read_to_string(fixture("key.txt")).unwrap();
+        let identity = key.lines().find(|l| !l.starts_with('#')).unwrap();
+        let recipient = age::x25519::Identity::from_str(identity)
+            .unwrap()
+            .to_public();
+        let ciphertext = age::encrypt(&recipient, b"hunter2\n").unwrap();
+        let path = temp_file("password.age", &ciphertext);
+
+        assert_eq!(
+            keyring("key.txt").read(&path).map(|(text, _)| text),
+            Ok("hunter2\n".to_string())
+        );
+        assert!(keyring("other-key.txt").read(&path).is_err());
+    }
+}
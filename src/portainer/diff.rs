@@ -0,0 +1,35 @@
+use std::collections::BTreeMap;
+
+use similar::TextDiff;
+
+/// Unified diff lines from `old` to `new`, empty when both are equal
+pub fn unified(old: &str, new: &str, old_name: &str, new_name: &str) -> Vec<String> {
+    if old == new {
+        return Vec::new();
+    }
+    TextDiff::from_lines(old, new)
+        .unified_diff()
+        .context_radius(3)
+        .header(old_name, new_name)
+        .to_string()
+        .lines()
+        .map(str::to_string)
+        .collect()
+}
+
+/// Names of changed variables prefixed by `+` when added, `-` when removed and `~` when
+/// changed, values are left out as they often hold secrets
+pub fn env_changes(old: &BTreeMap<String, String>, new: &BTreeMap<String, String>) -> Vec<String> {
+    let mut changes = Vec::new();
+    for (key, value) in new {
+        match old.get(key) {
+            None => changes.push(format!("+{}", key)),
+            Some(current) if current != value => changes.push(format!("~{}", key)),
+            _ => (),
+        }
+    }
+    for key in old.keys().filter(|key| !new.contains_key(*key)) {
+        changes.push(format!("-{}", key));
+    }
+    changes
+}
@@ -0,0 +1,311 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+
+use serde_yaml::Value as Yaml;
+
+use super::commands::InlineEnv;
+use super::compose::scalar;
+use super::decrypt::{Format, Keyring};
+use super::Res;
+
+/// Variables of a stack coming from one place, like an envfile or `-e` flags
+pub struct EnvSource {
+    pub name: String,
+    pub vars: Vec<InlineEnv>,
+}
+impl EnvSource {
+    pub fn new(name: &str, vars: Vec<InlineEnv>) -> EnvSource {
+        EnvSource {
+            name: name.to_string(),
+            vars,
+        }
+    }
+
+    /// Variables of the process environment whose names start with `prefix`, names are
+    /// kept as they are
+    pub fn from_prefix(prefix: &str) -> EnvSource {
+        let mut vars: Vec<InlineEnv> = std::env::vars()
+            .filter(|(key, _)| key.starts_with(prefix))
+            .map(|(key, value)| InlineEnv(key, value))
+            .collect();
+        vars.sort_by(|a, b| a.0.cmp(&b.0));
+        EnvSource::new(&format!("process env {}*", prefix), vars)
+    }
+
+    /// Reads a dotenv file, decrypting it when encrypted. SOPS YAML and JSON files are
+    /// read as a mapping of variables.
+    pub fn read(path: &Path, keys: &Keyring) -> Res<EnvSource> {
+        let name = path.to_string_lossy();
+        let invalid = |err: String| format!("Invalid envfile '{}': {}", name, err);
+        let (content, format) = keys.read(path)?;
+        let vars = match format {
+            Format::Yaml | Format::Json => {
+                let Ok(Yaml::Mapping(map)) = serde_yaml::from_str::<Yaml>(&content) else {
+                    return Err(invalid("expected a mapping".to_string()));
+                };
+                map.iter()
+                    .map(|(key, value)| match (scalar(key), scalar(value)) {
+                        (Some(key), Some(value)) => Ok(InlineEnv(key, value)),
+                        _ => Err(invalid("values must be scalars".to_string())),
+                    })
+                    .collect::<Res<Vec<InlineEnv>>>()?
+            }
+            Format::Text | Format::Dotenv => parse(&content).map_err(invalid)?,
+        };
+        Ok(EnvSource::new(&name, vars))
+    }
+}
+
+/// Variables of all sources, later sources take precedence over earlier ones. When
+/// strict, keys set to different values by several sources are an error listing them.
+pub fn merge(sources: Vec<EnvSource>, strict: bool) -> Res<Vec<InlineEnv>> {
+    let mut merged: Vec<InlineEnv> = Vec::new();
+    let mut origins: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    let mut conflicts = BTreeSet::new();
+    for source in sources {
+        for InlineEnv(key, value) in source.vars {
+            match merged.iter_mut().find(|var| var.0 == key) {
+                Some(var) => {
+                    if var.1 != value {
+                        conflicts.insert(key.to_string());
+                    }
+                    var.1 = value;
+                }
+                None => merged.push(InlineEnv(key.to_string(), value)),
+            }
+            origins
+                .entry(key)
+                .or_default()
+                .push(source.name.to_string());
+        }
+    }
+
+    if strict && !conflicts.is_empty() {
+        let keys: Vec<String> = conflicts
+            .iter()
+            .map(|key| format!("{} ({})", key, origins[key].join(", ")))
+            .collect();
+        return Err(format!("Conflicting env keys: {}", keys.join("; ")));
+    }
+    Ok(merged)
+}
+
+fn valid_key(key: &str) -> bool {
+    let mut chars = key.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-'))
+}
+
+/// Reads a quoted value starting after its opening quote, continuing on the next lines
+/// until the closing quote. Returns the value and the text after the closing quote.
+fn quoted<'a>(
+    quote: char,
+    mut text: &'a str,
+    lines: &mut impl Iterator<Item = (usize, &'a str)>,
+) -> Res<(String, &'a str)> {
+    let mut value = String::new();
+    loop {
+        let mut chars = text.char_indices();
+        while let Some((index, c)) = chars.next() {
+            match c {
+                c if c == quote => return Ok((value, &text[index + 1..])),
+                '\\' if quote == '"' => match chars.next() {
+                    Some((_, 'n')) => value.push('\n'),
+                    Some((_, 'r')) => value.push('\r'),
+                    Some((_, 't')) => value.push('\t'),
+                    Some((_, c @ ('\\' | '"' | '$'))) => value.push(c),
+                    Some((_, c)) => {
+                        value.push('\\');
+                        value.push(c);
+                    }
+                    None => value.push('\\'),
+                },
+                c => value.push(c),
+            }
+        }
+        let Some((_, line)) = lines.next() else {
+            return Err(format!("missing closing {}", quote));
+        };
+        value.push('\n');
+        text = line;
+    }
+}
+
+/// Parses dotenv content: `KEY=value` lines with an optional `export` prefix, `#`
+/// comments, single quoted literal values and double quoted values with escapes, both
+/// of which may span several lines
+pub fn parse(content: &str) -> Res<Vec<InlineEnv>> {
+    let mut vars = Vec::new();
+    let mut lines = content.lines().enumerate();
+    while let Some((index, line)) = lines.next() {
+        let context = |err: String| format!("line {}: {}", index + 1, err);
+        let line = line.trim_start();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = match line.strip_prefix("export") {
+            Some(rest) if rest.starts_with(char::is_whitespace) => rest.trim_start(),
+            _ => line,
+        };
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| context("expected `KEY=VALUE`".to_string()))?;
+        let key = key.trim();
+        if !valid_key(key) {
+            return Err(context(format!("invalid key '{}'", key)));
+        }
+
+        let value = value.trim_start();
+        let value = match value.chars().next() {
+            Some(quote @ ('"' | '\'')) => {
+                let (value, rest) = quoted(quote, &value[1..], &mut lines).map_err(context)?;
+                let rest = rest.trim();
+                if !rest.is_empty() && !rest.starts_with('#') {
+                    return Err(context(format!("unexpected '{}' after quoted value", rest)));
+                }
+                value
+            }
+            _ => {
+                let end = value
+                    .find(" #")
+                    .or_else(|| value.find("\t#"))
+                    .unwrap_or(value.len());
+                value[..end].trim_end().to_string()
+            }
+        };
+        vars.push(InlineEnv(key.to_string(), value));
+    }
+    Ok(vars)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars(pairs: &[(&str, &str)]) -> Vec<InlineEnv> {
+        pairs
+            .iter()
+            .map(|(key, value)| InlineEnv(key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn parses_plain_values_and_comments() {
+        let content =
+            "# database\n\nHOST=db.internal\nPORT = 5432 # default\nEMPTY=\nURL=http://x/#anchor\n";
+
+        assert_eq!(
+            parse(content),
+            Ok(vars(&[
+                ("HOST", "db.internal"),
+                ("PORT", "5432"),
+                ("EMPTY", ""),
+                ("URL", "http://x/#anchor"),
+            ]))
+        );
+    }
+
+    #[test]
+    fn parses_export_prefix() {
+        let content = "export A=1\nexport\tB=2\nexport   C=3\nexporter=4\n";
+
+        assert_eq!(
+            parse(content),
+            Ok(vars(&[
+                ("A", "1"),
+                ("B", "2"),
+                ("C", "3"),
+                ("exporter", "4")
+            ]))
+        );
+    }
+
+    #[test]
+    fn parses_quoted_values() {
+        let content = r#"SINGLE='lit\n $HOME # kept'
+DOUBLE="tab\tquote\" dollar\$ slash\\ other\q" # comment
+SPACED="  padded  "
+"#;
+
+        assert_eq!(
+            parse(content),
+            Ok(vars(&[
+                ("SINGLE", r"lit\n $HOME # kept"),
+                ("DOUBLE", "tab\tquote\" dollar$ slash\\ other\\q"),
+                ("SPACED", "  padded  "),
+            ]))
+        );
+    }
+
+    #[test]
+    fn parses_multiline_values() {
+        let content = "KEY=\"-----BEGIN-----\nabc\n-----END-----\"\nCERT='line1\nline2'\nNEXT=1\n";
+
+        assert_eq!(
+            parse(content),
+            Ok(vars(&[
+                ("KEY", "-----BEGIN-----\nabc\n-----END-----"),
+                ("CERT", "line1\nline2"),
+                ("NEXT", "1"),
+            ]))
+        );
+    }
+
+    #[test]
+    fn reports_invalid_lines() {
+        assert_eq!(
+            parse("A=1\nnot a variable\n"),
+            Err("line 2: expected `KEY=VALUE`".to_string())
+        );
+        assert_eq!(
+            parse("1KEY=1"),
+            Err("line 1: invalid key '1KEY'".to_string())
+        );
+        assert_eq!(
+            parse("A=\"open\nB=2\n"),
+            Err("line 1: missing closing \"".to_string())
+        );
+        assert_eq!(
+            parse("A='x' y"),
+            Err("line 1: unexpected 'y' after quoted value".to_string())
+        );
+    }
+
+    #[test]
+    fn later_sources_win() {
+        let sources = vec![
+            EnvSource::new("base.env", vars(&[("A", "1"), ("B", "1")])),
+            EnvSource::new("prod.env", vars(&[("B", "2"), ("C", "2")])),
+            EnvSource::new("-e", vars(&[("C", "3")])),
+        ];
+
+        assert_eq!(
+            merge(sources, false),
+            Ok(vars(&[("A", "1"), ("B", "2"), ("C", "3")]))
+        );
+    }
+
+    #[test]
+    fn strict_merge_reports_conflicts_with_their_sources() {
+        let sources = || {
+            vec![
+                EnvSource::new("base.env", vars(&[("A", "1"), ("B", "1"), ("C", "1")])),
+                EnvSource::new("prod.env", vars(&[("A", "2"), ("B", "1")])),
+                EnvSource::new("-e", vars(&[("C", "3")])),
+            ]
+        };
+
+        assert_eq!(
+            merge(sources(), true),
+            Err("Conflicting env keys: A (base.env, prod.env); C (base.env, -e)".to_string())
+        );
+        // repeating a key with the same value is no conflict
+        let sources = vec![
+            EnvSource::new("base.env", vars(&[("A", "1")])),
+            EnvSource::new("prod.env", vars(&[("A", "1")])),
+        ];
+        assert_eq!(merge(sources, true), Ok(vars(&[("A", "1")])));
+    }
+}
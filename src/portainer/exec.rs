@@ -0,0 +1,394 @@
+use std::io::{self, IsTerminal, Read, Write};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+
+use crossterm::terminal;
+
+use super::client::{Duplex, PortainerClient, Received};
+use super::logs::{Demuxer, StreamKind};
+use super::requests::raw_requests;
+use super::{Action, Res};
+
+/// Puts the local terminal in raw mode and restores it when dropped
+struct RawMode;
+impl RawMode {
+    fn enable() -> Res<RawMode> {
+        terminal::enable_raw_mode().map_err(|x| x.to_string())?;
+        Ok(RawMode)
+    }
+}
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+/// Stdin is read on its own thread, as reads block and the websocket must be polled meanwhile
+fn spawn_stdin() -> Receiver<Vec<u8>> {
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let mut stdin = io::stdin();
+        let mut buf = [0u8; 1024];
+        loop {
+            match stdin.read(&mut buf) {
+                Ok(0) | Err(_) => break,
+                Ok(n) => {
+                    if tx.send(buf[..n].to_vec()).is_err() {
+                        break;
+                    }
+                }
+            }
+        }
+    });
+    rx
+}
+
+pub fn tty_available() -> bool {
+    io::stdin().is_terminal() && io::stdout().is_terminal()
+}
+
+/// Local side of an attached exec, the user's terminal unless tests swap it out
+struct Terminal {
+    input: Receiver<Vec<u8>>,
+    stdout: Box<dyn Write>,
+    stderr: Box<dyn Write>,
+    size: Box<dyn Fn() -> Option<(u16, u16)>>,
+}
+impl Terminal {
+    fn local() -> Terminal {
+        Terminal {
+            input: spawn_stdin(),
+            stdout: Box::new(io::stdout()),
+            stderr: Box::new(io::stderr()),
+            size: Box::new(|| terminal::size().ok()),
+        }
+    }
+}
+
+/// An exec instance created on a container, ready to be attached
+pub struct Exec<'a> {
+    pub client: &'a dyn PortainerClient,
+    pub endpoint: i32,
+    /// Hostname of the node running the container, unknown outside of swarm
+    pub node: Option<String>,
+    pub id: String,
+    pub tty: bool,
+}
+impl<'a> Exec<'a> {
+    fn resize(&self, (width, height): (u16, u16)) -> Action {
+        raw_requests::resize_exec(self.endpoint, self.node.as_deref(), &self.id, width, height)
+            .send(self.client)
+            .map(|_| ())
+    }
+
+    fn write_output(&self, local: &mut Terminal, demuxer: &mut Demuxer, data: &[u8]) -> Action {
+        if self.tty {
+            local.stdout.write_all(data).map_err(|x| x.to_string())?;
+            return local.stdout.flush().map_err(|x| x.to_string());
+        }
+        for (kind, frame) in demuxer.push(data) {
+            match kind {
+                StreamKind::Stdout => local.stdout.write_all(&frame),
+                StreamKind::Stderr => local.stderr.write_all(&frame),
+            }
+            .map_err(|x| x.to_string())?;
+        }
+        Ok(())
+    }
+
+    /// Attaches the user's terminal to the channel, in raw mode for TTYs
+    pub fn run(&self, channel: &mut dyn Duplex) -> Action {
+        let _raw = if self.tty {
+            Some(RawMode::enable()?)
+        } else {
+            None
+        };
+        self.attach(channel, &mut Terminal::local())
+    }
+
+    /// Pumps data between the local terminal and the channel until the remote side closes it
+    fn attach(&self, channel: &mut dyn Duplex, local: &mut Terminal) -> Action {
+        let mut stdin_open = true;
+        let mut demuxer = Demuxer::default();
+        let mut size = None;
+
+        loop {
+            match channel.receive()? {
+                Received::Data(data) => self.write_output(local, &mut demuxer, &data)?,
+                Received::Idle => (),
+                Received::Closed => break,
+            }
+
+            while stdin_open {
+                match local.input.try_recv() {
+                    Ok(data) => channel.send(&data)?,
+                    Err(TryRecvError::Empty) => break,
+                    Err(TryRecvError::Disconnected) => stdin_open = false,
+                }
+            }
+
+            if self.tty {
+                let current = (local.size)();
+                if let Some(dimensions) = current.filter(|_| current != size) {
+                    size = current;
+                    self.resize(dimensions)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn exit_code(&self) -> Res<Option<i64>> {
+        raw_requests::inspect_exec(self.endpoint, self.node.as_deref(), &self.id)
+            .send(self.client)
+            .map(|inspect| {
+                if inspect.running {
+                    None
+                } else {
+                    inspect.exit_code
+                }
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::collections::VecDeque;
+    use std::rc::Rc;
+
+    use serde_json::{json, Value as Json};
+
+    use super::*;
+    use crate::portainer::client::PortainerRequestRaw;
+
+    /// Answers exec inspects and records every request
+    struct FakeClient {
+        requests: RefCell<Vec<String>>,
+        exit_code: i64,
+    }
+    impl FakeClient {
+        fn new(exit_code: i64) -> FakeClient {
+            FakeClient {
+                requests: RefCell::new(Vec::new()),
+                exit_code,
+            }
+        }
+    }
+    impl PortainerClient for FakeClient {
+        fn send(&self, req: &PortainerRequestRaw) -> Res<Json> {
+            let mut description = req.path().to_string();
+            if let (Some(w), Some(h)) = (req.query("w"), req.query("h")) {
+                description.push_str(&format!("?w={}&h={}", w, h));
+            }
+            self.requests.borrow_mut().push(description);
+            if req.path().ends_with("/json") {
+                Ok(json!({"Running": false, "ExitCode": self.exit_code}))
+            } else {
+                Ok(Json::Null)
+            }
+        }
+        fn stream(&self, _: &PortainerRequestRaw) -> Res<Box<dyn Read + Send>> {
+            Err("Not supported".to_string())
+        }
+        fn connect(&self, _: &PortainerRequestRaw) -> Res<Box<dyn Duplex>> {
+            Err("Not supported".to_string())
+        }
+    }
+
+    /// Replays scripted messages, then reports the channel as closed
+    struct FakeDuplex {
+        incoming: VecDeque<Res<Received>>,
+        sent: Vec<Vec<u8>>,
+    }
+    impl FakeDuplex {
+        fn new(incoming: Vec<Res<Received>>) -> FakeDuplex {
+            FakeDuplex {
+                incoming: incoming.into(),
+                sent: Vec::new(),
+            }
+        }
+    }
+    impl Duplex for FakeDuplex {
+        fn receive(&mut self) -> Res<Received> {
+            self.incoming.pop_front().unwrap_or(Ok(Received::Closed))
+        }
+        fn send(&mut self, data: &[u8]) -> Action {
+            self.sent.push(data.to_vec());
+            Ok(())
+        }
+    }
+
+    #[derive(Clone, Default)]
+    struct Captured(Rc<RefCell<Vec<u8>>>);
+    impl Write for Captured {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+    impl Captured {
+        fn text(&self) -> String {
+            String::from_utf8(self.0.borrow().clone()).unwrap()
+        }
+    }
+
+    struct Local {
+        terminal: Terminal,
+        stdout: Captured,
+        stderr: Captured,
+    }
+
+    fn local(input: &[&str], size: Option<(u16, u16)>) -> Local {
+        let (tx, rx) = mpsc::channel();
+        for data in input {
+            tx.send(data.as_bytes().to_vec()).unwrap();
+        }
+        let (stdout, stderr) = (Captured::default(), Captured::default());
+        Local {
+            terminal: Terminal {
+                input: rx,
+                stdout: Box::new(stdout.clone()),
+                stderr: Box::new(stderr.clone()),
+                size: Box::new(move || size),
+            },
+            stdout,
+            stderr,
+        }
+    }
+
+    fn exec(client: &FakeClient, tty: bool) -> Exec<'_> {
+        Exec {
+            client,
+            endpoint: 1,
+            node: Some("node".to_string()),
+            id: "abc".to_string(),
+            tty,
+        }
+    }
+
+    fn frame(kind: u8, data: &str) -> Vec<u8> {
+        let mut frame = vec![kind, 0, 0, 0];
+        frame.extend_from_slice(&(data.len() as u32).to_be_bytes());
+        frame.extend_from_slice(data.as_bytes());
+        frame
+    }
+
+    #[test]
+    fn tty_output_is_written_raw_and_terminal_is_resized_once() {
+        let client = FakeClient::new(0);
+        let mut local = local(&[], Some((80, 24)));
+        let mut channel = FakeDuplex::new(vec![
+            Ok(Received::Data(b"$ ".to_vec())),
+            Ok(Received::Idle),
+            Ok(Received::Data(b"ls\r\n".to_vec())),
+        ]);
+
+        exec(&client, true)
+            .attach(&mut channel, &mut local.terminal)
+            .unwrap();
+
+        assert_eq!(local.stdout.text(), "$ ls\r\n");
+        assert_eq!(
+            *client.requests.borrow(),
+            vec!["/endpoints/1/docker/exec/abc/resize?w=80&h=24"]
+        );
+    }
+
+    #[test]
+    fn tty_without_size_is_not_resized() {
+        let client = FakeClient::new(0);
+        let mut local = local(&[], None);
+        let mut channel = FakeDuplex::new(vec![Ok(Received::Idle)]);
+
+        exec(&client, true)
+            .attach(&mut channel, &mut local.terminal)
+            .unwrap();
+
+        assert!(client.requests.borrow().is_empty());
+    }
+
+    #[test]
+    fn non_tty_output_is_demultiplexed() {
+        let client = FakeClient::new(0);
+        let mut local = local(&[], Some((80, 24)));
+        let out = frame(1, "out\n");
+        let err = frame(2, "err\n");
+        // frames may be split across messages
+        let mut channel = FakeDuplex::new(vec![
+            Ok(Received::Data(out[..5].to_vec())),
+            Ok(Received::Data([&out[5..], &err[..]].concat())),
+        ]);
+
+        exec(&client, false)
+            .attach(&mut channel, &mut local.terminal)
+            .unwrap();
+
+        assert_eq!(local.stdout.text(), "out\n");
+        assert_eq!(local.stderr.text(), "err\n");
+        assert!(client.requests.borrow().is_empty());
+    }
+
+    #[test]
+    fn stdin_is_forwarded_to_the_channel() {
+        let client = FakeClient::new(0);
+        let mut local = local(&["echo hi\n", "exit\n"], None);
+        let mut channel = FakeDuplex::new(vec![Ok(Received::Idle), Ok(Received::Idle)]);
+
+        exec(&client, false)
+            .attach(&mut channel, &mut local.terminal)
+            .unwrap();
+
+        assert_eq!(
+            channel.sent,
+            vec![b"echo hi\n".to_vec(), b"exit\n".to_vec()]
+        );
+    }
+
+    #[test]
+    fn channel_errors_end_the_session() {
+        let client = FakeClient::new(0);
+        let mut local = local(&[], None);
+        let mut channel = FakeDuplex::new(vec![Err("connection reset".to_string())]);
+
+        let result = exec(&client, false).attach(&mut channel, &mut local.terminal);
+
+        assert_eq!(result, Err("connection reset".to_string()));
+    }
+
+    #[test]
+    fn exit_code_is_read_after_close() {
+        let client = FakeClient::new(3);
+        let mut local = local(&[], None);
+        let mut channel = FakeDuplex::new(vec![Ok(Received::Closed)]);
+        let exec = exec(&client, false);
+
+        exec.attach(&mut channel, &mut local.terminal).unwrap();
+
+        assert_eq!(exec.exit_code(), Ok(Some(3)));
+        assert_eq!(
+            *client.requests.borrow(),
+            vec!["/endpoints/1/docker/exec/abc/json"]
+        );
+    }
+
+    #[test]
+    fn requests_target_the_node_only_when_known() {
+        let create = |node| raw_requests::create_exec(1, node, "c1", vec![], false);
+        let attach = |node| raw_requests::attach_exec(1, node, "abc");
+
+        assert_eq!(
+            create(Some("worker-1"))
+                .raw()
+                .header("X-PortainerAgent-Target"),
+            Some("worker-1")
+        );
+        assert_eq!(attach(Some("worker-1")).query("nodeName"), Some("worker-1"));
+        assert_eq!(create(None).raw().header("X-PortainerAgent-Target"), None);
+        assert_eq!(attach(None).query("nodeName"), None);
+        assert_eq!(attach(None).query("id"), Some("abc"));
+    }
+}
@@ -0,0 +1,443 @@
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+
+use super::Action;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StreamKind {
+    Stdout,
+    Stderr,
+}
+
+fn frame_header(header: [u8; 8]) -> (StreamKind, usize) {
+    let kind = match header[0] {
+        2 => StreamKind::Stderr,
+        _ => StreamKind::Stdout,
+    };
+    let size = u32::from_be_bytes([header[4], header[5], header[6], header[7]]);
+    (kind, size as usize)
+}
+
+/// Seconds since the unix epoch of RFC3339 timestamps like `2024-05-01T12:00:00Z` or
+/// `2024-05-01T14:00:00.5+02:00`, fractions of seconds are dropped
+pub fn parse_timestamp(value: &str) -> Option<i64> {
+    let number = |text: &str| -> Option<i64> {
+        text.bytes()
+            .all(|b| b.is_ascii_digit())
+            .then(|| text.parse().ok())
+            .flatten()
+    };
+    if value.len() < 20 || !value.is_char_boundary(19) {
+        return None;
+    }
+    let (datetime, zone) = value.split_at(19);
+    let bytes = datetime.as_bytes();
+    let separators = [(4, b'-'), (7, b'-'), (13, b':'), (16, b':')];
+    if separators.iter().any(|(i, c)| bytes[*i] != *c) || !b"Tt ".contains(&bytes[10]) {
+        return None;
+    }
+    let (year, month, day) = (
+        number(&datetime[..4])?,
+        number(&datetime[5..7])?,
+        number(&datetime[8..10])?,
+    );
+    let (hour, minute, second) = (
+        number(&datetime[11..13])?,
+        number(&datetime[14..16])?,
+        number(&datetime[17..19])?,
+    );
+    let valid = (1..=12).contains(&month)
+        && (1..=days_in_month(year, month)).contains(&day)
+        && hour < 24
+        && minute < 60
+        && second <= 60;
+    if !valid {
+        return None;
+    }
+
+    let zone = match zone.strip_prefix('.') {
+        Some(fraction) => {
+            let digits = fraction.find(|c: char| !c.is_ascii_digit())?;
+            if digits == 0 {
+                return None;
+            }
+            &fraction[digits..]
+        }
+        None => zone,
+    };
+    let offset = match zone {
+        "Z" | "z" => 0,
+        _ => {
+            let sign = match zone.as_bytes().first()? {
+                b'+' => 1,
+                b'-' => -1,
+                _ => return None,
+            };
+            let (hours, minutes) = zone[1..].split_once(':')?;
+            if hours.len() != 2 || minutes.len() != 2 {
+                return None;
+            }
+            sign * (number(hours)? * 3600 + number(minutes)? * 60)
+        }
+    };
+
+    let days = days_from_civil(year, month, day);
+    Some(days * 86400 + hour * 3600 + minute * 60 + second - offset)
+}
+
+fn days_in_month(year: i64, month: i64) -> i64 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Days since 1970-01-01 of a proleptic gregorian date
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+/// Push based variant of `LogReader` for streams that arrive in chunks, like websocket messages
+#[derive(Default)]
+pub struct Demuxer(Vec<u8>);
+impl Demuxer {
+    pub fn push(&mut self, data: &[u8]) -> Vec<(StreamKind, Vec<u8>)> {
+        self.0.extend_from_slice(data);
+        let mut frames = Vec::new();
+        while self.0.len() >= 8 {
+            let (kind, size) = frame_header(self.0[..8].try_into().unwrap());
+            if self.0.len() < 8 + size {
+                break;
+            }
+            let frame: Vec<u8> = self.0.drain(..8 + size).skip(8).collect();
+            frames.push((kind, frame));
+        }
+        frames
+    }
+}
+
+/// Reads docker log streams, which are multiplexed with 8 byte frame headers
+/// unless the container has a TTY attached
+pub struct LogReader {
+    inner: Box<dyn Read + Send>,
+    multiplexed: bool,
+}
+impl LogReader {
+    pub fn new(inner: Box<dyn Read + Send>, multiplexed: bool) -> LogReader {
+        LogReader { inner, multiplexed }
+    }
+
+    /// Reads exactly `buf.len()` bytes, returns false on a clean end of stream
+    fn fill(&mut self, buf: &mut [u8]) -> io::Result<bool> {
+        let mut read = 0;
+        while read < buf.len() {
+            match self.inner.read(&mut buf[read..])? {
+                0 if read == 0 => return Ok(false),
+                0 => return Err(io::ErrorKind::UnexpectedEof.into()),
+                n => read += n,
+            }
+        }
+        Ok(true)
+    }
+
+    pub fn next_frame(&mut self) -> io::Result<Option<(StreamKind, Vec<u8>)>> {
+        if self.multiplexed {
+            let mut header = [0u8; 8];
+            if !self.fill(&mut header)? {
+                return Ok(None);
+            }
+            let (kind, size) = frame_header(header);
+            let mut payload = vec![0u8; size];
+            if !self.fill(&mut payload)? {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            Ok(Some((kind, payload)))
+        } else {
+            let mut payload = vec![0u8; 4096];
+            let n = self.inner.read(&mut payload)?;
+            if n == 0 {
+                return Ok(None);
+            }
+            payload.truncate(n);
+            Ok(Some((StreamKind::Stdout, payload)))
+        }
+    }
+}
+
+/// Accumulates frames until complete lines are available, frames may split lines
+#[derive(Default)]
+struct LineBuffer(Vec<u8>);
+impl LineBuffer {
+    fn push(&mut self, data: &[u8]) -> Vec<String> {
+        self.0.extend_from_slice(data);
+        let mut lines = Vec::new();
+        while let Some(pos) = self.0.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = self.0.drain(..=pos).collect();
+            lines.push(
+                String::from_utf8_lossy(&line[..pos])
+                    .trim_end_matches('\r')
+                    .to_string(),
+            );
+        }
+        lines
+    }
+    fn flush(&mut self) -> Option<String> {
+        if self.0.is_empty() {
+            None
+        } else {
+            let line = String::from_utf8_lossy(&self.0).to_string();
+            self.0.clear();
+            Some(line)
+        }
+    }
+}
+
+/// A log line requested with `details`, optionally preceded by a timestamp:
+/// `[timestamp ]key=value,key=value message`
+struct LogLine<'a> {
+    timestamp: Option<&'a str>,
+    task_id: Option<&'a str>,
+    message: &'a str,
+}
+impl<'a> LogLine<'a> {
+    fn parse(line: &'a str, timestamps: bool) -> LogLine<'a> {
+        let (timestamp, rest) = match line.split_once(' ') {
+            Some((ts, rest)) if timestamps => (Some(ts), rest),
+            _ => (None, line),
+        };
+        let (details, message) = rest.split_once(' ').unwrap_or((rest, ""));
+        let task_id = details
+            .split(',')
+            .filter_map(|attr| attr.split_once('='))
+            .find(|(key, _)| *key == "com.docker.swarm.task.id")
+            .map(|(_, value)| value);
+
+        // without task details the whole rest is the message
+        let message = if task_id.is_some() { message } else { rest };
+
+        LogLine {
+            timestamp,
+            task_id,
+            message,
+        }
+    }
+}
+
+/// Writes service logs with each line prefixed by its task name
+pub struct LogPrinter {
+    service: String,
+    tasks: HashMap<String, String>,
+    timestamps: bool,
+}
+impl LogPrinter {
+    pub fn new(service: String, tasks: HashMap<String, String>, timestamps: bool) -> LogPrinter {
+        LogPrinter {
+            service,
+            tasks,
+            timestamps,
+        }
+    }
+
+    fn prefix(&self, task_id: Option<&str>) -> String {
+        match task_id {
+            Some(id) => self
+                .tasks
+                .get(id)
+                .cloned()
+                .unwrap_or_else(|| format!("{}.{}", self.service, id)),
+            None => self.service.to_string(),
+        }
+    }
+
+    fn write(&self, kind: StreamKind, line: &str) -> io::Result<()> {
+        let parsed = LogLine::parse(line, self.timestamps);
+        let prefix = self.prefix(parsed.task_id);
+        let output = match parsed.timestamp {
+            Some(ts) => format!("{} | {} {}", prefix, ts, parsed.message),
+            None => format!("{} | {}", prefix, parsed.message),
+        };
+        match kind {
+            StreamKind::Stdout => writeln!(io::stdout().lock(), "{}", output),
+            StreamKind::Stderr => writeln!(io::stderr().lock(), "{}", output),
+        }
+    }
+
+    pub fn print(&self, mut reader: LogReader) -> Action {
+        let mut stdout = LineBuffer::default();
+        let mut stderr = LineBuffer::default();
+
+        while let Some((kind, data)) = reader.next_frame().map_err(|x| x.to_string())? {
+            let buffer = match kind {
+                StreamKind::Stdout => &mut stdout,
+                StreamKind::Stderr => &mut stderr,
+            };
+            for line in buffer.push(&data) {
+                self.write(kind, &line).map_err(|x| x.to_string())?;
+            }
+        }
+        for (kind, mut buffer) in [(StreamKind::Stdout, stdout), (StreamKind::Stderr, stderr)] {
+            if let Some(line) = buffer.flush() {
+                self.write(kind, &line).map_err(|x| x.to_string())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(kind: u8, data: &str) -> Vec<u8> {
+        let mut frame = vec![kind, 0, 0, 0];
+        frame.extend_from_slice(&(data.len() as u32).to_be_bytes());
+        frame.extend_from_slice(data.as_bytes());
+        frame
+    }
+
+    #[test]
+    fn demuxer_waits_for_complete_frames() {
+        let mut demuxer = Demuxer::default();
+        let data = [frame(1, "out"), frame(2, "err")].concat();
+
+        assert!(demuxer.push(&data[..4]).is_empty());
+        assert!(demuxer.push(&data[4..9]).is_empty());
+        let frames = demuxer.push(&data[9..]);
+
+        assert_eq!(
+            frames,
+            vec![
+                (StreamKind::Stdout, b"out".to_vec()),
+                (StreamKind::Stderr, b"err".to_vec())
+            ]
+        );
+    }
+
+    #[test]
+    fn demuxer_handles_empty_frames() {
+        let frames = Demuxer::default().push(&frame(1, ""));
+
+        assert_eq!(frames.len(), 1);
+        assert!(frames[0].1.is_empty());
+    }
+
+    #[test]
+    fn reader_splits_multiplexed_frames() {
+        let data = [frame(2, "err\n"), frame(1, "out\n")].concat();
+        let mut reader = LogReader::new(Box::new(io::Cursor::new(data)), true);
+
+        assert_eq!(
+            reader.next_frame().unwrap(),
+            Some((StreamKind::Stderr, b"err\n".to_vec()))
+        );
+        assert_eq!(
+            reader.next_frame().unwrap(),
+            Some((StreamKind::Stdout, b"out\n".to_vec()))
+        );
+        assert!(reader.next_frame().unwrap().is_none());
+    }
+
+    #[test]
+    fn reader_fails_on_truncated_frames() {
+        let data = frame(1, "output");
+        let mut reader = LogReader::new(Box::new(io::Cursor::new(data[..10].to_vec())), true);
+
+        let err = reader.next_frame().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn reader_passes_tty_output_through() {
+        let mut reader = LogReader::new(Box::new(io::Cursor::new(b"raw\n".to_vec())), false);
+
+        assert_eq!(
+            reader.next_frame().unwrap(),
+            Some((StreamKind::Stdout, b"raw\n".to_vec()))
+        );
+        assert!(reader.next_frame().unwrap().is_none());
+    }
+
+    #[test]
+    fn line_buffer_joins_split_lines() {
+        let mut buffer = LineBuffer::default();
+
+        assert!(buffer.push(b"first ").is_empty());
+        assert_eq!(
+            buffer.push(b"line\r\nsecond\nthi"),
+            vec!["first line", "second"]
+        );
+        assert_eq!(buffer.flush(), Some("thi".to_string()));
+        assert_eq!(buffer.flush(), None);
+    }
+
+    #[test]
+    fn log_line_reads_task_details() {
+        let line = LogLine::parse(
+            "com.docker.swarm.node.id=n1,com.docker.swarm.task.id=t1 hello world",
+            false,
+        );
+
+        assert_eq!(line.timestamp, None);
+        assert_eq!(line.task_id, Some("t1"));
+        assert_eq!(line.message, "hello world");
+    }
+
+    #[test]
+    fn log_line_reads_timestamps() {
+        let line = LogLine::parse(
+            "2024-05-01T12:00:00.000000001Z com.docker.swarm.task.id=t1 hello",
+            true,
+        );
+
+        assert_eq!(line.timestamp, Some("2024-05-01T12:00:00.000000001Z"));
+        assert_eq!(line.task_id, Some("t1"));
+        assert_eq!(line.message, "hello");
+    }
+
+    #[test]
+    fn log_line_without_details_keeps_message() {
+        let line = LogLine::parse("plain message", false);
+
+        assert_eq!(line.task_id, None);
+        assert_eq!(line.message, "plain message");
+    }
+
+    #[test]
+    fn parses_rfc3339_timestamps() {
+        assert_eq!(parse_timestamp("1970-01-01T00:00:00Z"), Some(0));
+        assert_eq!(parse_timestamp("2024-05-01T12:00:00Z"), Some(1714564800));
+        assert_eq!(
+            parse_timestamp("2024-05-01T14:00:00+02:00"),
+            Some(1714564800)
+        );
+        assert_eq!(
+            parse_timestamp("2024-05-01T11:30:00.25-00:30"),
+            Some(1714564800)
+        );
+        assert_eq!(parse_timestamp("2024-02-29T00:00:00Z"), Some(1709164800));
+    }
+
+    #[test]
+    fn rejects_invalid_timestamps() {
+        for value in [
+            "",
+            "2024-05-01",
+            "2024-05-01T12:00:00",
+            "2024-13-01T12:00:00Z",
+            "2023-02-29T12:00:00Z",
+            "2024-05-01T24:00:00Z",
+            "2024-05-01T12:00:00.Z",
+            "2024-05-01T12:00:00+0200",
+            "2024/05/01T12:00:00Z",
+        ] {
+            assert_eq!(parse_timestamp(value), None, "{}", value);
+        }
+    }
+}
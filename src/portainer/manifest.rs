@@ -0,0 +1,348 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+use serde_yaml::Value as Yaml;
+
+use super::commands::{EndpointSelector, FileMapping, InlineEnv};
+use super::compose::{scalar, ComposeFile};
+use super::decrypt::Keyring;
+use super::env::{self, EnvSource};
+use super::protect::Protection;
+use super::secrets::is_reference;
+use super::Res;
+
+/// Endpoint selection in a manifest, exactly one criterion must be set
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EndpointSpec {
+    name: Option<String>,
+    id: Option<i32>,
+    name_glob: Option<String>,
+    name_regex: Option<String>,
+    #[serde(default)]
+    tags: Vec<String>,
+    #[serde(default)]
+    tag_ids: Vec<i32>,
+    /// Tags match endpoints having any of them, instead of all of them
+    #[serde(default)]
+    any_tag: bool,
+    group: Option<String>,
+    group_id: Option<i32>,
+}
+impl EndpointSpec {
+    fn selector(&self) -> Res<EndpointSelector> {
+        let mut selectors = Vec::new();
+        if let Some(name) = &self.name {
+            selectors.push(EndpointSelector::ByName(name.to_string()));
+        }
+        if let Some(id) = self.id {
+            selectors.push(EndpointSelector::ById(id));
+        }
+        if let Some(glob) = &self.name_glob {
+            let pattern =
+                glob::Pattern::new(glob).map_err(|x| format!("Invalid glob '{}': {}", glob, x))?;
+            selectors.push(EndpointSelector::ByNameGlob(pattern));
+        }
+        if let Some(regex) = &self.name_regex {
+            let regex = regex::Regex::new(regex)
+                .map_err(|x| format!("Invalid regex '{}': {}", regex, x))?;
+            selectors.push(EndpointSelector::ByNameRegex(regex));
+        }
+        if !self.tags.is_empty() {
+            selectors.push(EndpointSelector::ByTags {
+                tags: self.tags.clone(),
+                any: self.any_tag,
+            });
+        }
+        if !self.tag_ids.is_empty() {
+            selectors.push(EndpointSelector::ByTagIds {
+                ids: self.tag_ids.clone(),
+                any: self.any_tag,
+            });
+        }
+        if let Some(group) = &self.group {
+            selectors.push(EndpointSelector::ByGroup(group.to_string()));
+        }
+        if let Some(id) = self.group_id {
+            selectors.push(EndpointSelector::ByGroupId(id));
+        }
+
+        match selectors.len() {
+            1 => Ok(selectors.remove(0)),
+            0 => Err("Endpoint must set one of name, id, name_glob, name_regex, tags, tag_ids, group or group_id".to_string()),
+            _ => Err("Endpoint must set only one selector".to_string()),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct StackSpec {
+    compose: PathBuf,
+    session: Option<String>,
+    endpoint: Option<EndpointSpec>,
+    /// Applied in order, later files override earlier ones, prefixed process variables
+    /// override files and `env` overrides all
+    #[serde(default)]
+    env_files: Vec<PathBuf>,
+    #[serde(default)]
+    env_from_prefix: Vec<String>,
+    #[serde(default)]
+    env: BTreeMap<String, Yaml>,
+    /// Fails when sources set a variable to different values
+    #[serde(default)]
+    strict_env: bool,
+    #[serde(default)]
+    configs: BTreeMap<String, PathBuf>,
+    #[serde(default)]
+    secrets: BTreeMap<String, PathBuf>,
+    #[serde(default)]
+    registries: Vec<String>,
+    /// Added to the deploy labels of every service, so stacks can be selected by label
+    #[serde(default)]
+    labels: BTreeMap<String, String>,
+    #[serde(default)]
+    depends_on: Vec<String>,
+}
+
+/// Declarative description of stacks, e.g. `pctl.yaml`:
+///
+/// ```yaml
+/// session: prod
+/// endpoint:
+///   name: swarm-eu
+/// stacks:
+///   db:
+///     compose: db/docker-compose.yml
+///     secrets:
+///       db_password: db/password.txt
+///   web:
+///     compose: web/docker-compose.yml
+///     env_files: [web/.env]
+///     labels:
+///       team: web
+///     depends_on: [db]
+/// protect:
+///   stacks: [db]
+/// ```
+///
+/// Sessions and endpoints set at the top level are defaults for stacks without their own.
+/// Paths are relative to the manifest.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Manifest {
+    session: Option<String>,
+    endpoint: Option<EndpointSpec>,
+    /// Added to the protection rules of sessions
+    #[serde(default)]
+    protect: Protection,
+    stacks: BTreeMap<String, StackSpec>,
+    #[serde(skip)]
+    base: PathBuf,
+}
+
+/// A manifest stack with its files read, ready to be planned
+pub struct ManifestStack {
+    pub name: String,
+    pub session: Option<String>,
+    pub endpoint: EndpointSelector,
+    pub compose: String,
+    pub inline_vars: Vec<InlineEnv>,
+    pub configs: Vec<FileMapping>,
+    pub secrets: Vec<FileMapping>,
+    pub registries: Vec<String>,
+}
+
+impl Manifest {
+    pub fn load(path: &Path) -> Res<Manifest> {
+        let content = fs::read_to_string(path)
+            .map_err(|err| format!("Cannot read manifest '{}': {}", path.display(), err))?;
+        let mut manifest: Manifest = serde_yaml::from_str(&content)
+            .map_err(|err| format!("Invalid manifest '{}': {}", path.display(), err))?;
+        manifest.base = path.parent().map(Path::to_path_buf).unwrap_or_default();
+        manifest.order()?;
+        Ok(manifest)
+    }
+
+    /// Stack names ordered so that every stack comes after the stacks it depends on
+    pub fn order(&self) -> Res<Vec<&str>> {
+        for (name, stack) in &self.stacks {
+            if let Some(missing) = stack
+                .depends_on
+                .iter()
+                .find(|dep| !self.stacks.contains_key(*dep))
+            {
+                return Err(format!(
+                    "Stack '{}' depends on unknown stack '{}'",
+                    name, missing
+                ));
+            }
+        }
+
+        let mut ordered: Vec<&str> = Vec::new();
+        while ordered.len() < self.stacks.len() {
+            let pending: Vec<(&String, &StackSpec)> = self
+                .stacks
+                .iter()
+                .filter(|(name, _)| !ordered.contains(&name.as_str()))
+                .collect();
+            let ready: Vec<&str> = pending
+                .iter()
+                .filter(|(_, stack)| {
+                    stack
+                        .depends_on
+                        .iter()
+                        .all(|dep| ordered.contains(&dep.as_str()))
+                })
+                .map(|(name, _)| name.as_str())
+                .collect();
+            if ready.is_empty() {
+                let names: Vec<&str> = pending.iter().map(|(name, _)| name.as_str()).collect();
+                return Err(format!(
+                    "Dependency cycle between stacks: {}",
+                    names.join(", ")
+                ));
+            }
+            ordered.extend(ready);
+        }
+        Ok(ordered)
+    }
+
+    pub fn protection(&self) -> &Protection {
+        &self.protect
+    }
+
+    fn path(&self, path: &Path) -> PathBuf {
+        self.base.join(path)
+    }
+
+    fn read(&self, path: &Path) -> Res<String> {
+        let path = self.path(path);
+        fs::read_to_string(&path)
+            .map_err(|err| format!("Cannot read '{}': {}", path.display(), err))
+    }
+
+    /// Sources of configs and secrets, paths are relative to the manifest, with or without
+    /// `file:`, and provider references are kept as they are
+    fn mappings(&self, files: &BTreeMap<String, PathBuf>) -> Vec<FileMapping> {
+        files
+            .iter()
+            .map(|(name, source)| {
+                let text = source.to_string_lossy();
+                let source = if is_reference(&text) {
+                    source.clone()
+                } else {
+                    match text.strip_prefix("file:") {
+                        Some(path) => self.path(Path::new(path)),
+                        None => self.path(source),
+                    }
+                };
+                FileMapping(name.to_string(), source.into_boxed_path())
+            })
+            .collect()
+    }
+
+    fn resolve(&self, name: &str, stack: &StackSpec, keys: &Keyring) -> Res<ManifestStack> {
+        let context = |err: String| format!("Stack '{}': {}", name, err);
+
+        let endpoint = stack
+            .endpoint
+            .as_ref()
+            .or(self.endpoint.as_ref())
+            .ok_or_else(|| context("No endpoint selected".to_string()))?
+            .selector()
+            .map_err(context)?;
+
+        let mut compose = self.read(&stack.compose).map_err(context)?;
+        if !stack.labels.is_empty() {
+            let mut file = ComposeFile::parse(&compose).map_err(context)?;
+            let labels: Vec<(String, String)> = stack
+                .labels
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect();
+            file.add_deploy_labels(&labels);
+            compose = file.to_yaml().map_err(context)?;
+        }
+
+        let mut sources = Vec::new();
+        for path in &stack.env_files {
+            sources.push(EnvSource::read(&self.path(path), keys).map_err(context)?);
+        }
+        for prefix in &stack.env_from_prefix {
+            sources.push(EnvSource::from_prefix(prefix));
+        }
+        let mut vars = Vec::new();
+        for (key, value) in &stack.env {
+            let value = scalar(value)
+                .ok_or_else(|| context(format!("Value of env '{}' must be a scalar", key)))?;
+            vars.push(InlineEnv(key.to_string(), value));
+        }
+        sources.push(EnvSource::new("env", vars));
+        let inline_vars = env::merge(sources, stack.strict_env).map_err(context)?;
+
+        Ok(ManifestStack {
+            name: name.to_string(),
+            session: stack.session.clone().or_else(|| self.session.clone()),
+            endpoint,
+            compose,
+            inline_vars,
+            configs: self.mappings(&stack.configs),
+            secrets: self.mappings(&stack.secrets),
+            registries: stack.registries.clone(),
+        })
+    }
+
+    /// All stacks in dependency order, encrypted envfiles are decrypted with `keys`
+    pub fn stacks(&self, keys: &Keyring) -> Res<Vec<ManifestStack>> {
+        self.order()?
+            .into_iter()
+            .map(|name| self.resolve(name, &self.stacks[name], keys))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn manifest(content: &str) -> Manifest {
+        let mut manifest: Manifest = serde_yaml::from_str(content).unwrap();
+        manifest.base = PathBuf::from("/deploy");
+        manifest
+    }
+
+    #[test]
+    fn file_sources_are_relative_to_the_manifest() {
+        let manifest = manifest(
+            "stacks:
+  db:
+    compose: db.yml
+    secrets:
+      plain: db/pw.txt
+      prefixed: file:db/pw.age
+      absolute: file:/etc/pw.age
+      vault: vault://kv/db#password
+      env: env:DB_PASSWORD
+",
+        );
+
+        let sources: Vec<(String, PathBuf)> = manifest
+            .mappings(&manifest.stacks["db"].secrets)
+            .into_iter()
+            .map(|FileMapping(name, path)| (name, path.into_path_buf()))
+            .collect();
+        assert_eq!(
+            sources,
+            vec![
+                ("absolute".to_string(), PathBuf::from("/etc/pw.age")),
+                ("env".to_string(), PathBuf::from("env:DB_PASSWORD")),
+                ("plain".to_string(), PathBuf::from("/deploy/db/pw.txt")),
+                ("prefixed".to_string(), PathBuf::from("/deploy/db/pw.age")),
+                ("vault".to_string(), PathBuf::from("vault://kv/db#password")),
+            ]
+        );
+    }
+}
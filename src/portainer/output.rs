@@ -0,0 +1,915 @@
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, IsTerminal};
+
+use crossterm::style::Stylize;
+use serde::Serialize;
+use serde_json::Value as Json;
+
+use super::query::Selector;
+use super::requests::{
+    DockerSnapshot, Endpoint, EndpointGroup, KubernetesSnapshot, Network, Registry, Stack, Tag,
+    Volume,
+};
+use super::{Action, Res};
+
+#[derive(Clone)]
+pub enum OutputFormat {
+    Table,
+    Json,
+    Yaml,
+    /// Text with `{{selector}}` placeholders, rendered once per item for lists
+    Template(String),
+}
+impl std::str::FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "table" => Ok(OutputFormat::Table),
+            "json" => Ok(OutputFormat::Json),
+            "yaml" => Ok(OutputFormat::Yaml),
+            _ => match s.strip_prefix("template=") {
+                Some(template) => Ok(OutputFormat::Template(template.to_string())),
+                None => Err(format!(
+                    "Invalid output '{}', must be one of table, json, yaml or template=<tmpl>",
+                    s
+                )),
+            },
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub enum Style {
+    Plain,
+    Good,
+    Bad,
+    Warn,
+}
+
+pub struct Cell(pub String, pub Style);
+impl Cell {
+    pub fn plain<T: ToString>(value: T) -> Cell {
+        Cell(value.to_string(), Style::Plain)
+    }
+}
+
+pub struct Table {
+    pub headers: Vec<&'static str>,
+    pub rows: Vec<Vec<Cell>>,
+}
+
+/// Anything printed by commands, serialized as is for json/yaml/templates
+pub trait Output: Serialize {
+    /// Printed above human readable tables
+    fn title(&self) -> Option<String> {
+        None
+    }
+    fn table(&self) -> Table;
+}
+
+/// An item of listings, e.g. `pctl stack ls`
+pub trait Row: Serialize {
+    fn headers() -> Vec<&'static str>;
+    fn row(&self) -> Vec<Cell>;
+}
+impl<T: Row> Output for Vec<T> {
+    fn table(&self) -> Table {
+        Table {
+            headers: T::headers(),
+            rows: self.iter().map(Row::row).collect(),
+        }
+    }
+}
+
+pub struct Printer {
+    format: OutputFormat,
+    color: bool,
+}
+
+fn render_template(template: &str, value: &Json) -> Res<String> {
+    let mut output = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        let (text, tail) = rest.split_at(start);
+        output.push_str(text);
+        let end = tail
+            .find("}}")
+            .ok_or_else(|| format!("Unclosed placeholder in template '{}'", template))?;
+        let selected = Selector::parse(&tail[2..end])?.select(value.clone());
+        let rendered: Vec<String> = selected
+            .iter()
+            .map(|v| match v {
+                Json::String(s) => s.to_string(),
+                Json::Null => String::new(),
+                other => other.to_string(),
+            })
+            .collect();
+        output.push_str(&rendered.join(","));
+        rest = &tail[end + 2..];
+    }
+    output.push_str(rest);
+    Ok(output)
+}
+
+impl Printer {
+    pub fn new(format: OutputFormat) -> Printer {
+        let color = io::stdout().is_terminal() && std::env::var_os("NO_COLOR").is_none();
+        Printer { format, color }
+    }
+
+    fn paint(&self, text: String, style: Style) -> String {
+        if !self.color {
+            return text;
+        }
+        match style {
+            Style::Plain => text,
+            Style::Good => text.green().to_string(),
+            Style::Bad => text.red().to_string(),
+            Style::Warn => text.yellow().to_string(),
+        }
+    }
+
+    fn print_table(&self, title: Option<String>, table: Table) {
+        if let Some(title) = title {
+            println!("{}", title);
+        }
+        let mut widths: Vec<usize> = table.headers.iter().map(|h| h.len()).collect();
+        for row in &table.rows {
+            for (i, Cell(text, _)) in row.iter().enumerate() {
+                widths[i] = widths[i].max(text.chars().count());
+            }
+        }
+
+        let pad = |text: &str, width: usize| {
+            format!("{}{}", text, " ".repeat(width - text.chars().count()))
+        };
+        let header: Vec<String> = table
+            .headers
+            .iter()
+            .zip(&widths)
+            .map(|(h, w)| pad(h, *w))
+            .collect();
+        let header = header.join("  ").trim_end().to_string();
+        if self.color {
+            println!("{}", header.bold());
+        } else {
+            println!("{}", header);
+        }
+
+        for row in table.rows {
+            let cells: Vec<String> = row
+                .into_iter()
+                .zip(&widths)
+                .map(|(Cell(text, style), w)| self.paint(pad(&text, *w), style))
+                .collect();
+            println!("{}", cells.join("  ").trim_end());
+        }
+    }
+
+    pub fn print<T: Output>(&self, value: &T) -> Action {
+        match &self.format {
+            OutputFormat::Table => {
+                self.print_table(value.title(), value.table());
+                Ok(())
+            }
+            OutputFormat::Json => self.print_json(value),
+            OutputFormat::Yaml => self.print_yaml(value),
+            OutputFormat::Template(template) => self.print_template(template, value),
+        }
+    }
+
+    pub fn print_json<T: Serialize>(&self, value: &T) -> Action {
+        let json = serde_json::to_string_pretty(value).map_err(|x| x.to_string())?;
+        println!("{}", json);
+        Ok(())
+    }
+
+    pub fn print_yaml<T: Serialize>(&self, value: &T) -> Action {
+        let yaml = serde_yaml::to_string(value).map_err(|x| x.to_string())?;
+        print!("{}", yaml);
+        Ok(())
+    }
+
+    pub fn print_template<T: Serialize>(&self, template: &str, value: &T) -> Action {
+        let value = serde_json::to_value(value).map_err(|x| x.to_string())?;
+        match &value {
+            Json::Array(items) => {
+                for item in items {
+                    println!("{}", render_template(template, item)?);
+                }
+            }
+            _ => println!("{}", render_template(template, &value)?),
+        }
+        Ok(())
+    }
+
+    /// Prints arbitrary json documents, which have no table representation
+    pub fn print_document(&self, value: &Json, compact: bool) -> Action {
+        match &self.format {
+            OutputFormat::Yaml => self.print_yaml(value),
+            OutputFormat::Template(template) => self.print_template(template, value),
+            _ if compact => {
+                match value {
+                    Json::String(text) => println!("{}", text),
+                    _ => println!("{}", value),
+                }
+                Ok(())
+            }
+            _ => self.print_json(value),
+        }
+    }
+}
+
+/// A step of a plan, as shown before confirmation
+#[derive(Serialize)]
+pub struct PlanStep {
+    /// One of `create`, `update`, `remove`, `start`, `stop`, `grant`, `skip`, `wait` or
+    /// `missing`
+    pub action: &'static str,
+    /// Kind of resource, e.g. `stack`, `config`, `secret`, `volume` or `registry`
+    pub resource: &'static str,
+    pub name: String,
+    /// Portainer or docker id, if the resource already exists
+    pub id: Option<String>,
+    /// Human readable details about the step
+    pub detail: Option<String>,
+}
+impl PlanStep {
+    pub fn new(action: &'static str, resource: &'static str, name: &str) -> PlanStep {
+        PlanStep {
+            action,
+            resource,
+            name: name.to_string(),
+            id: None,
+            detail: None,
+        }
+    }
+    pub fn with_id<T: ToString>(mut self, id: T) -> PlanStep {
+        self.id = Some(id.to_string());
+        self
+    }
+    pub fn with_detail(mut self, detail: String) -> PlanStep {
+        self.detail = Some(detail);
+        self
+    }
+
+    fn cells(&self) -> Vec<Cell> {
+        let style = match self.action {
+            "create" | "start" => Style::Good,
+            "remove" | "stop" | "missing" => Style::Bad,
+            "update" => Style::Warn,
+            _ => Style::Plain,
+        };
+        vec![
+            Cell(self.action.to_string(), style),
+            Cell::plain(self.resource),
+            Cell::plain(&self.name),
+            Cell::plain(self.id.clone().unwrap_or_default()),
+            Cell::plain(self.detail.clone().unwrap_or_default()),
+        ]
+    }
+}
+
+#[derive(Serialize)]
+pub struct PlanView {
+    /// Kind of plan, e.g. `deploy` or `destroy`
+    pub plan: &'static str,
+    pub endpoint: i32,
+    pub steps: Vec<PlanStep>,
+}
+impl Output for PlanView {
+    fn title(&self) -> Option<String> {
+        let mut kind = self.plan.to_string();
+        kind[..1].make_ascii_uppercase();
+        Some(format!("{} plan on endpoint {}:", kind, self.endpoint))
+    }
+
+    fn table(&self) -> Table {
+        Table {
+            headers: vec!["ACTION", "RESOURCE", "NAME", "ID", "DETAIL"],
+            rows: self.steps.iter().map(PlanStep::cells).collect(),
+        }
+    }
+}
+
+/// Plans of several endpoints, in the order they are executed
+#[derive(Serialize)]
+#[serde(transparent)]
+pub struct PlanGraphView(pub Vec<PlanView>);
+impl Output for PlanGraphView {
+    fn title(&self) -> Option<String> {
+        let mut kind = self.0.first().map_or("empty", |plan| plan.plan).to_string();
+        kind[..1].make_ascii_uppercase();
+        Some(format!("{} plan for {} stack(s):", kind, self.0.len()))
+    }
+
+    fn table(&self) -> Table {
+        Table {
+            headers: vec!["ENDPOINT", "ACTION", "RESOURCE", "NAME", "ID", "DETAIL"],
+            rows: self
+                .0
+                .iter()
+                .flat_map(|plan| {
+                    plan.steps.iter().map(|step| {
+                        let mut row = vec![Cell::plain(plan.endpoint)];
+                        row.extend(step.cells());
+                        row
+                    })
+                })
+                .collect(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct StackView {
+    pub id: i32,
+    pub name: String,
+    /// Either `active` or `inactive`
+    pub status: &'static str,
+    pub endpoint_id: i32,
+    pub swarm_id: Option<String>,
+}
+impl From<&Stack> for StackView {
+    fn from(stack: &Stack) -> Self {
+        StackView {
+            id: stack.id,
+            name: stack.name.to_string(),
+            status: if stack.is_active() {
+                "active"
+            } else {
+                "inactive"
+            },
+            endpoint_id: stack.endpoint_id,
+            swarm_id: stack.swarm_id.clone(),
+        }
+    }
+}
+impl Row for StackView {
+    fn headers() -> Vec<&'static str> {
+        vec!["ID", "NAME", "STATUS", "ENDPOINT", "SWARM"]
+    }
+
+    fn row(&self) -> Vec<Cell> {
+        vec![
+            Cell::plain(self.id),
+            Cell::plain(&self.name),
+            Cell(
+                self.status.to_string(),
+                if self.status == "active" {
+                    Style::Good
+                } else {
+                    Style::Warn
+                },
+            ),
+            Cell::plain(self.endpoint_id),
+            Cell::plain(self.swarm_id.clone().unwrap_or_default()),
+        ]
+    }
+}
+
+/// Summary of the latest snapshot portainer took of an endpoint
+#[derive(Serialize)]
+pub struct SnapshotView {
+    /// Unix timestamp of the snapshot
+    pub time: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub docker_version: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kubernetes_version: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub swarm: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub running_containers: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stopped_containers: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stacks: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub services: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub volumes: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub images: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub nodes: Option<u32>,
+}
+impl SnapshotView {
+    fn summary(&self) -> String {
+        let mut parts = Vec::new();
+        if let Some(version) = &self.docker_version {
+            parts.push(format!("docker {}", version));
+        }
+        if let Some(version) = &self.kubernetes_version {
+            parts.push(format!("kubernetes {}", version));
+        }
+        if self.swarm == Some(true) {
+            parts.push("swarm".to_string());
+        }
+        let counts = [
+            ("running", self.running_containers),
+            ("stopped", self.stopped_containers),
+            ("stacks", self.stacks),
+            ("services", self.services),
+            ("nodes", self.nodes),
+        ];
+        for (name, count) in counts {
+            if let Some(count) = count {
+                parts.push(format!("{} {}", count, name));
+            }
+        }
+        parts.join(", ")
+    }
+}
+impl From<&DockerSnapshot> for SnapshotView {
+    fn from(snapshot: &DockerSnapshot) -> Self {
+        SnapshotView {
+            time: snapshot.time,
+            docker_version: Some(snapshot.docker_version.to_string()),
+            kubernetes_version: None,
+            swarm: Some(snapshot.swarm),
+            running_containers: Some(snapshot.running_containers),
+            stopped_containers: Some(snapshot.stopped_containers),
+            stacks: Some(snapshot.stacks),
+            services: Some(snapshot.services),
+            volumes: Some(snapshot.volumes),
+            images: Some(snapshot.images),
+            nodes: None,
+        }
+    }
+}
+impl From<&KubernetesSnapshot> for SnapshotView {
+    fn from(snapshot: &KubernetesSnapshot) -> Self {
+        SnapshotView {
+            time: snapshot.time,
+            docker_version: None,
+            kubernetes_version: Some(snapshot.kubernetes_version.to_string()),
+            swarm: None,
+            running_containers: None,
+            stopped_containers: None,
+            stacks: None,
+            services: None,
+            volumes: None,
+            images: None,
+            nodes: Some(snapshot.nodes),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct EndpointView {
+    pub id: i32,
+    pub name: String,
+    /// One of `docker`, `agent`, `azure`, `edge`, `kubernetes`, `kubernetes-agent` or `kubernetes-edge`
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    pub url: String,
+    pub public_url: String,
+    pub group_id: i32,
+    /// Group name
+    pub group: String,
+    /// Tag names
+    pub tags: Vec<String>,
+    /// Either `up` or `down`
+    pub status: &'static str,
+    /// Whether an edge agent has checked in recently, only present for edge endpoints
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub heartbeat: Option<bool>,
+    /// Unix timestamp of the last edge agent check in, only present for edge endpoints
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_check_in: Option<i64>,
+    pub snapshot: Option<SnapshotView>,
+}
+impl EndpointView {
+    /// Tag and group ids are shown by name when found in the lookups
+    pub fn new(
+        endpoint: &Endpoint,
+        tags: &HashMap<i32, String>,
+        groups: &HashMap<i32, String>,
+    ) -> EndpointView {
+        let kind = match endpoint.kind {
+            1 => "docker",
+            2 => "agent",
+            3 => "azure",
+            4 => "edge",
+            5 => "kubernetes",
+            6 => "kubernetes-agent",
+            7 => "kubernetes-edge",
+            _ => "unknown",
+        };
+        let edge = matches!(endpoint.kind, 4 | 7);
+        let snapshot = endpoint
+            .docker_snapshot()
+            .map(SnapshotView::from)
+            .or_else(|| endpoint.kubernetes_snapshot().map(SnapshotView::from));
+
+        EndpointView {
+            id: endpoint.id(),
+            name: endpoint.name().to_string(),
+            kind,
+            url: endpoint.url.to_string(),
+            public_url: endpoint.public_url.to_string(),
+            group_id: endpoint.group_id,
+            group: groups
+                .get(&endpoint.group_id)
+                .cloned()
+                .unwrap_or_else(|| endpoint.group_id.to_string()),
+            tags: endpoint
+                .tag_ids()
+                .iter()
+                .map(|id| tags.get(id).cloned().unwrap_or_else(|| id.to_string()))
+                .collect(),
+            status: if endpoint.status == 1 { "up" } else { "down" },
+            heartbeat: Some(endpoint.heartbeat).filter(|_| edge),
+            last_check_in: Some(endpoint.last_check_in).filter(|_| edge),
+            snapshot,
+        }
+    }
+
+    fn status_cell(&self) -> Cell {
+        match (self.status, self.heartbeat) {
+            ("up", None | Some(true)) => Cell(self.status.to_string(), Style::Good),
+            _ => Cell(self.status.to_string(), Style::Bad),
+        }
+    }
+}
+impl Row for EndpointView {
+    fn headers() -> Vec<&'static str> {
+        vec![
+            "ID", "NAME", "TYPE", "URL", "GROUP", "TAGS", "STATUS", "SNAPSHOT",
+        ]
+    }
+
+    fn row(&self) -> Vec<Cell> {
+        vec![
+            Cell::plain(self.id),
+            Cell::plain(&self.name),
+            Cell::plain(self.kind),
+            Cell::plain(&self.url),
+            Cell::plain(&self.group),
+            Cell::plain(self.tags.join(",")),
+            self.status_cell(),
+            Cell::plain(
+                self.snapshot
+                    .as_ref()
+                    .map(SnapshotView::summary)
+                    .unwrap_or_default(),
+            ),
+        ]
+    }
+}
+impl Output for EndpointView {
+    fn table(&self) -> Table {
+        let optional = |value: Option<String>| value.unwrap_or_default();
+        let rows = vec![
+            ("ID", Cell::plain(self.id)),
+            ("Name", Cell::plain(&self.name)),
+            ("Type", Cell::plain(self.kind)),
+            ("URL", Cell::plain(&self.url)),
+            ("Public URL", Cell::plain(&self.public_url)),
+            ("Group", Cell::plain(&self.group)),
+            ("Group ID", Cell::plain(self.group_id)),
+            ("Tags", Cell::plain(self.tags.join(","))),
+            ("Status", self.status_cell()),
+            (
+                "Heartbeat",
+                Cell::plain(optional(self.heartbeat.map(|h| h.to_string()))),
+            ),
+            (
+                "Last check in",
+                Cell::plain(optional(self.last_check_in.map(|t| t.to_string()))),
+            ),
+            (
+                "Snapshot",
+                Cell::plain(optional(self.snapshot.as_ref().map(SnapshotView::summary))),
+            ),
+        ];
+        Table {
+            headers: vec!["FIELD", "VALUE"],
+            rows: rows
+                .into_iter()
+                .map(|(field, value)| vec![Cell::plain(field), value])
+                .collect(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct TagView {
+    pub id: i32,
+    pub name: String,
+    /// Ids of endpoints having this tag
+    pub endpoints: Vec<i32>,
+}
+impl From<&Tag> for TagView {
+    fn from(tag: &Tag) -> Self {
+        let mut endpoints: Vec<i32> = tag.tagged_endpoints().into_iter().collect();
+        endpoints.sort();
+        TagView {
+            id: tag.id,
+            name: tag.name.to_string(),
+            endpoints,
+        }
+    }
+}
+impl Row for TagView {
+    fn headers() -> Vec<&'static str> {
+        vec!["ID", "NAME", "ENDPOINTS"]
+    }
+
+    fn row(&self) -> Vec<Cell> {
+        let endpoints: Vec<String> = self.endpoints.iter().map(|e| e.to_string()).collect();
+        vec![
+            Cell::plain(self.id),
+            Cell::plain(&self.name),
+            Cell::plain(endpoints.join(",")),
+        ]
+    }
+}
+
+#[derive(Serialize)]
+pub struct GroupView {
+    pub id: i32,
+    pub name: String,
+    pub description: String,
+    /// Tag names
+    pub tags: Vec<String>,
+    /// Ids of endpoints in this group
+    pub endpoints: Vec<i32>,
+}
+impl GroupView {
+    pub fn new(
+        group: &EndpointGroup,
+        tags: &HashMap<i32, String>,
+        endpoints: &[Endpoint],
+    ) -> GroupView {
+        GroupView {
+            id: group.id,
+            name: group.name.to_string(),
+            description: group.description.to_string(),
+            tags: group
+                .tag_ids
+                .iter()
+                .map(|id| tags.get(id).cloned().unwrap_or_else(|| id.to_string()))
+                .collect(),
+            endpoints: endpoints
+                .iter()
+                .filter(|e| e.group_id == group.id)
+                .map(Endpoint::id)
+                .collect(),
+        }
+    }
+}
+impl Row for GroupView {
+    fn headers() -> Vec<&'static str> {
+        vec!["ID", "NAME", "DESCRIPTION", "TAGS", "ENDPOINTS"]
+    }
+
+    fn row(&self) -> Vec<Cell> {
+        let endpoints: Vec<String> = self.endpoints.iter().map(|e| e.to_string()).collect();
+        vec![
+            Cell::plain(self.id),
+            Cell::plain(&self.name),
+            Cell::plain(&self.description),
+            Cell::plain(self.tags.join(",")),
+            Cell::plain(endpoints.join(",")),
+        ]
+    }
+}
+
+#[derive(Serialize)]
+pub struct RegistryView {
+    pub id: i32,
+    pub name: String,
+    /// One of `quay`, `azure`, `custom`, `gitlab`, `proget`, `dockerhub`, `ecr` or `github`
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    pub url: String,
+    /// Username used to log in, empty for anonymous registries
+    pub username: String,
+}
+impl From<&Registry> for RegistryView {
+    fn from(registry: &Registry) -> Self {
+        let kind = match registry.kind {
+            1 => "quay",
+            2 => "azure",
+            3 => "custom",
+            4 => "gitlab",
+            5 => "proget",
+            6 => "dockerhub",
+            7 => "ecr",
+            8 => "github",
+            _ => "unknown",
+        };
+        RegistryView {
+            id: registry.id,
+            name: registry.name.to_string(),
+            kind,
+            url: registry.url.to_string(),
+            username: if registry.authentication {
+                registry.username.to_string()
+            } else {
+                String::new()
+            },
+        }
+    }
+}
+impl Row for RegistryView {
+    fn headers() -> Vec<&'static str> {
+        vec!["ID", "NAME", "TYPE", "URL", "USERNAME"]
+    }
+
+    fn row(&self) -> Vec<Cell> {
+        vec![
+            Cell::plain(self.id),
+            Cell::plain(&self.name),
+            Cell::plain(self.kind),
+            Cell::plain(&self.url),
+            Cell::plain(&self.username),
+        ]
+    }
+}
+
+fn joined(map: &BTreeMap<String, String>) -> String {
+    let pairs: Vec<String> = map.iter().map(|(k, v)| format!("{}={}", k, v)).collect();
+    pairs.join(",")
+}
+
+#[derive(Serialize)]
+pub struct VolumeView {
+    pub name: String,
+    pub driver: String,
+    /// Either `local` or `global`
+    pub scope: String,
+    pub mountpoint: String,
+    pub created_at: String,
+    pub labels: BTreeMap<String, String>,
+    /// Driver options
+    pub options: BTreeMap<String, String>,
+}
+impl From<&Volume> for VolumeView {
+    fn from(volume: &Volume) -> Self {
+        VolumeView {
+            name: volume.name.to_string(),
+            driver: volume.driver.to_string(),
+            scope: volume.scope.to_string(),
+            mountpoint: volume.mountpoint.to_string(),
+            created_at: volume.created_at.to_string(),
+            labels: volume.labels().into_iter().collect(),
+            options: volume.options().into_iter().collect(),
+        }
+    }
+}
+impl Row for VolumeView {
+    fn headers() -> Vec<&'static str> {
+        vec!["NAME", "DRIVER", "SCOPE", "LABELS"]
+    }
+
+    fn row(&self) -> Vec<Cell> {
+        vec![
+            Cell::plain(&self.name),
+            Cell::plain(&self.driver),
+            Cell::plain(&self.scope),
+            Cell::plain(joined(&self.labels)),
+        ]
+    }
+}
+impl Output for VolumeView {
+    fn table(&self) -> Table {
+        let rows = vec![
+            ("Name", Cell::plain(&self.name)),
+            ("Driver", Cell::plain(&self.driver)),
+            ("Scope", Cell::plain(&self.scope)),
+            ("Mountpoint", Cell::plain(&self.mountpoint)),
+            ("Created at", Cell::plain(&self.created_at)),
+            ("Labels", Cell::plain(joined(&self.labels))),
+            ("Options", Cell::plain(joined(&self.options))),
+        ];
+        Table {
+            headers: vec!["FIELD", "VALUE"],
+            rows: rows
+                .into_iter()
+                .map(|(field, value)| vec![Cell::plain(field), value])
+                .collect(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct NetworkView {
+    pub id: String,
+    pub name: String,
+    pub driver: String,
+    /// One of `local`, `global` or `swarm`
+    pub scope: String,
+    pub attachable: bool,
+    pub internal: bool,
+    pub labels: BTreeMap<String, String>,
+}
+impl From<&Network> for NetworkView {
+    fn from(network: &Network) -> Self {
+        NetworkView {
+            id: network.id.to_string(),
+            name: network.name.to_string(),
+            driver: network.driver.to_string(),
+            scope: network.scope.to_string(),
+            attachable: network.attachable,
+            internal: network.internal,
+            labels: network.labels().into_iter().collect(),
+        }
+    }
+}
+impl Row for NetworkView {
+    fn headers() -> Vec<&'static str> {
+        vec!["ID", "NAME", "DRIVER", "SCOPE", "LABELS"]
+    }
+
+    fn row(&self) -> Vec<Cell> {
+        vec![
+            // shortened like docker does
+            Cell::plain(self.id.chars().take(12).collect::<String>()),
+            Cell::plain(&self.name),
+            Cell::plain(&self.driver),
+            Cell::plain(&self.scope),
+            Cell::plain(joined(&self.labels)),
+        ]
+    }
+}
+
+/// Differences between a deployed stack and its local definition
+#[derive(Serialize)]
+pub struct StackDiff {
+    pub endpoint: i32,
+    pub stack: String,
+    /// Unified diff from the live stack file to the local compose file
+    pub compose: Vec<String>,
+    /// Changed variable names prefixed by `+` when added, `-` when removed and `~` when changed
+    pub env: Vec<String>,
+    /// Config names prefixed by `+` when missing on the endpoint and `~` when content differs
+    pub configs: Vec<String>,
+    /// Names of secrets missing on the endpoint prefixed by `+`, contents cannot be compared
+    pub secrets: Vec<String>,
+}
+impl StackDiff {
+    pub fn is_empty(&self) -> bool {
+        self.compose.is_empty()
+            && self.env.is_empty()
+            && self.configs.is_empty()
+            && self.secrets.is_empty()
+    }
+}
+
+fn diff_style(line: &str) -> Style {
+    match line.chars().next() {
+        Some('+') if !line.starts_with("+++") => Style::Good,
+        Some('-') if !line.starts_with("---") => Style::Bad,
+        Some('~') => Style::Warn,
+        _ => Style::Plain,
+    }
+}
+
+#[derive(Serialize)]
+#[serde(transparent)]
+pub struct DiffView(pub Vec<StackDiff>);
+impl Output for DiffView {
+    fn table(&self) -> Table {
+        let mut rows = Vec::new();
+        for diff in &self.0 {
+            let lines: Vec<Cell> = if diff.is_empty() {
+                vec![Cell::plain("no changes")]
+            } else {
+                let resources = [
+                    ("env", &diff.env),
+                    ("config", &diff.configs),
+                    ("secret", &diff.secrets),
+                ];
+                resources
+                    .into_iter()
+                    .flat_map(|(kind, changes)| {
+                        changes.iter().map(move |change| {
+                            Cell(format!("{} {}", kind, change), diff_style(change))
+                        })
+                    })
+                    .chain(
+                        diff.compose
+                            .iter()
+                            .map(|line| Cell(line.to_string(), diff_style(line))),
+                    )
+                    .collect()
+            };
+            for (index, line) in lines.into_iter().enumerate() {
+                let (endpoint, stack) = if index == 0 {
+                    (Cell::plain(diff.endpoint), Cell::plain(&diff.stack))
+                } else {
+                    (Cell::plain(""), Cell::plain(""))
+                };
+                rows.push(vec![endpoint, stack, line]);
+            }
+        }
+        Table {
+            headers: vec!["ENDPOINT", "STACK", "CHANGE"],
+            rows,
+        }
+    }
+}
@@ -0,0 +1,106 @@
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use regex::{Captures, Regex};
+
+use super::Res;
+
+/// Label of services, configs and secrets holding the id of their preview
+pub const ID_LABEL: &str = "pctl.preview.id";
+/// Label of services, configs and secrets holding when their preview expires, in seconds
+/// since the unix epoch
+pub const EXPIRES_LABEL: &str = "pctl.preview.expires";
+
+/// Preview environment of a stack, e.g. of a pull request. `{{id}}`, `{{stack}}` and
+/// `{{name}}`, the name of the preview stack, are replaced in its compose file, env
+/// values and config and secret names.
+pub struct PreviewSpec {
+    pub id: String,
+    /// Name of the preview stack, from `{{id}}` and `{{stack}}`
+    pub name_template: String,
+    pub ttl: Option<Duration>,
+}
+impl PreviewSpec {
+    pub fn stack_name(&self, stack: &str) -> String {
+        render(&self.name_template, &[("id", &self.id), ("stack", stack)])
+    }
+
+    pub fn render(&self, text: &str, stack: &str) -> String {
+        let name = self.stack_name(stack);
+        render(text, &[("id", &self.id), ("stack", stack), ("name", &name)])
+    }
+
+    /// Labels marking the preview, its expiry counted from now
+    pub fn labels(&self) -> Vec<(String, String)> {
+        let mut labels = vec![(ID_LABEL.to_string(), self.id.to_string())];
+        if let Some(ttl) = self.ttl {
+            let expires = now().saturating_add(ttl.as_secs());
+            labels.push((EXPIRES_LABEL.to_string(), expires.to_string()));
+        }
+        labels
+    }
+}
+
+/// Replaces known placeholders, others like docker's `{{.Task.Slot}}` are kept
+fn render(template: &str, vars: &[(&str, &str)]) -> String {
+    let placeholder = Regex::new(r"\{\{\s*(\w+)\s*\}\}").unwrap();
+    placeholder
+        .replace_all(template, |caps: &Captures| {
+            vars.iter()
+                .find(|(key, _)| *key == &caps[1])
+                .map_or_else(|| caps[0].to_string(), |(_, value)| value.to_string())
+        })
+        .into_owned()
+}
+
+/// Preview ids end up in stack names and hostnames
+pub fn parse_id(value: &str) -> Res<String> {
+    let valid = !value.is_empty()
+        && value.len() <= 63
+        && !value.starts_with('-')
+        && !value.ends_with('-')
+        && value
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-');
+    if valid {
+        Ok(value.to_string())
+    } else {
+        Err(format!(
+            "Invalid preview id '{}', use lowercase letters, digits and dashes, e.g. pr-123",
+            value
+        ))
+    }
+}
+
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// A deployed preview, read from labels of a stack's services or of a config or secret
+pub struct Preview {
+    pub id: String,
+    pub expires: Option<u64>,
+}
+impl Preview {
+    pub fn from_labels<'a>(
+        labels: impl IntoIterator<Item = (&'a String, &'a String)>,
+    ) -> Option<Preview> {
+        let labels: HashMap<&str, &str> = labels
+            .into_iter()
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+            .collect();
+        Some(Preview {
+            id: labels.get(ID_LABEL)?.to_string(),
+            expires: labels.get(EXPIRES_LABEL).and_then(|e| e.parse().ok()),
+        })
+    }
+
+    /// Whether the preview expired or, when open ids are given, is not among them
+    pub fn is_garbage(&self, open: Option<&[String]>, now: u64) -> bool {
+        self.expires.is_some_and(|expires| expires <= now)
+            || open.is_some_and(|open| !open.contains(&self.id))
+    }
+}
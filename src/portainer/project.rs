@@ -0,0 +1,56 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use super::Res;
+
+pub const PROJECT_FILE: &str = ".pctl.toml";
+
+/// Endpoint of a project, an integer is an endpoint id and a string an endpoint name
+#[derive(Deserialize, Clone)]
+#[serde(untagged)]
+pub enum ProjectEndpoint {
+    Id(i32),
+    Name(String),
+}
+
+/// Connection defaults of a project, read from the closest `.pctl.toml`. Flags and
+/// environment variables take precedence over them.
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Project {
+    pub session: Option<String>,
+    pub url: Option<String>,
+    /// Only read to be rejected, project files are usually committed
+    token: Option<String>,
+    pub username: Option<String>,
+    pub endpoint: Option<ProjectEndpoint>,
+}
+impl Project {
+    /// Reads the project file of the directory or of its closest parent having one
+    pub fn discover(dir: &Path) -> Res<Project> {
+        match dir
+            .ancestors()
+            .map(|d| d.join(PROJECT_FILE))
+            .find(|f| f.is_file())
+        {
+            Some(file) => Project::read(&file),
+            None => Ok(Project::default()),
+        }
+    }
+
+    fn read(file: &PathBuf) -> Res<Project> {
+        let content = fs::read_to_string(file)
+            .map_err(|err| format!("Cannot read '{}': {}", file.display(), err))?;
+        let project: Project = toml::from_str(&content)
+            .map_err(|err| format!("Invalid '{}': {}", file.display(), err))?;
+        if project.token.is_some() {
+            return Err(format!(
+                "'{}' must not hold an API token, it is usually committed. Use a saved session with `session` or PCTL_SESSION, or set PORTAINER_TOKEN",
+                file.display()
+            ));
+        }
+        Ok(project)
+    }
+}
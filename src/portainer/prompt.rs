@@ -0,0 +1,98 @@
+use std::fs;
+use std::io::{self, IsTerminal, Read, Write};
+use std::path::Path;
+
+use super::Res;
+
+/// Answers confirmations with yes when set to `1`, `true` or `yes`
+pub const ASSUME_YES_VAR: &str = "PCTL_ASSUME_YES";
+
+pub fn assume_yes() -> bool {
+    std::env::var(ASSUME_YES_VAR)
+        .is_ok_and(|value| matches!(value.to_lowercase().as_str(), "1" | "true" | "yes"))
+}
+
+/// Asks a yes/no question on stderr, an empty answer or the end of input takes the
+/// default. Fails when stdin is not a terminal, instead of waiting for an answer.
+pub fn confirm(question: &str, default: bool) -> Res<bool> {
+    if assume_yes() {
+        return Ok(true);
+    }
+    if !io::stdin().is_terminal() {
+        return Err(format!(
+            "Cannot ask for confirmation, stdin is not a terminal. Pass -Y or set {}=1",
+            ASSUME_YES_VAR
+        ));
+    }
+    let choices = if default { "[Y/n]" } else { "[y/N]" };
+    loop {
+        eprint!("{} {} ", question, choices);
+        io::stderr().flush().map_err(|err| err.to_string())?;
+        let mut answer = String::new();
+        if io::stdin()
+            .read_line(&mut answer)
+            .map_err(|err| err.to_string())?
+            == 0
+        {
+            eprintln!();
+            return Ok(default);
+        }
+        match answer.trim().to_lowercase().as_str() {
+            "" => return Ok(default),
+            "y" | "yes" => return Ok(true),
+            "n" | "no" => return Ok(false),
+            _ => eprintln!("You must answer 'yes' or 'no'"),
+        }
+    }
+}
+
+/// Asks for a line of text on stderr, like the name of what is confirmed
+pub fn typed(question: &str) -> Res<String> {
+    if !io::stdin().is_terminal() {
+        return Err("Cannot ask for confirmation, stdin is not a terminal".to_string());
+    }
+    eprint!("{} ", question);
+    io::stderr().flush().map_err(|err| err.to_string())?;
+    let mut answer = String::new();
+    io::stdin()
+        .read_line(&mut answer)
+        .map_err(|err| err.to_string())?;
+    Ok(answer.trim().to_string())
+}
+
+/// Reads a password from the terminal without echoing it
+pub fn password(prompt: &str) -> Res<String> {
+    if !io::stdin().is_terminal() {
+        return Err(
+            "Cannot ask for a password, stdin is not a terminal. Pass --password-stdin or --password-file"
+                .to_string(),
+        );
+    }
+    rpassword::prompt_password(prompt).map_err(|err| format!("Cannot read password: {}", err))
+}
+
+fn trim_newline(mut value: String) -> String {
+    if value.ends_with('\n') {
+        value.pop();
+        if value.ends_with('\r') {
+            value.pop();
+        }
+    }
+    value
+}
+
+/// Password piped on stdin, without its trailing newline
+pub fn password_stdin() -> Res<String> {
+    let mut password = String::new();
+    io::stdin()
+        .read_to_string(&mut password)
+        .map_err(|err| format!("Cannot read password from stdin: {}", err))?;
+    Ok(trim_newline(password))
+}
+
+/// Password stored in a file, without its trailing newline
+pub fn password_file(path: &Path) -> Res<String> {
+    fs::read_to_string(path)
+        .map(trim_newline)
+        .map_err(|err| format!("Cannot read password file '{}': {}", path.display(), err))
+}
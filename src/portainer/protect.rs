@@ -0,0 +1,75 @@
+use serde::{Deserialize, Serialize};
+
+use super::Res;
+
+/// Endpoints and stacks whose destruction must be confirmed by typing their name, kept
+/// in sessions and manifests:
+///
+/// ```yaml
+/// protect:
+///   endpoints: [prod-*]
+///   endpoint_tags: [production]
+///   stacks: [db, "*-db"]
+/// ```
+///
+/// Endpoints and stacks are matched by name globs, endpoints also by tag.
+#[derive(Serialize, Deserialize, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Protection {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub endpoints: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub endpoint_tags: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub stacks: Vec<String>,
+}
+impl Protection {
+    pub fn is_empty(&self) -> bool {
+        self.endpoints.is_empty() && self.endpoint_tags.is_empty() && self.stacks.is_empty()
+    }
+
+    /// Rules of both, e.g. of a session and of a manifest
+    pub fn merge(&self, other: &Protection) -> Protection {
+        let join = |a: &Vec<String>, b: &Vec<String>| {
+            let mut all = a.clone();
+            all.extend(b.iter().filter(|v| !a.contains(v)).cloned());
+            all
+        };
+        Protection {
+            endpoints: join(&self.endpoints, &other.endpoints),
+            endpoint_tags: join(&self.endpoint_tags, &other.endpoint_tags),
+            stacks: join(&self.stacks, &other.stacks),
+        }
+    }
+
+    /// Whether rules depend on endpoints, which then have to be fetched to be matched
+    pub fn has_endpoint_rules(&self) -> bool {
+        !self.endpoints.is_empty() || !self.endpoint_tags.is_empty()
+    }
+
+    pub fn protects_endpoint(&self, name: &str, tags: &[String]) -> Res<bool> {
+        Ok(matches_any(&self.endpoints, name)?
+            || self.endpoint_tags.iter().any(|tag| tags.contains(tag)))
+    }
+
+    pub fn protects_stack(&self, name: &str) -> Res<bool> {
+        matches_any(&self.stacks, name)
+    }
+}
+
+fn matches_any(patterns: &[String], name: &str) -> Res<bool> {
+    for pattern in patterns {
+        let glob = glob::Pattern::new(pattern)
+            .map_err(|err| format!("Invalid protection pattern '{}': {}", pattern, err))?;
+        if glob.matches(name) {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// A protected endpoint or stack a plan would destroy or change destructively
+pub struct Protected {
+    pub kind: &'static str,
+    pub name: String,
+}
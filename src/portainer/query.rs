@@ -0,0 +1,65 @@
+use serde_json::Value as Json;
+
+use super::Res;
+
+enum Segment {
+    Field(String),
+    Index(usize),
+    Iterate,
+}
+
+/// Minimal jq-like selector supporting `.field`, `[index]` and `[]`, e.g. `.[].Name`
+pub struct Selector(Vec<Segment>);
+
+impl Selector {
+    pub fn parse(expr: &str) -> Res<Selector> {
+        let invalid = || format!("Invalid selector '{}'", expr);
+        let mut segments = Vec::new();
+        let mut rest = expr.trim();
+        if !rest.starts_with('.') {
+            return Err(invalid());
+        }
+
+        while !rest.is_empty() {
+            if let Some(tail) = rest.strip_prefix('[') {
+                let (inner, tail) = tail.split_once(']').ok_or_else(invalid)?;
+                segments.push(match inner.trim() {
+                    "" => Segment::Iterate,
+                    idx => Segment::Index(idx.parse().map_err(|_| invalid())?),
+                });
+                rest = tail;
+            } else if let Some(tail) = rest.strip_prefix('.') {
+                let end = tail.find(['.', '[']).unwrap_or(tail.len());
+                let (field, tail) = tail.split_at(end);
+                if !field.is_empty() {
+                    segments.push(Segment::Field(field.to_string()));
+                }
+                rest = tail;
+            } else {
+                return Err(invalid());
+            }
+        }
+        Ok(Selector(segments))
+    }
+
+    pub fn select(&self, value: Json) -> Vec<Json> {
+        self.0.iter().fold(vec![value], |values, segment| {
+            values
+                .into_iter()
+                .flat_map(|value| match (segment, value) {
+                    (Segment::Field(name), Json::Object(mut map)) => {
+                        vec![map.remove(name).unwrap_or(Json::Null)]
+                    }
+                    (Segment::Index(idx), Json::Array(mut items)) if *idx < items.len() => {
+                        vec![items.swap_remove(*idx)]
+                    }
+                    (Segment::Iterate, Json::Array(items)) => items,
+                    (Segment::Iterate, Json::Object(map)) => {
+                        map.into_iter().map(|(_, v)| v).collect()
+                    }
+                    _ => vec![Json::Null],
+                })
+                .collect()
+        })
+    }
+}
@@ -0,0 +1,329 @@
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::Path;
+use std::process::Command;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+
+use super::api::PlanGraph;
+use super::Res;
+
+const STATES: [&str; 4] = ["unchanged", "applied", "pending", "failed"];
+/// Time a status client gets to send its request and read the response
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Outcome of a single stack in a sync, `pending` stacks need changes that are not
+/// applied automatically
+#[derive(Serialize)]
+pub struct StackStatus {
+    pub endpoint: i32,
+    pub stack: String,
+    pub state: &'static str,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub detail: String,
+}
+
+/// State of the last sync, shared with the status server
+#[derive(Serialize, Default)]
+pub struct SyncStatus {
+    pub revision: Option<String>,
+    pub last_sync: Option<u64>,
+    pub last_success: Option<u64>,
+    pub duration_seconds: f64,
+    pub error: Option<String>,
+    pub syncs: u64,
+    pub failures: u64,
+    pub stacks: Vec<StackStatus>,
+}
+impl SyncStatus {
+    fn healthy(&self) -> bool {
+        self.last_sync.is_some() && self.error.is_none()
+    }
+
+    /// Records the result of a sync, a failed stack fails the whole sync
+    pub fn record(
+        &mut self,
+        revision: Option<String>,
+        started: Instant,
+        result: Res<Vec<StackStatus>>,
+    ) {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        self.revision = revision.or(self.revision.take());
+        self.last_sync = Some(now);
+        self.duration_seconds = started.elapsed().as_secs_f64();
+        self.syncs += 1;
+
+        let result = result.and_then(|stacks| {
+            let failed = stacks.iter().find(|s| s.state == "failed").map(|failed| {
+                format!(
+                    "Stack '{}' on endpoint {}: {}",
+                    failed.stack, failed.endpoint, failed.detail
+                )
+            });
+            self.stacks = stacks;
+            failed.map_or(Ok(()), Err)
+        });
+        match result {
+            Ok(()) => {
+                self.last_success = Some(now);
+                self.error = None;
+            }
+            Err(err) => {
+                self.failures += 1;
+                self.error = Some(err);
+            }
+        }
+    }
+
+    /// Prometheus text exposition of the sync state
+    fn metrics(&self) -> String {
+        let mut lines = Vec::new();
+        let mut metric = |name: &str, kind: &str, help: &str, samples: Vec<(String, f64)>| {
+            lines.push(format!("# HELP pctl_reconcile_{} {}", name, help));
+            lines.push(format!("# TYPE pctl_reconcile_{} {}", name, kind));
+            for (labels, value) in samples {
+                lines.push(format!("pctl_reconcile_{}{} {}", name, labels, value));
+            }
+        };
+        let single = |value: f64| vec![(String::new(), value)];
+
+        metric(
+            "syncs_total",
+            "counter",
+            "Syncs since start.",
+            single(self.syncs as f64),
+        );
+        metric(
+            "sync_failures_total",
+            "counter",
+            "Failed syncs since start.",
+            single(self.failures as f64),
+        );
+        metric(
+            "last_sync_success",
+            "gauge",
+            "Whether the last sync succeeded.",
+            single(if self.healthy() { 1.0 } else { 0.0 }),
+        );
+        metric(
+            "last_sync_timestamp_seconds",
+            "gauge",
+            "Time of the last sync.",
+            single(self.last_sync.unwrap_or_default() as f64),
+        );
+        metric(
+            "last_success_timestamp_seconds",
+            "gauge",
+            "Time of the last successful sync.",
+            single(self.last_success.unwrap_or_default() as f64),
+        );
+        metric(
+            "last_sync_duration_seconds",
+            "gauge",
+            "Duration of the last sync.",
+            single(self.duration_seconds),
+        );
+        metric(
+            "stacks",
+            "gauge",
+            "Stacks by state in the last sync.",
+            STATES
+                .iter()
+                .map(|state| {
+                    let count = self.stacks.iter().filter(|s| s.state == *state).count();
+                    (format!("{{state=\"{}\"}}", state), count as f64)
+                })
+                .collect(),
+        );
+        if let Some(revision) = &self.revision {
+            metric(
+                "revision_info",
+                "gauge",
+                "Revision of the repository at the last sync.",
+                vec![(format!("{{revision=\"{}\"}}", revision), 1.0)],
+            );
+        }
+        lines.push(String::new());
+        lines.join("\n")
+    }
+}
+
+fn git(dir: &Path, args: &[&str]) -> Res<String> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        .output()
+        .map_err(|err| format!("Cannot run git: {}", err))?;
+    if !output.status.success() {
+        return Err(format!(
+            "git {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// Fast-forwards the checked out branch of the repository
+pub fn pull(dir: &Path) -> Res<()> {
+    git(dir, &["pull", "--ff-only", "--quiet"]).map(|_| ())
+}
+
+/// Commit checked out in the repository, none when it is not a git repository
+pub fn revision(dir: &Path) -> Option<String> {
+    git(dir, &["rev-parse", "HEAD"]).ok()
+}
+
+/// Applies plans of stacks that differ from the endpoint in order, until one fails
+pub fn sync(plans: PlanGraph, allow_destructive: bool) -> Res<Vec<StackStatus>> {
+    let mut stacks = Vec::new();
+    for plan in plans.0 {
+        let Some(diff) = plan.diff()? else {
+            continue;
+        };
+        let status = |state, detail: String| StackStatus {
+            endpoint: diff.endpoint,
+            stack: diff.stack.to_string(),
+            state,
+            detail,
+        };
+        if diff.is_empty() {
+            stacks.push(status("unchanged", String::new()));
+            continue;
+        }
+
+        // removing services needs destructive changes allowed and an unprotected stack
+        let removed = plan.removed_services()?;
+        if !removed.is_empty() && !allow_destructive {
+            stacks.push(status(
+                "pending",
+                format!("would remove services {}", removed.join(", ")),
+            ));
+            continue;
+        }
+        if !removed.is_empty() && !plan.protected()?.is_empty() {
+            stacks.push(status(
+                "pending",
+                format!(
+                    "would remove services {} of a protected stack",
+                    removed.join(", ")
+                ),
+            ));
+            continue;
+        }
+
+        match plan.execute() {
+            Ok(()) => stacks.push(status("applied", String::new())),
+            Err(err) => {
+                stacks.push(status("failed", err));
+                break;
+            }
+        }
+    }
+    Ok(stacks)
+}
+
+/// Serves `/healthz`, `/status` as JSON and `/metrics` for Prometheus in the background
+pub fn serve(address: &str, status: Arc<Mutex<SyncStatus>>) -> Res<()> {
+    let listener = TcpListener::bind(address)
+        .map_err(|err| format!("Cannot listen on '{}': {}", address, err))?;
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let status = status.clone();
+            // each connection on its own thread, so idle clients do not block probes
+            thread::spawn(move || {
+                if let Err(err) = respond(stream, &status) {
+                    eprintln!("Status request failed: {}", err);
+                }
+            });
+        }
+    });
+    Ok(())
+}
+
+fn respond(mut stream: TcpStream, status: &Mutex<SyncStatus>) -> io::Result<()> {
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    stream.set_write_timeout(Some(REQUEST_TIMEOUT))?;
+    let mut reader = BufReader::new(&stream);
+    let mut request = String::new();
+    reader.read_line(&mut request)?;
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 0 && !header.trim().is_empty() {
+        header.clear();
+    }
+
+    let path = request.split_whitespace().nth(1).unwrap_or("/");
+    let status = status.lock().unwrap_or_else(|err| err.into_inner());
+    let (code, content_type, body) = match path {
+        "/healthz" if status.healthy() => ("200 OK", "text/plain", "ok\n".to_string()),
+        "/healthz" => (
+            "503 Service Unavailable",
+            "text/plain",
+            format!(
+                "{}\n",
+                status.error.as_deref().unwrap_or("waiting for first sync")
+            ),
+        ),
+        "/status" => (
+            "200 OK",
+            "application/json",
+            serde_json::to_string_pretty(&*status).map_err(io::Error::other)?,
+        ),
+        "/metrics" => ("200 OK", "text/plain; version=0.0.4", status.metrics()),
+        _ => ("404 Not Found", "text/plain", "not found\n".to_string()),
+    };
+    drop(status);
+
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        code,
+        content_type,
+        body.len(),
+        body
+    )?;
+    stream.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use super::*;
+
+    #[test]
+    fn idle_clients_do_not_block_health_checks() {
+        let address = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .to_string();
+        let status = Arc::new(Mutex::new(SyncStatus::default()));
+        status
+            .lock()
+            .unwrap()
+            .record(None, Instant::now(), Ok(Vec::new()));
+        serve(&address, status).unwrap();
+
+        let _idle = TcpStream::connect(&address).unwrap();
+        let mut probe = TcpStream::connect(&address).unwrap();
+        probe
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        probe
+            .write_all(b"GET /healthz HTTP/1.1\r\nHost: test\r\n\r\n")
+            .unwrap();
+        let mut response = String::new();
+        probe.read_to_string(&mut response).unwrap();
+
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.ends_with("ok\n"));
+    }
+}
@@ -2,6 +2,8 @@ use std::collections::{HashMap, HashSet};
 
 use serde::{Deserialize, Serialize};
 
+use super::Res;
+
 #[derive(Serialize)]
 struct Login {
     pub username: String,
@@ -16,8 +18,8 @@ pub struct JwtToken {
 
 #[derive(Deserialize)]
 pub struct Tag {
-    #[serde(rename = "id")]
-    pub id: String,
+    #[serde(rename = "ID")]
+    pub id: i32,
     #[serde(rename = "Name")]
     pub name: String,
     #[serde(rename = "Endpoints")]
@@ -33,13 +35,45 @@ impl Tag {
     }
 }
 
+#[derive(Serialize)]
+struct TagCreate {
+    #[serde(rename = "Name")]
+    name: String,
+}
+
 #[derive(Deserialize)]
-pub struct SwarmInfo {
-    #[serde(rename = "Cluster")]
-    pub cluster: String,
+pub struct EndpointGroup {
+    #[serde(rename = "Id")]
+    pub id: i32,
+    #[serde(rename = "Name")]
+    pub name: String,
+    #[serde(rename = "Description", default)]
+    pub description: String,
+    #[serde(rename = "TagIds", default)]
+    pub tag_ids: Vec<i32>,
+}
+
+#[derive(Serialize)]
+struct EndpointGroupCreate {
+    #[serde(rename = "Name")]
+    name: String,
+    #[serde(rename = "Description")]
+    description: String,
+    #[serde(rename = "TagIDs")]
+    tag_ids: Vec<i32>,
+}
+
+#[derive(Deserialize)]
+pub struct SwarmCluster {
     #[serde(rename = "ID")]
     pub id: String,
 }
+
+#[derive(Deserialize)]
+pub struct SwarmInfo {
+    #[serde(rename = "Cluster")]
+    pub cluster: SwarmCluster,
+}
 #[derive(Deserialize)]
 pub struct EndpointInfo {
     #[serde(rename = "Swarm")]
@@ -48,42 +82,119 @@ pub struct EndpointInfo {
 
 #[derive(Deserialize)]
 struct ConfigSecretSpec {
-    #[serde(rename = "name")]
+    #[serde(rename = "Name")]
     name: String,
-}
-
-#[derive(Serialize)]
-struct ConfigSecretFilter {
-    #[serde(rename = "id")]
-    id: Option<String>,
-    #[serde(rename = "names")]
-    names: Option<Vec<String>>,
+    #[serde(rename = "Labels", default)]
+    labels: HashMap<String, String>,
+    /// Base64 content, docker never returns it for secrets
+    #[serde(rename = "Data", default)]
+    data: String,
 }
 
 #[derive(Deserialize)]
 pub struct Config {
     #[serde(rename = "ID")]
     pub id: String,
-    #[serde(rename = "spec")]
+    #[serde(rename = "Spec")]
     spec: ConfigSecretSpec,
 }
 impl Config {
     pub fn name(&self) -> &String {
         &self.spec.name
     }
+    pub fn labels(&self) -> &HashMap<String, String> {
+        &self.spec.labels
+    }
+    pub fn content(&self) -> Res<String> {
+        use base64::engine::general_purpose;
+        use base64::Engine as _;
+        let data = general_purpose::STANDARD
+            .decode(&self.spec.data)
+            .map_err(|x| format!("Invalid data of config '{}': {}", self.name(), x))?;
+        Ok(String::from_utf8_lossy(&data).to_string())
+    }
 }
 
 #[derive(Deserialize)]
 pub struct Secret {
     #[serde(rename = "ID")]
     pub id: String,
-    #[serde(rename = "spec")]
+    #[serde(rename = "Spec")]
     spec: ConfigSecretSpec,
 }
 impl Secret {
     pub fn name(&self) -> &String {
         &self.spec.name
     }
+    pub fn labels(&self) -> &HashMap<String, String> {
+        &self.spec.labels
+    }
+}
+
+#[derive(Deserialize)]
+pub struct Volume {
+    #[serde(rename = "Name")]
+    pub name: String,
+    #[serde(rename = "Driver")]
+    pub driver: String,
+    #[serde(rename = "Scope", default)]
+    pub scope: String,
+    #[serde(rename = "Mountpoint", default)]
+    pub mountpoint: String,
+    #[serde(rename = "CreatedAt", default)]
+    pub created_at: String,
+    #[serde(rename = "Labels", default)]
+    labels: Option<HashMap<String, String>>,
+    #[serde(rename = "Options", default)]
+    options: Option<HashMap<String, String>>,
+}
+impl Volume {
+    pub fn labels(&self) -> HashMap<String, String> {
+        self.labels.clone().unwrap_or_default()
+    }
+    pub fn options(&self) -> HashMap<String, String> {
+        self.options.clone().unwrap_or_default()
+    }
+}
+
+#[derive(Deserialize)]
+pub struct VolumeList {
+    #[serde(rename = "Volumes", default)]
+    volumes: Option<Vec<Volume>>,
+}
+impl VolumeList {
+    pub fn volumes(self) -> Vec<Volume> {
+        self.volumes.unwrap_or_default()
+    }
+}
+
+/// Networks created by docker itself, they cannot be removed
+const PREDEFINED_NETWORKS: [&str; 5] = ["bridge", "host", "none", "ingress", "docker_gwbridge"];
+
+#[derive(Deserialize)]
+pub struct Network {
+    #[serde(rename = "Id")]
+    pub id: String,
+    #[serde(rename = "Name")]
+    pub name: String,
+    #[serde(rename = "Driver", default)]
+    pub driver: String,
+    #[serde(rename = "Scope", default)]
+    pub scope: String,
+    #[serde(rename = "Attachable", default)]
+    pub attachable: bool,
+    #[serde(rename = "Internal", default)]
+    pub internal: bool,
+    #[serde(rename = "Labels", default)]
+    labels: Option<HashMap<String, String>>,
+}
+impl Network {
+    pub fn labels(&self) -> HashMap<String, String> {
+        self.labels.clone().unwrap_or_default()
+    }
+    pub fn is_predefined(&self) -> bool {
+        PREDEFINED_NETWORKS.contains(&self.name.as_str())
+    }
 }
 
 #[derive(Deserialize, Clone)]
@@ -95,7 +206,38 @@ pub struct Stack {
     #[serde(rename = "SwarmId")]
     pub swarm_id: Option<String>,
     #[serde(rename = "EndpointId")]
-    pub endpoint_id: String,
+    pub endpoint_id: i32,
+    #[serde(rename = "Status")]
+    pub status: i32,
+    #[serde(rename = "Env", default)]
+    pub env: Vec<StackEnv>,
+}
+impl Stack {
+    pub fn is_active(&self) -> bool {
+        self.status == 1
+    }
+}
+
+/// Stack environment variables, sent and received as a list of name/value pairs
+#[derive(Serialize, Deserialize, Clone)]
+pub struct StackEnv {
+    pub name: String,
+    pub value: String,
+}
+
+fn stack_env(env: HashMap<String, String>) -> Vec<StackEnv> {
+    let mut env: Vec<StackEnv> = env
+        .into_iter()
+        .map(|(name, value)| StackEnv { name, value })
+        .collect();
+    env.sort_by(|a, b| a.name.cmp(&b.name));
+    env
+}
+
+#[derive(Deserialize)]
+pub struct StackFile {
+    #[serde(rename = "StackFileContent")]
+    pub content: String,
 }
 #[derive(Serialize)]
 struct StackFilter {
@@ -112,7 +254,7 @@ struct StackUpdate {
     #[serde(rename = "StackFileContent")]
     content: String,
     #[serde(rename = "Env")]
-    env: HashMap<String, String>,
+    env: Vec<StackEnv>,
     #[serde(rename = "Prune")]
     prune: bool,
 }
@@ -125,7 +267,227 @@ struct StackCreate {
     #[serde(rename = "StackFileContent")]
     content: String,
     #[serde(rename = "Env")]
-    env: HashMap<String, String>,
+    env: Vec<StackEnv>,
+}
+
+#[derive(Serialize, Default)]
+struct DockerFilter {
+    #[serde(rename = "label", skip_serializing_if = "Vec::is_empty")]
+    label: Vec<String>,
+    #[serde(rename = "service", skip_serializing_if = "Vec::is_empty")]
+    service: Vec<String>,
+    #[serde(rename = "desired-state", skip_serializing_if = "Vec::is_empty")]
+    desired_state: Vec<String>,
+    /// Docker matches names by prefix
+    #[serde(rename = "name", skip_serializing_if = "Vec::is_empty")]
+    name: Vec<String>,
+}
+
+#[derive(Deserialize, Default)]
+struct ServiceReplicated {
+    #[serde(rename = "Replicas")]
+    replicas: Option<u64>,
+}
+#[derive(Deserialize)]
+struct ServiceMode {
+    #[serde(rename = "Replicated")]
+    replicated: Option<ServiceReplicated>,
+}
+#[derive(Deserialize)]
+struct ConfigReference {
+    #[serde(rename = "ConfigID")]
+    id: String,
+}
+#[derive(Deserialize)]
+struct SecretReference {
+    #[serde(rename = "SecretID")]
+    id: String,
+}
+#[derive(Deserialize, Default)]
+struct ContainerSpec {
+    #[serde(rename = "TTY", default)]
+    tty: bool,
+    #[serde(rename = "Configs", default)]
+    configs: Vec<ConfigReference>,
+    #[serde(rename = "Secrets", default)]
+    secrets: Vec<SecretReference>,
+}
+#[derive(Deserialize, Default)]
+struct TaskTemplate {
+    #[serde(rename = "ContainerSpec", default)]
+    container_spec: ContainerSpec,
+}
+#[derive(Deserialize)]
+struct ServiceSpec {
+    #[serde(rename = "Name")]
+    name: String,
+    #[serde(rename = "Mode")]
+    mode: ServiceMode,
+    #[serde(rename = "TaskTemplate", default)]
+    task_template: TaskTemplate,
+    #[serde(rename = "Labels", default)]
+    labels: HashMap<String, String>,
+}
+
+#[derive(Deserialize)]
+pub struct Service {
+    #[serde(rename = "ID")]
+    pub id: String,
+    #[serde(rename = "Spec")]
+    spec: ServiceSpec,
+}
+impl Service {
+    pub fn name(&self) -> &String {
+        &self.spec.name
+    }
+    pub fn tty(&self) -> bool {
+        self.spec.task_template.container_spec.tty
+    }
+    /// Stack deploying the service, if any
+    pub fn stack(&self) -> Option<&String> {
+        self.spec.labels.get("com.docker.stack.namespace")
+    }
+    pub fn config_ids(&self) -> impl Iterator<Item = &String> {
+        let spec = &self.spec.task_template.container_spec;
+        spec.configs.iter().map(|c| &c.id)
+    }
+    pub fn secret_ids(&self) -> impl Iterator<Item = &String> {
+        let spec = &self.spec.task_template.container_spec;
+        spec.secrets.iter().map(|s| &s.id)
+    }
+    /// Desired number of replicas, `None` for global services
+    pub fn replicas(&self) -> Option<u64> {
+        self.spec
+            .mode
+            .replicated
+            .as_ref()
+            .map(|r| r.replicas.unwrap_or(1))
+    }
+}
+
+#[derive(Deserialize)]
+struct ContainerStatus {
+    #[serde(rename = "ContainerID")]
+    container_id: Option<String>,
+}
+#[derive(Deserialize)]
+struct TaskStatus {
+    #[serde(rename = "State")]
+    state: String,
+    #[serde(rename = "ContainerStatus")]
+    container_status: Option<ContainerStatus>,
+}
+
+#[derive(Deserialize)]
+pub struct Task {
+    #[serde(rename = "ID")]
+    pub id: String,
+    #[serde(rename = "ServiceID")]
+    pub service_id: String,
+    #[serde(rename = "NodeID")]
+    pub node_id: Option<String>,
+    #[serde(rename = "Slot")]
+    pub slot: Option<u64>,
+    #[serde(rename = "Status")]
+    status: TaskStatus,
+}
+impl Task {
+    pub fn is_running(&self) -> bool {
+        self.status.state == "running"
+    }
+    pub fn container_id(&self) -> Option<&String> {
+        self.status
+            .container_status
+            .as_ref()
+            .and_then(|c| c.container_id.as_ref())
+    }
+    /// Task name as shown by docker, e.g. `stack_web.1`
+    pub fn name(&self, service: &str) -> String {
+        match (&self.slot, &self.node_id) {
+            (Some(slot), _) => format!("{}.{}", service, slot),
+            (None, Some(node)) => format!("{}.{}", service, node),
+            (None, None) => format!("{}.{}", service, self.id),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct NodeDescription {
+    #[serde(rename = "Hostname")]
+    hostname: String,
+}
+#[derive(Deserialize)]
+pub struct Node {
+    #[serde(rename = "Description")]
+    description: NodeDescription,
+}
+impl Node {
+    pub fn hostname(&self) -> &String {
+        &self.description.hostname
+    }
+}
+
+#[derive(Serialize)]
+struct ExecCreate {
+    #[serde(rename = "AttachStdin")]
+    attach_stdin: bool,
+    #[serde(rename = "AttachStdout")]
+    attach_stdout: bool,
+    #[serde(rename = "AttachStderr")]
+    attach_stderr: bool,
+    #[serde(rename = "Tty")]
+    tty: bool,
+    #[serde(rename = "Cmd")]
+    cmd: Vec<String>,
+}
+#[derive(Deserialize)]
+pub struct ExecInstance {
+    #[serde(rename = "Id")]
+    pub id: String,
+}
+#[derive(Deserialize)]
+pub struct ExecInspect {
+    #[serde(rename = "Running")]
+    pub running: bool,
+    #[serde(rename = "ExitCode")]
+    pub exit_code: Option<i64>,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct DockerSnapshot {
+    #[serde(rename = "Time")]
+    pub time: i64,
+    #[serde(rename = "DockerVersion")]
+    pub docker_version: String,
+    #[serde(rename = "Swarm")]
+    pub swarm: bool,
+    #[serde(rename = "RunningContainerCount")]
+    pub running_containers: u32,
+    #[serde(rename = "StoppedContainerCount")]
+    pub stopped_containers: u32,
+    #[serde(rename = "StackCount")]
+    pub stacks: u32,
+    #[serde(rename = "ServiceCount")]
+    pub services: u32,
+    #[serde(rename = "VolumeCount")]
+    pub volumes: u32,
+    #[serde(rename = "ImageCount")]
+    pub images: u32,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct KubernetesSnapshot {
+    #[serde(rename = "Time")]
+    pub time: i64,
+    #[serde(rename = "KubernetesVersion")]
+    pub kubernetes_version: String,
+    #[serde(rename = "NodeCount")]
+    pub nodes: u32,
+}
+#[derive(Deserialize, Clone, Default)]
+struct KubernetesData {
+    #[serde(rename = "Snapshots", default)]
+    snapshots: Vec<KubernetesSnapshot>,
 }
 
 #[derive(Deserialize)]
@@ -134,8 +496,26 @@ pub struct Endpoint {
     id: i32,
     #[serde(rename = "Name")]
     name: String,
-    #[serde(rename = "TagIds")]
-    tag_ids: Vec<String>,
+    #[serde(rename = "Type")]
+    pub kind: i32,
+    #[serde(rename = "URL")]
+    pub url: String,
+    #[serde(rename = "PublicURL", default)]
+    pub public_url: String,
+    #[serde(rename = "GroupId")]
+    pub group_id: i32,
+    #[serde(rename = "TagIds", default)]
+    tag_ids: Vec<i32>,
+    #[serde(rename = "Status")]
+    pub status: i32,
+    #[serde(rename = "Heartbeat", default)]
+    pub heartbeat: bool,
+    #[serde(rename = "LastCheckInDate", default)]
+    pub last_check_in: i64,
+    #[serde(rename = "Snapshots", default)]
+    snapshots: Vec<DockerSnapshot>,
+    #[serde(rename = "Kubernetes", default)]
+    kubernetes: KubernetesData,
 }
 impl Endpoint {
     pub fn name(&self) -> &str {
@@ -144,6 +524,127 @@ impl Endpoint {
     pub fn id(&self) -> i32 {
         self.id
     }
+    pub fn tag_ids(&self) -> &Vec<i32> {
+        &self.tag_ids
+    }
+    pub fn docker_snapshot(&self) -> Option<&DockerSnapshot> {
+        self.snapshots.iter().max_by_key(|s| s.time)
+    }
+    pub fn kubernetes_snapshot(&self) -> Option<&KubernetesSnapshot> {
+        self.kubernetes.snapshots.iter().max_by_key(|s| s.time)
+    }
+}
+
+#[derive(Serialize)]
+struct EndpointUpdate {
+    #[serde(rename = "Name", skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+    #[serde(rename = "URL", skip_serializing_if = "Option::is_none")]
+    url: Option<String>,
+    #[serde(rename = "PublicURL", skip_serializing_if = "Option::is_none")]
+    public_url: Option<String>,
+    #[serde(rename = "GroupID", skip_serializing_if = "Option::is_none")]
+    group_id: Option<i32>,
+    #[serde(rename = "TagIDs", skip_serializing_if = "Option::is_none")]
+    tag_ids: Option<Vec<i32>>,
+}
+
+/// Registry `Type` follows portainer's `RegistryType`: 1 quay, 2 azure, 3 custom,
+/// 4 gitlab, 5 proget, 6 dockerhub, 7 ecr, 8 github
+#[derive(Deserialize)]
+pub struct Registry {
+    #[serde(rename = "Id")]
+    pub id: i32,
+    #[serde(rename = "Name")]
+    pub name: String,
+    #[serde(rename = "Type")]
+    pub kind: i32,
+    #[serde(rename = "URL")]
+    pub url: String,
+    #[serde(rename = "Authentication", default)]
+    pub authentication: bool,
+    #[serde(rename = "Username", default)]
+    pub username: String,
+}
+
+#[derive(Serialize)]
+pub struct GitlabRegistryData {
+    #[serde(rename = "InstanceURL")]
+    pub instance_url: String,
+}
+
+#[derive(Serialize)]
+pub struct EcrRegistryData {
+    #[serde(rename = "Region")]
+    pub region: String,
+}
+
+#[derive(Serialize)]
+struct RegistryCreate {
+    #[serde(rename = "Name")]
+    name: String,
+    #[serde(rename = "Type")]
+    kind: i32,
+    #[serde(rename = "URL")]
+    url: String,
+    #[serde(rename = "Authentication")]
+    authentication: bool,
+    #[serde(rename = "Username")]
+    username: String,
+    #[serde(rename = "Password")]
+    password: String,
+    #[serde(rename = "Gitlab", skip_serializing_if = "Option::is_none")]
+    gitlab: Option<GitlabRegistryData>,
+    #[serde(rename = "Ecr", skip_serializing_if = "Option::is_none")]
+    ecr: Option<EcrRegistryData>,
+}
+
+#[derive(Serialize)]
+struct RegistryUpdate {
+    #[serde(rename = "Name", skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+    #[serde(rename = "URL", skip_serializing_if = "Option::is_none")]
+    url: Option<String>,
+    #[serde(rename = "Authentication")]
+    authentication: bool,
+    #[serde(rename = "Username", skip_serializing_if = "Option::is_none")]
+    username: Option<String>,
+    #[serde(rename = "Password", skip_serializing_if = "Option::is_none")]
+    password: Option<String>,
+}
+
+#[derive(Serialize)]
+struct VolumeRequest {
+    #[serde(rename = "Name")]
+    name: String,
+    #[serde(rename = "Driver", skip_serializing_if = "Option::is_none")]
+    driver: Option<String>,
+    #[serde(rename = "DriverOpts")]
+    options: HashMap<String, String>,
+    #[serde(rename = "Labels")]
+    labels: HashMap<String, String>,
+}
+
+#[derive(Serialize)]
+struct NetworkRequest {
+    #[serde(rename = "Name")]
+    name: String,
+    #[serde(rename = "Driver", skip_serializing_if = "Option::is_none")]
+    driver: Option<String>,
+    #[serde(rename = "Attachable")]
+    attachable: bool,
+    #[serde(rename = "Internal")]
+    internal: bool,
+    #[serde(rename = "Labels")]
+    labels: HashMap<String, String>,
+    #[serde(rename = "CheckDuplicate")]
+    check_duplicate: bool,
+}
+
+#[derive(Deserialize)]
+pub struct NetworkCreated {
+    #[serde(rename = "Id")]
+    pub id: String,
 }
 
 #[derive(Serialize)]
@@ -163,7 +664,7 @@ fn base64(data: String) -> String {
 }
 
 pub mod raw_requests {
-    use crate::portainer::client::{PortainerRequest, PortainerRequestRaw};
+    use crate::portainer::client::{HttpMethod, PortainerRequest, PortainerRequestRaw};
     use crate::portainer::requests::*;
 
     pub fn login(username: &str, password: &str) -> PortainerRequest<JwtToken> {
@@ -180,17 +681,118 @@ pub mod raw_requests {
     pub fn list_tags() -> PortainerRequest<Vec<Tag>> {
         PortainerRequestRaw::get("/tags").into()
     }
+    pub fn create_tag(name: String) -> PortainerRequest<Tag> {
+        PortainerRequestRaw::post("/tags", TagCreate { name }).into()
+    }
+    pub fn delete_tag(id: i32) -> PortainerRequestRaw {
+        PortainerRequestRaw::delete(&format!("/tags/{}", id))
+    }
+
+    pub fn list_endpoint_groups() -> PortainerRequest<Vec<EndpointGroup>> {
+        PortainerRequestRaw::get("/endpoint_groups").into()
+    }
+    pub fn create_endpoint_group(
+        name: String,
+        description: String,
+        tag_ids: Vec<i32>,
+    ) -> PortainerRequest<EndpointGroup> {
+        PortainerRequestRaw::post(
+            "/endpoint_groups",
+            EndpointGroupCreate {
+                name,
+                description,
+                tag_ids,
+            },
+        )
+        .into()
+    }
+    pub fn delete_endpoint_group(id: i32) -> PortainerRequestRaw {
+        PortainerRequestRaw::delete(&format!("/endpoint_groups/{}", id))
+    }
+    pub fn add_endpoint_to_group(group: i32, endpoint: i32) -> PortainerRequestRaw {
+        PortainerRequestRaw::new(
+            HttpMethod::PUT,
+            &format!("/endpoint_groups/{}/endpoints/{}", group, endpoint),
+            None,
+        )
+    }
+
+    pub fn list_registries() -> PortainerRequest<Vec<Registry>> {
+        PortainerRequestRaw::get("/registries").into()
+    }
+    /// Registries the current user may pull from on the endpoint
+    pub fn list_endpoint_registries(endpoint: i32) -> PortainerRequest<Vec<Registry>> {
+        PortainerRequestRaw::get(&format!("/endpoints/{}/registries", endpoint)).into()
+    }
+    pub fn create_registry(
+        name: String,
+        kind: i32,
+        url: String,
+        username: Option<String>,
+        password: Option<String>,
+        gitlab: Option<GitlabRegistryData>,
+        ecr: Option<EcrRegistryData>,
+    ) -> PortainerRequest<Registry> {
+        PortainerRequestRaw::post(
+            "/registries",
+            RegistryCreate {
+                name,
+                kind,
+                url,
+                authentication: username.is_some(),
+                username: username.unwrap_or_default(),
+                password: password.unwrap_or_default(),
+                gitlab,
+                ecr,
+            },
+        )
+        .into()
+    }
+    pub fn update_registry(
+        id: i32,
+        name: Option<String>,
+        url: Option<String>,
+        authentication: bool,
+        username: Option<String>,
+        password: Option<String>,
+    ) -> PortainerRequestRaw {
+        PortainerRequestRaw::put(
+            &format!("/registries/{}", id),
+            RegistryUpdate {
+                name,
+                url,
+                authentication,
+                username,
+                password,
+            },
+        )
+    }
+    pub fn delete_registry(id: i32) -> PortainerRequestRaw {
+        PortainerRequestRaw::delete(&format!("/registries/{}", id))
+    }
+    /// Grants the endpoint access to the registry, so deploys log in to it on the nodes
+    pub fn grant_registry_access(endpoint: i32, registry: i32) -> PortainerRequestRaw {
+        PortainerRequestRaw::put(
+            &format!("/endpoints/{}/registries/{}", endpoint, registry),
+            serde_json::json!({ "UserAccessPolicies": {}, "TeamAccessPolicies": {} }),
+        )
+    }
 
     pub fn get_endpoint_info(id: i32) -> PortainerRequest<EndpointInfo> {
         PortainerRequestRaw::get(&format!("/endpoints/{}/docker/info", id)).into()
     }
 
-    pub fn create_secret(endpoint: i32, name: String, content: String) -> PortainerRequestRaw {
+    pub fn create_secret(
+        endpoint: i32,
+        name: String,
+        labels: HashMap<String, String>,
+        content: String,
+    ) -> PortainerRequestRaw {
         PortainerRequestRaw::post(
             &format!("/endpoints/{}/docker/secrets/create", endpoint),
             ConfigSecretRequest {
                 name,
-                labels: HashMap::new(),
+                labels,
                 content: base64(content),
             },
         )
@@ -199,22 +801,27 @@ pub mod raw_requests {
         PortainerRequestRaw::delete(&format!("/endpoints/{}/docker/secrets/{}", endpoint, id))
     }
 
-    pub fn list_secrets(
-        endpoint: i32,
-        id: Option<String>,
-        names: Option<Vec<String>>,
-    ) -> PortainerRequest<Vec<Secret>> {
+    /// Secrets whose names start with one of the names, all secrets without names
+    pub fn list_secrets(endpoint: i32, names: Vec<String>) -> PortainerRequest<Vec<Secret>> {
         PortainerRequestRaw::get(&format!("/endpoints/{}/docker/secrets", endpoint))
-            .with_filters(ConfigSecretFilter { id, names })
+            .with_filters(DockerFilter {
+                name: names,
+                ..Default::default()
+            })
             .into()
     }
 
-    pub fn create_config(endpoint: i32, name: String, content: String) -> PortainerRequestRaw {
+    pub fn create_config(
+        endpoint: i32,
+        name: String,
+        labels: HashMap<String, String>,
+        content: String,
+    ) -> PortainerRequestRaw {
         PortainerRequestRaw::post(
             &format!("/endpoints/{}/docker/configs/create", endpoint),
             ConfigSecretRequest {
                 name,
-                labels: HashMap::new(),
+                labels,
                 content: base64(content),
             },
         )
@@ -223,13 +830,13 @@ pub mod raw_requests {
         PortainerRequestRaw::delete(&format!("/endpoints/{}/docker/configs/{}", endpoint, id))
     }
 
-    pub fn list_configs(
-        endpoint: i32,
-        id: Option<String>,
-        names: Option<Vec<String>>,
-    ) -> PortainerRequest<Vec<Config>> {
+    /// Configs whose names start with one of the names, all configs without names
+    pub fn list_configs(endpoint: i32, names: Vec<String>) -> PortainerRequest<Vec<Config>> {
         PortainerRequestRaw::get(&format!("/endpoints/{}/docker/configs", endpoint))
-            .with_filters(ConfigSecretFilter { id, names })
+            .with_filters(DockerFilter {
+                name: names,
+                ..Default::default()
+            })
             .into()
     }
 
@@ -245,6 +852,70 @@ pub mod raw_requests {
     pub fn get_endpoint(id: i32) -> PortainerRequest<Endpoint> {
         PortainerRequestRaw::get(&format!("/endpoints/{}", id)).into()
     }
+    /// Endpoints are created with a multipart form, `creation_type` follows portainer's
+    /// `EndpointCreationType`: 1 docker, 2 agent, 4 edge agent, 5 local kubernetes
+    #[allow(clippy::too_many_arguments)]
+    pub fn create_endpoint(
+        name: String,
+        creation_type: i32,
+        url: Option<String>,
+        public_url: Option<String>,
+        group_id: Option<i32>,
+        tag_ids: Vec<i32>,
+        tls: bool,
+        tls_skip_verify: bool,
+    ) -> PortainerRequest<Endpoint> {
+        let mut form = vec![
+            ("Name".to_string(), name),
+            (
+                "EndpointCreationType".to_string(),
+                creation_type.to_string(),
+            ),
+            (
+                "TagIds".to_string(),
+                serde_json::to_string(&tag_ids).unwrap(),
+            ),
+        ];
+        let optional = [
+            ("URL", url),
+            ("PublicURL", public_url),
+            ("GroupID", group_id.map(|id| id.to_string())),
+        ];
+        for (key, value) in optional {
+            if let Some(value) = value {
+                form.push((key.to_string(), value));
+            }
+        }
+        if tls {
+            form.push(("TLS".to_string(), "true".to_string()));
+            form.push(("TLSSkipVerify".to_string(), tls_skip_verify.to_string()));
+            form.push(("TLSSkipClientVerify".to_string(), "true".to_string()));
+        }
+        PortainerRequestRaw::post_form("/endpoints", form).into()
+    }
+    pub fn update_endpoint(
+        id: i32,
+        name: Option<String>,
+        url: Option<String>,
+        public_url: Option<String>,
+        group_id: Option<i32>,
+        tag_ids: Option<Vec<i32>>,
+    ) -> PortainerRequest<Endpoint> {
+        PortainerRequestRaw::put(
+            &format!("/endpoints/{}", id),
+            EndpointUpdate {
+                name,
+                url,
+                public_url,
+                group_id,
+                tag_ids,
+            },
+        )
+        .into()
+    }
+    pub fn delete_endpoint(id: i32) -> PortainerRequestRaw {
+        PortainerRequestRaw::delete(&format!("/endpoints/{}", id))
+    }
 
     pub fn list_stacks(
         endpoint_id: Option<i32>,
@@ -268,11 +939,11 @@ pub mod raw_requests {
         prune: bool,
     ) -> PortainerRequestRaw {
         PortainerRequestRaw::put(
-            "/stacks",
+            &format!("/stacks/{}", id),
             StackUpdate {
                 id,
                 content,
-                env,
+                env: stack_env(env),
                 prune,
             },
         )
@@ -291,7 +962,7 @@ pub mod raw_requests {
                 swarm_id,
                 name,
                 content,
-                env,
+                env: stack_env(env),
             },
         )
         .with_query("endpointId", &format!("{}", endpoint_id))
@@ -301,4 +972,184 @@ pub mod raw_requests {
     pub fn delete_stack(id: i32) -> PortainerRequestRaw {
         PortainerRequestRaw::delete(&format!("/stacks/{}", id))
     }
+    pub fn get_stack_file(id: i32) -> PortainerRequest<StackFile> {
+        PortainerRequestRaw::get(&format!("/stacks/{}/file", id)).into()
+    }
+    pub fn start_stack(endpoint_id: i32, id: i32) -> PortainerRequestRaw {
+        PortainerRequestRaw::post(&format!("/stacks/{}/start", id), ())
+            .with_query("endpointId", &endpoint_id.to_string())
+    }
+    pub fn stop_stack(endpoint_id: i32, id: i32) -> PortainerRequestRaw {
+        PortainerRequestRaw::post(&format!("/stacks/{}/stop", id), ())
+            .with_query("endpointId", &endpoint_id.to_string())
+    }
+
+    pub fn list_volumes(endpoint: i32) -> PortainerRequest<VolumeList> {
+        PortainerRequestRaw::get(&format!("/endpoints/{}/docker/volumes", endpoint)).into()
+    }
+    pub fn get_volume(endpoint: i32, name: &str) -> PortainerRequest<Volume> {
+        PortainerRequestRaw::get(&format!("/endpoints/{}/docker/volumes/{}", endpoint, name)).into()
+    }
+    pub fn create_volume(
+        endpoint: i32,
+        name: String,
+        driver: Option<String>,
+        options: HashMap<String, String>,
+        labels: HashMap<String, String>,
+    ) -> PortainerRequest<Volume> {
+        PortainerRequestRaw::post(
+            &format!("/endpoints/{}/docker/volumes/create", endpoint),
+            VolumeRequest {
+                name,
+                driver,
+                options,
+                labels,
+            },
+        )
+        .into()
+    }
+    pub fn delete_volume(endpoint: i32, name: &str) -> PortainerRequestRaw {
+        PortainerRequestRaw::delete(&format!("/endpoints/{}/docker/volumes/{}", endpoint, name))
+    }
+    pub fn list_networks(endpoint: i32) -> PortainerRequest<Vec<Network>> {
+        PortainerRequestRaw::get(&format!("/endpoints/{}/docker/networks", endpoint)).into()
+    }
+    pub fn get_network(endpoint: i32, id: &str) -> PortainerRequest<Network> {
+        PortainerRequestRaw::get(&format!("/endpoints/{}/docker/networks/{}", endpoint, id)).into()
+    }
+    pub fn create_network(
+        endpoint: i32,
+        name: String,
+        driver: Option<String>,
+        attachable: bool,
+        internal: bool,
+        labels: HashMap<String, String>,
+    ) -> PortainerRequest<NetworkCreated> {
+        PortainerRequestRaw::post(
+            &format!("/endpoints/{}/docker/networks/create", endpoint),
+            NetworkRequest {
+                name,
+                driver,
+                attachable,
+                internal,
+                labels,
+                check_duplicate: true,
+            },
+        )
+        .into()
+    }
+    pub fn delete_network(endpoint: i32, id: &str) -> PortainerRequestRaw {
+        PortainerRequestRaw::delete(&format!("/endpoints/{}/docker/networks/{}", endpoint, id))
+    }
+
+    pub fn list_services(endpoint: i32) -> PortainerRequest<Vec<Service>> {
+        PortainerRequestRaw::get(&format!("/endpoints/{}/docker/services", endpoint)).into()
+    }
+    pub fn list_stack_services(endpoint: i32, stack: &str) -> PortainerRequest<Vec<Service>> {
+        PortainerRequestRaw::get(&format!("/endpoints/{}/docker/services", endpoint))
+            .with_filters(DockerFilter {
+                label: vec![format!("com.docker.stack.namespace={}", stack)],
+                ..Default::default()
+            })
+            .into()
+    }
+    pub fn list_service_tasks(endpoint: i32, services: Vec<String>) -> PortainerRequest<Vec<Task>> {
+        PortainerRequestRaw::get(&format!("/endpoints/{}/docker/tasks", endpoint))
+            .with_filters(DockerFilter {
+                service: services,
+                ..Default::default()
+            })
+            .into()
+    }
+    pub fn service_logs(
+        endpoint: i32,
+        service: &str,
+        follow: bool,
+        since: Option<u64>,
+        tail: Option<String>,
+        timestamps: bool,
+    ) -> PortainerRequestRaw {
+        PortainerRequestRaw::get(&format!(
+            "/endpoints/{}/docker/services/{}/logs",
+            endpoint, service
+        ))
+        .with_query("stdout", "1")
+        .with_query("stderr", "1")
+        .with_query("details", "1")
+        .with_query("follow", if follow { "1" } else { "0" })
+        .with_query("timestamps", if timestamps { "1" } else { "0" })
+        .with_query_opt("since", since)
+        .with_query_opt("tail", tail)
+    }
+    pub fn get_node(endpoint: i32, id: &str) -> PortainerRequest<Node> {
+        PortainerRequestRaw::get(&format!("/endpoints/{}/docker/nodes/{}", endpoint, id)).into()
+    }
+
+    /// Docker requests on agent endpoints are routed to the node named by this header,
+    /// without it the agent picks the node
+    const AGENT_TARGET: &str = "X-PortainerAgent-Target";
+
+    pub fn create_exec(
+        endpoint: i32,
+        node: Option<&str>,
+        container: &str,
+        cmd: Vec<String>,
+        tty: bool,
+    ) -> PortainerRequest<ExecInstance> {
+        PortainerRequestRaw::post(
+            &format!(
+                "/endpoints/{}/docker/containers/{}/exec",
+                endpoint, container
+            ),
+            ExecCreate {
+                attach_stdin: true,
+                attach_stdout: true,
+                attach_stderr: true,
+                tty,
+                cmd,
+            },
+        )
+        .with_header_opt(AGENT_TARGET, node)
+        .into()
+    }
+    pub fn resize_exec(
+        endpoint: i32,
+        node: Option<&str>,
+        id: &str,
+        width: u16,
+        height: u16,
+    ) -> PortainerRequestRaw {
+        PortainerRequestRaw::post(
+            &format!("/endpoints/{}/docker/exec/{}/resize", endpoint, id),
+            (),
+        )
+        .with_header_opt(AGENT_TARGET, node)
+        .with_query("h", &height.to_string())
+        .with_query("w", &width.to_string())
+    }
+    pub fn inspect_exec(
+        endpoint: i32,
+        node: Option<&str>,
+        id: &str,
+    ) -> PortainerRequest<ExecInspect> {
+        PortainerRequestRaw::get(&format!("/endpoints/{}/docker/exec/{}/json", endpoint, id))
+            .with_header_opt(AGENT_TARGET, node)
+            .into()
+    }
+    pub fn attach_exec(endpoint: i32, node: Option<&str>, id: &str) -> PortainerRequestRaw {
+        PortainerRequestRaw::get("/websocket/exec")
+            .with_query("endpointId", &endpoint.to_string())
+            .with_query_opt("nodeName", node)
+            .with_query("id", id)
+    }
+
+    pub fn list_running_tasks(endpoint: i32, services: Vec<String>) -> PortainerRequest<Vec<Task>> {
+        PortainerRequestRaw::get(&format!("/endpoints/{}/docker/tasks", endpoint))
+            .with_filters(DockerFilter {
+                service: services,
+                desired_state: vec!["running".to_string()],
+                ..Default::default()
+            })
+            .into()
+    }
 }
@@ -0,0 +1,319 @@
+use std::path::Path;
+use std::process::Command;
+use std::rc::Rc;
+
+use serde_json::Value as Json;
+
+use super::decrypt::Keyring;
+use super::Res;
+
+/// Resolves references of one scheme, like `vault://secret/app#password`, to secret values
+pub trait SecretProvider {
+    /// Prefix of references handled by the provider, e.g. `vault://` or `env:`
+    fn scheme(&self) -> &'static str;
+    /// Value of a reference without its scheme
+    fn resolve(&self, reference: &str) -> Res<String>;
+}
+
+/// Reads process environment variables, `env:DB_PASSWORD`
+pub struct EnvProvider;
+impl SecretProvider for EnvProvider {
+    fn scheme(&self) -> &'static str {
+        "env:"
+    }
+
+    fn resolve(&self, reference: &str) -> Res<String> {
+        std::env::var(reference)
+            .map_err(|_| format!("Environment variable '{}' is not set", reference))
+    }
+}
+
+/// Reads files, decrypting encrypted ones, `file:db/password.age` or a plain path
+pub struct FileProvider(pub Rc<Keyring>);
+impl SecretProvider for FileProvider {
+    fn scheme(&self) -> &'static str {
+        "file:"
+    }
+
+    fn resolve(&self, reference: &str) -> Res<String> {
+        self.0
+            .read(Path::new(reference))
+            .map(|(content, _)| content)
+    }
+}
+
+/// Runs a shell command and uses its output without the trailing newline,
+/// `exec:pass show db/password`
+pub struct ExecProvider;
+impl SecretProvider for ExecProvider {
+    fn scheme(&self) -> &'static str {
+        "exec:"
+    }
+
+    fn resolve(&self, reference: &str) -> Res<String> {
+        let output = Command::new("sh")
+            .arg("-c")
+            .arg(reference)
+            .output()
+            .map_err(|err| format!("Cannot run '{}': {}", reference, err))?;
+        if !output.status.success() {
+            return Err(format!(
+                "Command '{}' failed: {}",
+                reference,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+        let mut value = String::from_utf8(output.stdout)
+            .map_err(|_| format!("Output of '{}' is not UTF-8", reference))?;
+        if value.ends_with('\n') {
+            value.pop();
+        }
+        Ok(value)
+    }
+}
+
+/// Reads keys of HashiCorp Vault KV v2 secrets, `vault://<mount>/<path>#<key>`
+pub struct VaultProvider {
+    pub address: Option<String>,
+    pub token: Option<String>,
+    pub namespace: Option<String>,
+}
+impl VaultProvider {
+    /// The server and token come from `$VAULT_ADDR` and `$VAULT_TOKEN`, or `~/.vault-token`,
+    /// and `$VAULT_NAMESPACE` when set
+    pub fn from_env() -> VaultProvider {
+        let token = std::env::var("VAULT_TOKEN").ok().or_else(|| {
+            std::env::var("HOME")
+                .ok()
+                .and_then(|home| {
+                    std::fs::read_to_string(Path::new(&home).join(".vault-token")).ok()
+                })
+                .map(|token| token.trim().to_string())
+        });
+        VaultProvider {
+            address: std::env::var("VAULT_ADDR").ok(),
+            token,
+            namespace: std::env::var("VAULT_NAMESPACE").ok(),
+        }
+    }
+}
+impl SecretProvider for VaultProvider {
+    fn scheme(&self) -> &'static str {
+        "vault://"
+    }
+
+    fn resolve(&self, reference: &str) -> Res<String> {
+        let (path, key) = reference.split_once('#').ok_or_else(|| {
+            format!(
+                "Vault reference '{}' needs a key, e.g. vault://secret/app#password",
+                reference
+            )
+        })?;
+        let (mount, secret) = path
+            .split_once('/')
+            .ok_or_else(|| format!("Vault reference '{}' needs a mount and a path", reference))?;
+        let address = self
+            .address
+            .as_ref()
+            .ok_or_else(|| "Vault address is required, set VAULT_ADDR".to_string())?;
+        let token = self
+            .token
+            .as_ref()
+            .ok_or_else(|| "Vault token is required, set VAULT_TOKEN".to_string())?;
+        let url = format!(
+            "{}/v1/{}/data/{}",
+            address.trim_end_matches('/'),
+            mount,
+            secret
+        );
+
+        let mut request = reqwest::blocking::Client::new()
+            .get(&url)
+            .header("X-Vault-Token", token);
+        if let Some(namespace) = &self.namespace {
+            request = request.header("X-Vault-Namespace", namespace);
+        }
+        let response = request
+            .send()
+            .map_err(|err| format!("Cannot reach Vault: {}", err))?;
+        match response.status().as_u16() {
+            200 => (),
+            404 => return Err(format!("Vault secret '{}' not found", path)),
+            403 => return Err(format!("Vault denied access to '{}'", path)),
+            status => {
+                return Err(format!(
+                    "Vault returned {} for '{}': {}",
+                    status,
+                    path,
+                    response.text().unwrap_or_default()
+                ))
+            }
+        }
+        let body: Json = response
+            .json()
+            .map_err(|err| format!("Invalid Vault response: {}", err))?;
+        match &body["data"]["data"][key] {
+            Json::Null => Err(format!("Vault secret '{}' has no key '{}'", path, key)),
+            Json::String(value) => Ok(value.to_string()),
+            value => Ok(value.to_string()),
+        }
+    }
+}
+
+/// Whether a source is a reference for a provider other than plain files
+pub fn is_reference(source: &str) -> bool {
+    ["vault://", "env:", "exec:"]
+        .iter()
+        .any(|scheme| source.starts_with(scheme))
+}
+
+/// Providers resolving the sources of configs and secrets, sources without the scheme of
+/// a provider are files
+pub struct SecretProviders {
+    providers: Vec<Box<dyn SecretProvider>>,
+    files: FileProvider,
+}
+impl SecretProviders {
+    pub fn new(keys: Rc<Keyring>) -> SecretProviders {
+        SecretProviders {
+            providers: vec![
+                Box::new(VaultProvider::from_env()),
+                Box::new(EnvProvider),
+                Box::new(ExecProvider),
+            ],
+            files: FileProvider(keys),
+        }
+    }
+
+    pub fn resolve(&self, source: &str) -> Res<String> {
+        for provider in &self.providers {
+            if let Some(reference) = source.strip_prefix(provider.scheme()) {
+                return provider.resolve(reference);
+            }
+        }
+        let path = source.strip_prefix(self.files.scheme()).unwrap_or(source);
+        self.files.resolve(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::thread::{self, JoinHandle};
+
+    use super::*;
+
+    /// Answers a single request and returns its head
+    fn serve(status: &str, body: &str) -> (String, JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = format!("http://{}", listener.local_addr().unwrap());
+        let response = format!(
+            "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            status,
+            body.len(),
+            body
+        );
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = Vec::new();
+            let mut buf = [0u8; 1024];
+            while !request.ends_with(b"\r\n\r\n") {
+                let n = stream.read(&mut buf).unwrap();
+                if n == 0 {
+                    break;
+                }
+                request.extend_from_slice(&buf[..n]);
+            }
+            stream.write_all(response.as_bytes()).unwrap();
+            String::from_utf8(request).unwrap()
+        });
+        (address, server)
+    }
+
+    fn vault(address: &str) -> VaultProvider {
+        VaultProvider {
+            address: Some(address.to_string()),
+            token: Some("root-token".to_string()),
+            namespace: Some("team".to_string()),
+        }
+    }
+
+    #[test]
+    fn vault_reads_key_of_secret() {
+        let (address, server) = serve(
+            "200 OK",
+            r#"{"data": {"data": {"password": "hunter2", "port": 5432}}}"#,
+        );
+
+        let value = vault(&address).resolve("secret/app/db#password");
+
+        assert_eq!(value, Ok("hunter2".to_string()));
+        let request = server.join().unwrap().to_lowercase();
+        assert!(request.starts_with("get /v1/secret/data/app/db "));
+        assert!(request.contains("x-vault-token: root-token"));
+        assert!(request.contains("x-vault-namespace: team"));
+    }
+
+    #[test]
+    fn vault_reports_missing_secret() {
+        let (address, server) = serve("404 Not Found", r#"{"errors": []}"#);
+
+        let value = vault(&address).resolve("secret/app#password");
+
+        assert_eq!(
+            value,
+            Err("Vault secret 'secret/app' not found".to_string())
+        );
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn vault_reports_denied_access() {
+        let (address, server) = serve("403 Forbidden", r#"{"errors": ["permission denied"]}"#);
+
+        let value = vault(&address).resolve("secret/app#password");
+
+        assert_eq!(
+            value,
+            Err("Vault denied access to 'secret/app'".to_string())
+        );
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn vault_reports_missing_key() {
+        let (address, server) = serve("200 OK", r#"{"data": {"data": {"user": "app"}}}"#);
+
+        let value = vault(&address).resolve("secret/app#password");
+
+        assert_eq!(
+            value,
+            Err("Vault secret 'secret/app' has no key 'password'".to_string())
+        );
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn vault_requires_address_and_token() {
+        let provider = VaultProvider {
+            address: None,
+            token: None,
+            namespace: None,
+        };
+        assert_eq!(
+            provider.resolve("secret/app#password"),
+            Err("Vault address is required, set VAULT_ADDR".to_string())
+        );
+
+        let provider = VaultProvider {
+            address: Some("http://127.0.0.1:1".to_string()),
+            token: None,
+            namespace: None,
+        };
+        assert_eq!(
+            provider.resolve("secret/app#password"),
+            Err("Vault token is required, set VAULT_TOKEN".to_string())
+        );
+    }
+}
@@ -1,34 +1,48 @@
 use std::{
     collections::HashMap,
     fs::{self, File},
-    io::Read,
     path::Path,
 };
 
 use serde::{Deserialize, Serialize};
 
-use super::{api::Authentication, Action, Res};
+use super::{api::Authentication, protect::Protection, Action, Res};
 
+pub mod encrypted;
+pub mod env;
+pub mod helper;
+
+#[allow(clippy::upper_case_acronyms)]
 type URL = String;
 
 #[derive(Serialize, Deserialize, Clone)]
 pub struct SessionData {
     credential: SessionCredential,
     address: URL,
+    #[serde(default, skip_serializing_if = "Protection::is_empty")]
+    protect: Protection,
 }
 impl SessionData {
     pub fn login(url: &str, token: &str) -> SessionData {
         SessionData {
             credential: SessionCredential::UsernamePassword(token.to_string()),
             address: url.to_string(),
+            protect: Protection::default(),
         }
     }
     pub fn api(url: &str, token: &str) -> SessionData {
         SessionData {
             credential: SessionCredential::APIToken(token.to_string()),
             address: url.to_string(),
+            protect: Protection::default(),
         }
     }
+    pub fn protection(&self) -> &Protection {
+        &self.protect
+    }
+    pub fn with_protection(self, protect: Protection) -> SessionData {
+        SessionData { protect, ..self }
+    }
     pub fn to_tuple(&self) -> (Authentication, URL) {
         (
             match &self.credential {
@@ -48,11 +62,16 @@ enum SessionCredential {
     APIToken(String),
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Default)]
 struct SessionStorage {
     sessions: HashMap<String, SessionData>,
 }
 
+/// Error of managers getting or removing a session that does not exist
+pub fn not_found(name: &str) -> String {
+    format!("Session '{}' does not exist", name)
+}
+
 pub trait SessionManager {
     fn get(&self, name: &str) -> Res<SessionData>;
     fn save(&self, name: &str, session: &SessionData) -> Action;
@@ -63,19 +82,23 @@ pub struct LocalSessionManager {
     path: Box<Path>,
 }
 impl LocalSessionManager {
+    /// The file is only created once a session is saved, until then it holds no sessions
     pub fn new(path: Box<Path>) -> Res<LocalSessionManager> {
         if path.exists() {
-            File::open(&path)
-        } else {
-            File::create(&path)
+            File::open(&path).map_err(|err| err.to_string())?; //TODO model error
         }
-        .map_err(|err| err.to_string())?; //TODO model error
 
         Ok(LocalSessionManager { path })
     }
 
     fn load(&self) -> Res<SessionStorage> {
+        if !self.path.exists() {
+            return Ok(SessionStorage::default());
+        }
         let content = fs::read_to_string(&self.path).map_err(|err| err.to_string())?; //TODO model error
+        if content.trim().is_empty() {
+            return Ok(SessionStorage::default());
+        }
         serde_json::from_str::<SessionStorage>(&content).map_err(|err| err.to_string())
         //TODO model error
     }
@@ -88,15 +111,19 @@ impl LocalSessionManager {
 impl SessionManager for LocalSessionManager {
     fn get(&self, name: &str) -> Res<SessionData> {
         let ss = self.load()?;
-        let ses = ss.sessions.get(name).ok_or_else(|| "".to_string())?;
+        let ses = ss.sessions.get(name).ok_or_else(|| not_found(name))?;
         Ok(ses.clone())
     }
 
-    fn save(&self, _name: &str, _session: &SessionData) -> Action {
-        todo!()
+    fn save(&self, name: &str, session: &SessionData) -> Action {
+        let mut ss = self.load()?;
+        ss.sessions.insert(name.to_string(), session.clone());
+        self.store(ss)
     }
 
-    fn remove(&self, _name: &str) -> Action {
-        todo!()
+    fn remove(&self, name: &str) -> Action {
+        let mut ss = self.load()?;
+        ss.sessions.remove(name).ok_or_else(|| not_found(name))?;
+        self.store(ss)
     }
 }
@@ -0,0 +1,466 @@
+use std::cell::OnceCell;
+use std::fs;
+use std::io::{IsTerminal, Write};
+use std::path::Path;
+use std::rc::Rc;
+use std::str::FromStr;
+
+use aes_gcm::aead::rand_core::RngCore;
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng};
+use aes_gcm::{Aes256Gcm, Nonce};
+use age::armor::{ArmoredWriter, Format};
+use age::{Encryptor, Recipient};
+use argon2::{Algorithm, Argon2, Params, Version};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine as _;
+use serde::{Deserialize, Serialize};
+
+use super::super::decrypt::Keyring;
+use super::super::{Action, Res};
+use super::{not_found, SessionData, SessionManager, SessionStorage};
+
+/// Derived key of an unlocked passphrase store, set by `eval $(pctl session unlock)` so
+/// a shell session asks for the passphrase once
+pub const KEY_VAR: &str = "PCTL_SESSION_KEY";
+/// Passphrase of the store for non interactive use
+pub const PASSPHRASE_VAR: &str = "PCTL_SESSION_PASSPHRASE";
+
+/// Encrypted sessions file, either sealed with a key derived from a passphrase by
+/// argon2id or encrypted to age recipients
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "scheme", rename_all = "lowercase")]
+enum Envelope {
+    Passphrase {
+        salt: String,
+        memory: u32,
+        iterations: u32,
+        parallelism: u32,
+        nonce: String,
+        data: String,
+    },
+    Age {
+        recipients: Vec<String>,
+        data: String,
+    },
+}
+
+/// How a new store is encrypted
+pub enum Seal {
+    Passphrase,
+    Age(Vec<String>),
+}
+
+fn decode(value: &str) -> Res<Vec<u8>> {
+    BASE64
+        .decode(value)
+        .map_err(|_| "Corrupted session store".to_string())
+}
+
+fn derive_key(
+    passphrase: &str,
+    salt: &[u8],
+    memory: u32,
+    iterations: u32,
+    parallelism: u32,
+) -> Res<Vec<u8>> {
+    let params =
+        Params::new(memory, iterations, parallelism, Some(32)).map_err(|err| err.to_string())?;
+    let mut key = vec![0; 32];
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|err| err.to_string())?;
+    Ok(key)
+}
+
+fn read_passphrase(prompt: &str) -> Res<String> {
+    if let Ok(passphrase) = std::env::var(PASSPHRASE_VAR) {
+        return Ok(passphrase);
+    }
+    if !std::io::stdin().is_terminal() {
+        return Err(format!(
+            "Cannot ask for the session store passphrase, stdin is not a terminal. Set {} or {}",
+            PASSPHRASE_VAR, KEY_VAR
+        ));
+    }
+    rpassword::prompt_password(prompt).map_err(|err| format!("Cannot read passphrase: {}", err))
+}
+
+fn encrypt_age(recipients: &[String], plaintext: &[u8]) -> Res<String> {
+    let recipients = recipients
+        .iter()
+        .map(|r| {
+            age::x25519::Recipient::from_str(r)
+                .map_err(|err| format!("Invalid age recipient '{}': {}", r, err))
+        })
+        .collect::<Res<Vec<age::x25519::Recipient>>>()?;
+    let encryptor = Encryptor::with_recipients(recipients.iter().map(|r| r as &dyn Recipient))
+        .map_err(|err| err.to_string())?;
+
+    let mut output = Vec::new();
+    let armor =
+        ArmoredWriter::wrap_output(&mut output, Format::AsciiArmor).map_err(|e| e.to_string())?;
+    let mut writer = encryptor.wrap_output(armor).map_err(|e| e.to_string())?;
+    writer.write_all(plaintext).map_err(|e| e.to_string())?;
+    writer
+        .finish()
+        .and_then(|armor| armor.finish())
+        .map_err(|e| e.to_string())?;
+    String::from_utf8(output).map_err(|e| e.to_string())
+}
+
+/// Sessions kept encrypted at rest, the plaintext store stays with `LocalSessionManager`
+pub struct EncryptedSessionManager {
+    path: Box<Path>,
+    keys: Rc<Keyring>,
+    key: OnceCell<Vec<u8>>,
+}
+impl EncryptedSessionManager {
+    pub fn new(path: Box<Path>, keys: Rc<Keyring>) -> EncryptedSessionManager {
+        EncryptedSessionManager {
+            path,
+            keys,
+            key: OnceCell::new(),
+        }
+    }
+
+    /// Creates the encrypted store with the given sessions, asking for a new passphrase
+    /// twice when sealed with a passphrase
+    fn create(&self, seal: Seal, storage: &SessionStorage) -> Action {
+        let envelope = match seal {
+            Seal::Passphrase => {
+                let passphrase = read_passphrase("New session store passphrase: ")?;
+                if std::env::var(PASSPHRASE_VAR).is_err()
+                    && read_passphrase("Repeat passphrase: ")? != passphrase
+                {
+                    return Err("Passphrases do not match".to_string());
+                }
+                let mut salt = [0u8; 16];
+                OsRng.fill_bytes(&mut salt);
+                let (memory, iterations, parallelism) = (
+                    Params::DEFAULT_M_COST,
+                    Params::DEFAULT_T_COST,
+                    Params::DEFAULT_P_COST,
+                );
+                let key = derive_key(&passphrase, &salt, memory, iterations, parallelism)?;
+                Envelope::Passphrase {
+                    salt: BASE64.encode(salt),
+                    memory,
+                    iterations,
+                    parallelism,
+                    nonce: String::new(),
+                    data: String::new(),
+                }
+                .seal(&key, storage)?
+            }
+            Seal::Age(recipients) => Envelope::Age {
+                recipients,
+                data: String::new(),
+            }
+            .seal(&[], storage)?,
+        };
+        self.write(&envelope)
+    }
+
+    fn read(&self) -> Res<Envelope> {
+        let content = fs::read_to_string(&self.path)
+            .map_err(|err| format!("Cannot read session store: {}", err))?;
+        serde_json::from_str(&content).map_err(|err| format!("Invalid session store: {}", err))
+    }
+
+    fn write(&self, envelope: &Envelope) -> Action {
+        let content = serde_json::to_string_pretty(envelope).map_err(|err| err.to_string())?;
+        fs::write(&self.path, content).map_err(|err| format!("Cannot write session store: {}", err))
+    }
+
+    /// Key of a passphrase store, from the unlocked shell session or the passphrase
+    fn key(&self, envelope: &Envelope) -> Res<Vec<u8>> {
+        if let Some(key) = self.key.get() {
+            return Ok(key.clone());
+        }
+        let key = match std::env::var(KEY_VAR) {
+            Ok(key) => match decode(&key) {
+                Ok(key) if key.len() == 32 => key,
+                _ => {
+                    return Err(format!(
+                        "Invalid {}, unlock the session store again",
+                        KEY_VAR
+                    ))
+                }
+            },
+            Err(_) => envelope.derive_key(&read_passphrase("Session store passphrase: ")?)?,
+        };
+        Ok(self.key.get_or_init(|| key).clone())
+    }
+
+    fn load(&self) -> Res<SessionStorage> {
+        let envelope = self.read()?;
+        let plaintext = match &envelope {
+            Envelope::Passphrase { nonce, data, .. } => {
+                let key = self.key(&envelope)?;
+                Aes256Gcm::new_from_slice(&key)
+                    .map_err(|err| err.to_string())?
+                    .decrypt(Nonce::from_slice(&decode(nonce)?), decode(data)?.as_slice())
+                    .map_err(|_| {
+                        if std::env::var(KEY_VAR).is_ok() {
+                            format!(
+                                "{} does not unlock the session store, unlock it again",
+                                KEY_VAR
+                            )
+                        } else {
+                            "Wrong passphrase or corrupted session store".to_string()
+                        }
+                    })?
+            }
+            Envelope::Age { data, .. } => self.keys.decrypt_age(data.as_bytes())?,
+        };
+        serde_json::from_slice(&plaintext).map_err(|err| format!("Invalid session store: {}", err))
+    }
+
+    fn store(&self, storage: &SessionStorage) -> Action {
+        let envelope = self.read()?;
+        let key = match envelope {
+            Envelope::Passphrase { .. } => self.key(&envelope)?,
+            Envelope::Age { .. } => Vec::new(),
+        };
+        self.write(&envelope.seal(&key, storage)?)
+    }
+
+    /// Derived key of a passphrase store, to be cached in the shell environment
+    pub fn unlock(&self) -> Res<String> {
+        let envelope = self.read()?;
+        if let Envelope::Age { .. } = envelope {
+            return Err(
+                "Session store is encrypted with age, it is unlocked by age identities".to_string(),
+            );
+        }
+        self.load()?;
+        Ok(BASE64.encode(self.key(&envelope)?))
+    }
+
+    /// Moves sessions of a plaintext store into a new encrypted store
+    pub fn encrypt(&self, plain: &Path, seal: Seal) -> Action {
+        if self.path.exists() {
+            return Err("Session store is already encrypted".to_string());
+        }
+        let storage = super::LocalSessionManager::new(plain.into())?.load()?;
+        self.create(seal, &storage)?;
+        if !plain.exists() {
+            return Ok(());
+        }
+        fs::remove_file(plain).map_err(|err| format!("Cannot remove plaintext sessions: {}", err))
+    }
+
+    /// Moves sessions back into a plaintext store
+    pub fn decrypt(&self, plain: &Path) -> Action {
+        let storage = self.load()?;
+        super::LocalSessionManager::new(plain.into())?.store(storage)?;
+        fs::remove_file(&self.path).map_err(|err| format!("Cannot remove session store: {}", err))
+    }
+}
+
+impl Envelope {
+    /// Key of a passphrase store derived from the passphrase with the store's parameters
+    fn derive_key(&self, passphrase: &str) -> Res<Vec<u8>> {
+        let Envelope::Passphrase {
+            salt,
+            memory,
+            iterations,
+            parallelism,
+            ..
+        } = self
+        else {
+            return Err("Session store is not sealed with a passphrase".to_string());
+        };
+        derive_key(
+            passphrase,
+            &decode(salt)?,
+            *memory,
+            *iterations,
+            *parallelism,
+        )
+    }
+
+    /// Encrypts sessions into a copy of this envelope, keeping its salt or recipients
+    fn seal(self, key: &[u8], storage: &SessionStorage) -> Res<Envelope> {
+        let plaintext = serde_json::to_vec(storage).map_err(|err| err.to_string())?;
+        Ok(match self {
+            Envelope::Passphrase {
+                salt,
+                memory,
+                iterations,
+                parallelism,
+                ..
+            } => {
+                let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+                let data = Aes256Gcm::new_from_slice(key)
+                    .map_err(|err| err.to_string())?
+                    .encrypt(&nonce, plaintext.as_slice())
+                    .map_err(|err| err.to_string())?;
+                Envelope::Passphrase {
+                    salt,
+                    memory,
+                    iterations,
+                    parallelism,
+                    nonce: BASE64.encode(nonce),
+                    data: BASE64.encode(data),
+                }
+            }
+            Envelope::Age { recipients, .. } => {
+                let data = encrypt_age(&recipients, &plaintext)?;
+                Envelope::Age { recipients, data }
+            }
+        })
+    }
+}
+
+impl SessionManager for EncryptedSessionManager {
+    fn get(&self, name: &str) -> Res<SessionData> {
+        self.load()?
+            .sessions
+            .remove(name)
+            .ok_or_else(|| not_found(name))
+    }
+
+    fn save(&self, name: &str, session: &SessionData) -> Action {
+        let mut storage = self.load()?;
+        storage.sessions.insert(name.to_string(), session.clone());
+        self.store(&storage)
+    }
+
+    fn remove(&self, name: &str) -> Action {
+        let mut storage = self.load()?;
+        storage
+            .sessions
+            .remove(name)
+            .ok_or_else(|| not_found(name))?;
+        self.store(&storage)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::portainer::api::Authentication;
+
+    /// Path in the temporary directory, unique per test process
+    fn temp_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("pctl-{}-{}", std::process::id(), name));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    fn keyring_path(key: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("testdata/sops")
+            .join(key)
+    }
+
+    fn keyring(key: &str) -> Rc<Keyring> {
+        Rc::new(Keyring::new(Some(keyring_path(key))))
+    }
+
+    /// Creates an empty passphrase store, with cheap argon2 parameters to keep tests fast
+    fn passphrase_store(path: &Path, passphrase: &str) {
+        let envelope = Envelope::Passphrase {
+            salt: BASE64.encode([7u8; 16]),
+            memory: Params::MIN_M_COST,
+            iterations: 1,
+            parallelism: 1,
+            nonce: String::new(),
+            data: String::new(),
+        };
+        let key = envelope.derive_key(passphrase).unwrap();
+        let manager = EncryptedSessionManager::new(path.into(), keyring("key.txt"));
+        manager
+            .write(&envelope.seal(&key, &SessionStorage::default()).unwrap())
+            .unwrap();
+    }
+
+    /// Manager of a passphrase store as if the passphrase had been typed
+    fn unlocked(path: &Path, passphrase: &str) -> EncryptedSessionManager {
+        let manager = EncryptedSessionManager::new(path.into(), keyring("key.txt"));
+        let key = manager.read().unwrap().derive_key(passphrase).unwrap();
+        manager.key.set(key).unwrap();
+        manager
+    }
+
+    fn token(manager: &EncryptedSessionManager, name: &str) -> Res<String> {
+        match manager.get(name)?.to_tuple() {
+            (Authentication::APIToken(token), _) => Ok(token),
+            _ => Err("Not an API token".to_string()),
+        }
+    }
+
+    fn round_trip(path: &Path, open: impl Fn() -> EncryptedSessionManager) {
+        open()
+            .save("prod", &SessionData::api("http://prod", "prod-token"))
+            .unwrap();
+        open()
+            .save("dev", &SessionData::api("http://dev", "dev-token"))
+            .unwrap();
+        assert!(!fs::read_to_string(path).unwrap().contains("prod-token"));
+
+        assert_eq!(token(&open(), "prod"), Ok("prod-token".to_string()));
+        open().remove("prod").unwrap();
+        assert_eq!(token(&open(), "prod"), Err(not_found("prod")));
+        assert_eq!(token(&open(), "dev"), Ok("dev-token".to_string()));
+        assert_eq!(open().remove("prod"), Err(not_found("prod")));
+    }
+
+    #[test]
+    fn passphrase_store_round_trip() {
+        let path = temp_path("sessions-passphrase.enc");
+        passphrase_store(&path, "correct horse");
+
+        round_trip(&path, || unlocked(&path, "correct horse"));
+        assert_eq!(
+            unlocked(&path, "correct horse").unlock(),
+            Ok(BASE64.encode(unlocked(&path, "correct horse").key.get().unwrap()))
+        );
+    }
+
+    #[test]
+    fn wrong_passphrase_fails_without_touching_the_store() {
+        let path = temp_path("sessions-wrong-passphrase.enc");
+        passphrase_store(&path, "correct horse");
+        unlocked(&path, "correct horse")
+            .save("prod", &SessionData::api("http://prod", "prod-token"))
+            .unwrap();
+        let content = fs::read_to_string(&path).unwrap();
+
+        let wrong = unlocked(&path, "battery staple");
+        let error = "Wrong passphrase or corrupted session store".to_string();
+        assert_eq!(token(&wrong, "prod"), Err(error.clone()));
+        assert_eq!(wrong.unlock(), Err(error.clone()));
+        assert_eq!(
+            wrong.save("dev", &SessionData::api("http://dev", "dev-token")),
+            Err(error.clone())
+        );
+        assert_eq!(wrong.remove("prod"), Err(error));
+        assert_eq!(fs::read_to_string(&path).unwrap(), content);
+    }
+
+    #[test]
+    fn age_store_round_trip() {
+        let path = temp_path("sessions-age.enc");
+        let key = fs::read_to_string(keyring_path("key.txt")).unwrap();
+        let identity = key.lines().find(|l| !l.starts_with('#')).unwrap();
+        let recipient = age::x25519::Identity::from_str(identity)
+            .unwrap()
+            .to_public()
+            .to_string();
+        EncryptedSessionManager::new(path.clone().into(), keyring("key.txt"))
+            .create(Seal::Age(vec![recipient]), &SessionStorage::default())
+            .unwrap();
+
+        round_trip(&path, || {
+            EncryptedSessionManager::new(path.clone().into(), keyring("key.txt"))
+        });
+
+        let other = EncryptedSessionManager::new(path.clone().into(), keyring("other-key.txt"));
+        assert!(other.get("dev").is_err());
+        assert!(other.unlock().is_err());
+    }
+}
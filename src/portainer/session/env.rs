@@ -0,0 +1,170 @@
+use super::super::protect::Protection;
+use super::super::{Action, Res};
+use super::{not_found, SessionData, SessionManager};
+
+/// Sessions defined by `PCTL_SESSION_<NAME>_TOKEN` and `PCTL_SESSION_<NAME>_URL`, where
+/// `<NAME>` is the session name in upper case with other characters than letters and
+/// digits replaced by `_`. Other sessions come from the wrapped store.
+pub struct EnvSessionManager {
+    store: Box<dyn SessionManager>,
+}
+impl EnvSessionManager {
+    pub fn new(store: Box<dyn SessionManager>) -> EnvSessionManager {
+        EnvSessionManager { store }
+    }
+
+    /// Manager of saved sessions, changes of sessions go there so tokens of the
+    /// environment never end up on disk
+    pub fn store(&self) -> &dyn SessionManager {
+        self.store.as_ref()
+    }
+
+    /// Whether the session is given by environment variables
+    pub fn in_env(&self, name: &str) -> bool {
+        std::env::var_os(variable(name, "TOKEN")).is_some()
+    }
+}
+
+fn variable(name: &str, suffix: &str) -> String {
+    let name: String = name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_uppercase()
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    format!("PCTL_SESSION_{}_{}", name, suffix)
+}
+
+impl SessionManager for EnvSessionManager {
+    fn get(&self, name: &str) -> Res<SessionData> {
+        let token = variable(name, "TOKEN");
+        let Ok(value) = std::env::var(&token) else {
+            return self.store.get(name);
+        };
+        let url = variable(name, "URL");
+        let address = std::env::var(&url)
+            .map_err(|_| format!("Session '{}' needs {} along with {}", name, url, token))?;
+        // protection rules of a saved session of the same name stay in force
+        let protection = match self.store.get(name) {
+            Ok(stored) => stored.protection().clone(),
+            Err(err) if err == not_found(name) => Protection::default(),
+            Err(err) => {
+                return Err(format!(
+                    "Cannot read protection rules of session '{}': {}",
+                    name, err
+                ))
+            }
+        };
+        Ok(SessionData::api(&address, &value).with_protection(protection))
+    }
+
+    fn save(&self, name: &str, session: &SessionData) -> Action {
+        self.store.save(name, session)
+    }
+
+    fn remove(&self, name: &str) -> Action {
+        self.store.remove(name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::collections::HashMap;
+
+    use super::*;
+
+    /// Keeps sessions in memory, failing every call when broken
+    #[derive(Default)]
+    struct MemoryStore {
+        sessions: RefCell<HashMap<String, SessionData>>,
+        broken: bool,
+    }
+    impl SessionManager for MemoryStore {
+        fn get(&self, name: &str) -> Res<SessionData> {
+            if self.broken {
+                return Err("Sessions are locked".to_string());
+            }
+            self.sessions
+                .borrow()
+                .get(name)
+                .cloned()
+                .ok_or_else(|| not_found(name))
+        }
+        fn save(&self, name: &str, session: &SessionData) -> Action {
+            self.sessions
+                .borrow_mut()
+                .insert(name.to_string(), session.clone());
+            Ok(())
+        }
+        fn remove(&self, name: &str) -> Action {
+            self.sessions
+                .borrow_mut()
+                .remove(name)
+                .map(|_| ())
+                .ok_or_else(|| not_found(name))
+        }
+    }
+
+    fn protection() -> Protection {
+        Protection {
+            stacks: vec!["prod-*".to_string()],
+            ..Default::default()
+        }
+    }
+
+    /// Each test uses its own session name, as tests share the process environment
+    fn set_env(name: &str) {
+        std::env::set_var(variable(name, "TOKEN"), "env-token");
+        std::env::set_var(variable(name, "URL"), "http://env/api");
+    }
+
+    #[test]
+    fn protection_survives_env_override() {
+        let store = MemoryStore::default();
+        let stored = SessionData::api("http://stored/api", "stored-token");
+        store
+            .save("env-protected", &stored.with_protection(protection()))
+            .unwrap();
+        set_env("env-protected");
+        let manager = EnvSessionManager::new(Box::new(store));
+
+        let session = manager.get("env-protected").unwrap();
+
+        let (_, address) = session.to_tuple();
+        assert_eq!(address, "http://env/api");
+        assert_eq!(session.protection().stacks, protection().stacks);
+    }
+
+    #[test]
+    fn env_only_sessions_are_unprotected() {
+        set_env("env-only");
+        let manager = EnvSessionManager::new(Box::<MemoryStore>::default());
+
+        let session = manager.get("env-only").unwrap();
+
+        assert!(session.protection().is_empty());
+        assert!(manager.in_env("env-only"));
+    }
+
+    #[test]
+    fn unreadable_store_fails_env_sessions() {
+        set_env("env-locked");
+        let manager = EnvSessionManager::new(Box::new(MemoryStore {
+            broken: true,
+            ..Default::default()
+        }));
+
+        assert_eq!(
+            manager.get("env-locked").map(|_| ()),
+            Err(
+                "Cannot read protection rules of session 'env-locked': Sessions are locked"
+                    .to_string()
+            )
+        );
+    }
+}
@@ -0,0 +1,120 @@
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+use serde::{Deserialize, Serialize};
+
+use super::super::protect::Protection;
+use super::super::{Action, Res};
+use super::{not_found, SessionCredential, SessionData, SessionManager};
+
+/// Credential of a session as exchanged with helpers, `Kind` is `APIToken` when a helper
+/// does not keep it and `Protect` holds protection rules of the session
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct HelperCredential {
+    session: String,
+    #[serde(rename = "ServerURL")]
+    server_url: String,
+    secret: String,
+    #[serde(default)]
+    kind: HelperKind,
+    #[serde(default, skip_serializing_if = "Protection::is_empty")]
+    protect: Protection,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+enum HelperKind {
+    #[default]
+    APIToken,
+    Jwt,
+}
+
+/// Sessions kept by an external `pctl-credential-<name>` program, like docker credential
+/// helpers. The program is run with `get`, `store` or `erase`: `get` and `erase` read the
+/// session name on stdin, `store` reads the credential as JSON and `get` prints it. A
+/// failing helper prints its error on stdout or stderr, `credentials not found` when the
+/// session does not exist.
+pub struct HelperSessionManager {
+    program: String,
+}
+impl HelperSessionManager {
+    pub fn new(name: &str) -> HelperSessionManager {
+        HelperSessionManager {
+            program: format!("pctl-credential-{}", name),
+        }
+    }
+
+    fn run(&self, action: &str, input: &str) -> Res<String> {
+        let mut child = Command::new(&self.program)
+            .arg(action)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|err| err.to_string())?;
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin
+                .write_all(input.as_bytes())
+                .map_err(|err| err.to_string())?;
+        }
+        let output = child.wait_with_output().map_err(|err| err.to_string())?;
+        let stdout = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr).trim().to_string();
+            return Err(if stdout.is_empty() { stderr } else { stdout });
+        }
+        Ok(stdout)
+    }
+
+    fn run_session(&self, action: &str, name: &str) -> Res<String> {
+        self.run(action, name).map_err(|err| {
+            if err.contains("credentials not found") {
+                not_found(name)
+            } else {
+                format!(
+                    "Credential helper '{} {}' failed: {}",
+                    self.program, action, err
+                )
+            }
+        })
+    }
+}
+impl SessionManager for HelperSessionManager {
+    fn get(&self, name: &str) -> Res<SessionData> {
+        let output = self.run_session("get", name)?;
+        let credential: HelperCredential = serde_json::from_str(&output).map_err(|err| {
+            format!(
+                "Invalid output of credential helper '{}': {}",
+                self.program, err
+            )
+        })?;
+        Ok(SessionData {
+            credential: match credential.kind {
+                HelperKind::APIToken => SessionCredential::APIToken(credential.secret),
+                HelperKind::Jwt => SessionCredential::UsernamePassword(credential.secret),
+            },
+            address: credential.server_url,
+            protect: credential.protect,
+        })
+    }
+
+    fn save(&self, name: &str, session: &SessionData) -> Action {
+        let (kind, secret) = match &session.credential {
+            SessionCredential::APIToken(value) => (HelperKind::APIToken, value),
+            SessionCredential::UsernamePassword(value) => (HelperKind::Jwt, value),
+        };
+        let credential = HelperCredential {
+            session: name.to_string(),
+            server_url: session.address.to_string(),
+            secret: secret.to_string(),
+            kind,
+            protect: session.protect.clone(),
+        };
+        let input = serde_json::to_string(&credential).map_err(|err| err.to_string())?;
+        self.run_session("store", &input).map(|_| ())
+    }
+
+    fn remove(&self, name: &str) -> Action {
+        self.run_session("erase", name).map(|_| ())
+    }
+}